@@ -1,4 +1,10 @@
 <?xml version="1.0" encoding="UTF-8"?>
 <tileset version="1.4" tiledversion="1.4.3" name="basic" tilewidth="32" tileheight="32" tilecount="100" columns="10">
  <image source="../grass_tileset.png" width="320" height="320"/>
+ <tile id="0" type="grass"/>
+ <tile id="1" type="grass_above_dirt"/>
+ <tile id="2" type="grass_left_of_dirt"/>
+ <tile id="3" type="grass_corner"/>
+ <tile id="4" type="dirt"/>
+ <tile id="5" type="wall"/>
 </tileset>
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use rand::Rng;
+use specs::prelude::*;
+use crate::component::*;
+use crate::map::{self, MapTile};
+use crate::pathfinding;
+use crate::simulation::WorldRng;
+
+/// How many times a wandering npc tries to find a reachable tile before giving up for now.
+const WANDER_ATTEMPTS: u32 = 10;
+const WANDER_MIN_WAIT_TICKS: u32 = 60;
+const WANDER_MAX_WAIT_TICKS: u32 = 180;
+const PATROL_WAIT_TICKS: u32 = 30;
+/// How long to wait before trying again when no path could be found.
+const RETRY_WAIT_TICKS: u32 = 60;
+/// Chasing npcs only walk this many tiles of their path before looking for the player again.
+const CHASE_STEPS: usize = 2;
+const CHASE_LOOK_TICKS: u32 = 20;
+
+pub struct AiSystem {}
+
+impl<'a> System<'a> for AiSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, BTreeMap<(i32, i32), MapTile>>,
+        WriteExpect<'a, WorldRng>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Ai>,
+        WriteStorage<'a, PathFollow>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, map, mut rng, positions, players, mut ais, mut paths) = data;
+        let player_tile = (&positions, &players).join()
+            .next()
+            .map(|(position, _player)| (map::to_tl(position.x), map::to_tl(position.y)));
+
+        for (entity, position, ai) in (&entities, &positions, &mut ais).join() {
+            // Npcs only think once they are done walking and have waited long enough
+            if paths.contains(entity) {
+                continue;
+            }
+            if ai.wait > 0 {
+                ai.wait -= 1;
+                continue;
+            }
+
+            let here = (map::to_tl(position.x), map::to_tl(position.y));
+            let planned = match &mut ai.behaviour {
+                Behaviour::Idle => continue,
+                Behaviour::Wander { origin, radius } => {
+                    let origin = *origin;
+                    let radius = *radius;
+                    (0..WANDER_ATTEMPTS)
+                        .map(|_| (
+                            origin.0 + rng.0.gen_range(-radius, radius + 1),
+                            origin.1 + rng.0.gen_range(-radius, radius + 1),
+                        ))
+                        .filter(|target| *target != here)
+                        .find_map(|target| pathfinding::a_star(&map, here, target))
+                        .map(|path| (path, rng.0.gen_range(WANDER_MIN_WAIT_TICKS, WANDER_MAX_WAIT_TICKS)))
+                }
+                Behaviour::Patrol { route, next } => {
+                    if route.is_empty() {
+                        continue;
+                    }
+                    // Arriving at a point moves on to the next one. If we got here without
+                    // arriving, something blocked the way, so we try the same point again.
+                    if here == route[*next] {
+                        *next = (*next + 1) % route.len();
+                    }
+                    pathfinding::a_star(&map, here, route[*next]).map(|path| (path, PATROL_WAIT_TICKS))
+                }
+                Behaviour::Chase { sight } => {
+                    let target = match player_tile {
+                        Some(target) if (target.0 - here.0).abs().max((target.1 - here.1).abs()) <= *sight => target,
+                        _ => {
+                            ai.wait = CHASE_LOOK_TICKS;
+                            continue;
+                        }
+                    };
+                    // The player keeps moving, so only a little of the path is walked before replanning
+                    pathfinding::a_star(&map, here, target).map(|mut path| {
+                        path.truncate(CHASE_STEPS);
+                        (path, 0)
+                    })
+                }
+            };
+
+            match planned {
+                Some((path, wait)) => {
+                    paths.insert(entity, PathFollow { path: path.into_iter().collect() })
+                        .expect("could not insert path for npc");
+                    ai.wait = wait;
+                }
+                None => ai.wait = RETRY_WAIT_TICKS,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::{Headless, object};
+
+    #[test]
+    fn patrolling_npc_walks_its_route_back_and_forth() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        let mut route = object("route", "route", (2, 5), &[]);
+        route.points = vec![(64.0, 160.0), (224.0, 160.0), (224.0, 224.0)];
+        game.spawn(&[
+            object("Guard", "npc", (2, 5), &[("behaviour", "patrol"), ("route", "route")]),
+            route,
+        ]);
+
+        let mut visited = vec![game.npc_tile("Guard")];
+        for _ in 0..600 {
+            game.step(1);
+            let tile = game.npc_tile("Guard");
+            if visited.last() != Some(&tile) {
+                visited.push(tile);
+            }
+        }
+        assert!(visited.contains(&(7, 5)), "guard visited {:?}", visited);
+        assert!(visited.contains(&(7, 7)), "guard visited {:?}", visited);
+        let back = visited.iter().rposition(|tile| *tile == (2, 5)).expect("guard never returned");
+        assert!(back > 0, "guard never returned, visited {:?}", visited);
+    }
+
+    #[test]
+    fn wandering_npc_stays_near_where_it_started() {
+        let mut game = Headless::new(Headless::floor(20, 20));
+        game.spawn(&[object("Gardener", "npc", (10, 10), &[("behaviour", "wander"), ("radius", "2")])]);
+        let mut moved = false;
+        for _ in 0..1200 {
+            game.step(1);
+            let (x, y) = game.npc_tile("Gardener");
+            assert!((x - 10).abs() <= 2 && (y - 10).abs() <= 2, "gardener wandered off to {:?}", (x, y));
+            moved |= (x, y) != (10, 10);
+        }
+        assert!(moved, "gardener never moved");
+    }
+
+    #[test]
+    fn wandering_npcs_with_a_negative_radius_wander_the_default_distance() {
+        let mut game = Headless::new(Headless::floor(20, 20));
+        game.spawn(&[object("Gardener", "npc", (10, 10), &[("behaviour", "wander"), ("radius", "-1")])]);
+        for _ in 0..600 {
+            game.step(1);
+            let (x, y) = game.npc_tile("Gardener");
+            assert!((x - 10).abs() <= 3 && (y - 10).abs() <= 3, "gardener wandered off to {:?}", (x, y));
+        }
+    }
+}
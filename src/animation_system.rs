@@ -8,11 +8,13 @@ pub struct AnimationSystem {}
 impl<'a> System<'a> for AnimationSystem {
     type SystemData = (
         ReadStorage<'a, Position>,
-        WriteStorage<'a, Player>
+        WriteStorage<'a, Player>,
+        WriteStorage<'a, Npc>,
+        ReadStorage<'a, PathFollow>
     );
 
     fn run(&mut self, data : Self::SystemData) {
-        let (positions, mut players) = data;
+        let (positions, mut players, mut npcs, paths) = data;
 
         for (_positions, player) in (&positions, &mut players).join() {
             // Alternate between 4 animation frames (0-3)
@@ -22,5 +24,14 @@ impl<'a> System<'a> for AnimationSystem {
                 player.animation_index = 0;
             }
         }
+
+        // Npcs are only ever moving while they have a path to walk
+        for (_positions, npc, path) in (&positions, &mut npcs, paths.maybe()).join() {
+            if path.is_some() {
+                npc.animation_index = (npc.animation_index + 1) % PLAYER_ANIMATION_FRAMES;
+            } else {
+                npc.animation_index = 0;
+            }
+        }
     }
 }
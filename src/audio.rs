@@ -0,0 +1,537 @@
+use crate::component::{AudioEmitter, Player, Position};
+use crate::events::{GameEvent, Subscription};
+use crate::map::TileType;
+use crate::simulation::MapProperties;
+use crate::{GameState, RootState, WIDTH_PX};
+use ggez::audio::{self, SoundSource};
+use ggez::{filesystem, timer, Context};
+use specs::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// How long the music of one map takes to fade into the music of the next.
+const CROSSFADE_SECONDS: f32 = 1.5;
+/// Music lives in this resource directory, named after the `music` property of each map.
+const MUSIC_DIR: &str = "/music";
+/// Sounds made by audio emitters live in this resource directory, named after the emitters `sound`.
+const AMBIENT_DIR: &str = "/ambient";
+
+/// A sound effect the game can play.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sound {
+    /// Taking a step onto a tile, which sounds different depending on the tile.
+    Footstep(TileType),
+    Pickup,
+    MenuClick,
+}
+
+impl Sound {
+    /// Every sound there is, for loading them all up front.
+    pub const ALL: [Sound; 5] = [
+        Sound::Footstep(TileType::Floor),
+        Sound::Footstep(TileType::Wall),
+        Sound::Footstep(TileType::Missing),
+        Sound::Pickup,
+        Sound::MenuClick,
+    ];
+
+    /// Where the sound is found among the resources.
+    pub fn path(self) -> String {
+        match self {
+            Sound::Footstep(tile_type) => format!("/sounds/footstep_{}.wav", format!("{:?}", tile_type).to_lowercase()),
+            Sound::Pickup => "/sounds/pickup.wav".to_string(),
+            Sound::MenuClick => "/sounds/menu_click.wav".to_string(),
+        }
+    }
+}
+
+/// The sound an event in the world makes, if any. Only the players own footsteps are heard.
+pub fn event_sound(ecs: &World, event: &GameEvent) -> Option<Sound> {
+    match event {
+        GameEvent::TileEntered { entity, tile_type, .. } if ecs.read_storage::<Player>().contains(*entity) => {
+            Some(Sound::Footstep(*tile_type))
+        }
+        GameEvent::ItemPickedUp { .. } => Some(Sound::Pickup),
+        _ => None,
+    }
+}
+
+/// Whatever actually makes the noise. Everything has to be loaded when the backend is created,
+/// so that playing sounds never needs the ggez context.
+pub trait AudioBackend {
+    /// Starts a music track looping from the beginning.
+    fn play_music(&mut self, track: &str);
+    fn set_music_volume(&mut self, track: &str, volume: f32);
+    fn stop_music(&mut self, track: &str);
+    fn play_sound(&mut self, sound: Sound, volume: f32);
+    /// Plays an ambient sound once, panned from -1.0 on the far left to 1.0 on the far right.
+    fn play_ambient(&mut self, sound: &str, volume: f32, pan: f32);
+    /// Starts an ambient sound looping if it is not already, then changes how loud it is and where.
+    fn loop_ambient(&mut self, sound: &str, volume: f32, pan: f32);
+    fn stop_ambient(&mut self, sound: &str);
+}
+
+/// Plays nothing, for headless runs and machines without a sound device.
+#[derive(Default, Debug)]
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn play_music(&mut self, _track: &str) {}
+    fn set_music_volume(&mut self, _track: &str, _volume: f32) {}
+    fn stop_music(&mut self, _track: &str) {}
+    fn play_sound(&mut self, _sound: Sound, _volume: f32) {}
+    fn play_ambient(&mut self, _sound: &str, _volume: f32, _pan: f32) {}
+    fn loop_ambient(&mut self, _sound: &str, _volume: f32, _pan: f32) {}
+    fn stop_ambient(&mut self, _sound: &str) {}
+}
+
+/// Plays through ggez, with every sound and music track loaded into memory.
+pub struct GgezBackend {
+    music: HashMap<String, audio::Source>,
+    sounds: HashMap<Sound, audio::Source>,
+    ambient: HashMap<String, audio::SpatialSource>,
+}
+
+impl GgezBackend {
+    /// Loads every sound and music track there is. Anything which fails to load stays silent.
+    pub fn new(ctx: &mut Context) -> GgezBackend {
+        let mut sounds = HashMap::new();
+        for sound in Sound::ALL.iter() {
+            if !filesystem::exists(ctx, sound.path()) {
+                continue;
+            }
+            match audio::Source::new(ctx, sound.path()) {
+                Ok(source) => {
+                    sounds.insert(*sound, source);
+                }
+                Err(e) => println!("Could not load sound {}: {}", sound.path(), e),
+            }
+        }
+
+        let mut music = HashMap::new();
+        for (track, path) in named_files(ctx, MUSIC_DIR) {
+            match audio::Source::new(ctx, &path) {
+                Ok(mut source) => {
+                    source.set_repeat(true);
+                    music.insert(track, source);
+                }
+                Err(e) => println!("Could not load music {}: {}", path.display(), e),
+            }
+        }
+
+        let mut ambient = HashMap::new();
+        for (sound, path) in named_files(ctx, AMBIENT_DIR) {
+            match audio::SpatialSource::new(ctx, &path) {
+                Ok(mut source) => {
+                    source.set_ears([-1.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+                    ambient.insert(sound, source);
+                }
+                Err(e) => println!("Could not load ambient sound {}: {}", path.display(), e),
+            }
+        }
+        GgezBackend { music, sounds, ambient }
+    }
+
+    /// Moves an ambient sound between the ears, a little in front so that it is never right on top of one.
+    fn pan(source: &mut audio::SpatialSource, volume: f32, pan: f32) {
+        source.set_volume(volume);
+        source.set_position([pan, 0.0, 1.0]);
+    }
+}
+
+impl AudioBackend for GgezBackend {
+    fn play_music(&mut self, track: &str) {
+        match self.music.get_mut(track) {
+            Some(source) => {
+                source.set_volume(0.0);
+                if let Err(e) = source.play() {
+                    println!("Could not play music {}: {}", track, e);
+                }
+            }
+            None => println!("No music called {}", track),
+        }
+    }
+
+    fn set_music_volume(&mut self, track: &str, volume: f32) {
+        if let Some(source) = self.music.get_mut(track) {
+            source.set_volume(volume);
+        }
+    }
+
+    fn stop_music(&mut self, track: &str) {
+        if let Some(source) = self.music.get_mut(track) {
+            source.stop();
+        }
+    }
+
+    fn play_sound(&mut self, sound: Sound, volume: f32) {
+        if let Some(source) = self.sounds.get_mut(&sound) {
+            source.set_volume(volume);
+            // Detached so that the same sound can overlap itself, like quick footsteps
+            if let Err(e) = source.play_detached() {
+                println!("Could not play sound {}: {}", sound.path(), e);
+            }
+        }
+    }
+
+    fn play_ambient(&mut self, sound: &str, volume: f32, pan: f32) {
+        if let Some(source) = self.ambient.get_mut(sound) {
+            GgezBackend::pan(source, volume, pan);
+            source.set_repeat(false);
+            if let Err(e) = source.play_detached() {
+                println!("Could not play ambient sound {}: {}", sound, e);
+            }
+        }
+    }
+
+    fn loop_ambient(&mut self, sound: &str, volume: f32, pan: f32) {
+        if let Some(source) = self.ambient.get_mut(sound) {
+            GgezBackend::pan(source, volume, pan);
+            if !source.playing() {
+                source.set_repeat(true);
+                if let Err(e) = source.play() {
+                    println!("Could not play ambient sound {}: {}", sound, e);
+                }
+            }
+        }
+    }
+
+    fn stop_ambient(&mut self, sound: &str) {
+        if let Some(source) = self.ambient.get_mut(sound) {
+            source.stop();
+        }
+    }
+}
+
+/// Every file in a resource directory, named by the file name without its extension.
+fn named_files(ctx: &mut Context, dir: &str) -> Vec<(String, std::path::PathBuf)> {
+    let paths: Vec<_> = filesystem::read_dir(ctx, dir).map(|paths| paths.collect()).unwrap_or_default();
+    paths.into_iter()
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, path))
+        })
+        .collect()
+}
+
+/// An audio emitter which is close enough to be heard.
+#[derive(Clone, Debug)]
+pub struct Heard {
+    pub entity: Entity,
+    pub sound: String,
+    pub looping: bool,
+    pub volume: f32,
+    pub pan: f32,
+}
+
+/// How loud an emitter is and how far off to one side, from how far it is from the middle of the
+/// screen. Emitters fall off quickly at first and then slowly, until they cannot be heard at all.
+pub fn hear(emitter: &AudioEmitter, offset: (f32, f32)) -> Option<(f32, f32)> {
+    let distance = (offset.0 * offset.0 + offset.1 * offset.1).sqrt();
+    if distance >= emitter.range {
+        return None;
+    }
+    let falloff = (1.0 - distance / emitter.range).powi(2);
+    let pan = (offset.0 / (WIDTH_PX as f32 / 2.0)).clamp(-1.0, 1.0);
+    Some((emitter.volume * falloff, pan))
+}
+
+/// How loud each kind of audio is, from 0.0 for silence to 1.0 for full volume.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Volumes {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for Volumes {
+    fn default() -> Volumes {
+        Volumes { master: 1.0, music: 1.0, sfx: 1.0 }
+    }
+}
+
+/// A music track that is playing, along with how far it has faded in.
+#[derive(Clone, Debug)]
+struct Track {
+    name: String,
+    /// From 0.0 when it has just started or has faded out completely, to 1.0 when fully faded in.
+    fade: f32,
+}
+
+/// Plays the music and sound effects, keeping track of what is playing and how loud it should be.
+pub struct AudioManager {
+    backend: Box<dyn AudioBackend>,
+    volumes: Volumes,
+    /// The track fading in or playing.
+    music: Option<Track>,
+    /// Tracks that were replaced and are fading out.
+    fading_out: Vec<Track>,
+    /// Emitters which play once that were already in range last time, so they do not play again.
+    emitters_in_range: HashSet<Entity>,
+    /// Ambient sounds which are looping.
+    loops: HashSet<String>,
+    events: Subscription,
+}
+
+impl AudioManager {
+    pub fn new(backend: Box<dyn AudioBackend>, volumes: Volumes) -> AudioManager {
+        AudioManager { backend, volumes, music: None, fading_out: vec![], emitters_in_range: HashSet::new(), loops: HashSet::new(), events: Subscription::default() }
+    }
+
+    /// An audio manager which never makes a sound.
+    pub fn silent() -> AudioManager {
+        AudioManager::new(Box::new(NullBackend), Volumes::default())
+    }
+
+    /// Starts listening for sounds from a different world.
+    pub fn listen(&mut self, ecs: &World) {
+        self.events.subscribe(ecs);
+    }
+
+    pub fn set_volumes(&mut self, volumes: Volumes) {
+        self.volumes = volumes;
+        self.apply_music_volumes();
+    }
+
+    /// The track which is playing or fading in, if any.
+    pub fn music(&self) -> Option<&str> {
+        self.music.as_ref().map(|track| track.name.as_str())
+    }
+
+    /// Crossfades into a different track, or fades out into silence. Asking for the track
+    /// which is already playing carries on with it, so this can be called every frame.
+    pub fn play_music(&mut self, track: Option<&str>) {
+        if self.music() == track {
+            return;
+        }
+        if let Some(old) = self.music.take() {
+            self.fading_out.push(old);
+        }
+        if let Some(track) = track {
+            // Coming back to a track which was on its way out picks up where it was
+            let track = match self.fading_out.iter().position(|old| old.name == track) {
+                Some(index) => self.fading_out.remove(index),
+                None => {
+                    self.backend.play_music(track);
+                    Track { name: track.to_string(), fade: 0.0 }
+                }
+            };
+            self.music = Some(track);
+        }
+        self.apply_music_volumes();
+    }
+
+    pub fn play_sound(&mut self, sound: Sound) {
+        let volume = self.volumes.master * self.volumes.sfx;
+        if volume > 0.0 {
+            self.backend.play_sound(sound, volume);
+        }
+    }
+
+    /// Plays an ambient sound once, as if it came from right where the player is.
+    pub fn play_ambient(&mut self, sound: &str) {
+        let volume = self.volumes.master * self.volumes.sfx;
+        if volume > 0.0 {
+            self.backend.play_ambient(sound, volume, 0.0);
+        }
+    }
+
+    /// Plays the emitters which can be heard from the middle of the screen. Looping emitters keep
+    /// going until they are out of range, and when several loop the same sound the loudest one wins.
+    pub fn hear_emitters(&mut self, heard: &[Heard]) {
+        let volume = self.volumes.master * self.volumes.sfx;
+        let mut in_range = HashSet::new();
+        let mut loudest: HashMap<&str, &Heard> = HashMap::new();
+        for emitter in heard {
+            if emitter.looping {
+                let current = loudest.entry(&emitter.sound).or_insert(emitter);
+                if emitter.volume > current.volume {
+                    *current = emitter;
+                }
+            } else {
+                if !self.emitters_in_range.contains(&emitter.entity) && volume > 0.0 {
+                    self.backend.play_ambient(&emitter.sound, emitter.volume * volume, emitter.pan);
+                }
+                in_range.insert(emitter.entity);
+            }
+        }
+        self.emitters_in_range = in_range;
+
+        for (sound, emitter) in loudest.iter() {
+            self.backend.loop_ambient(sound, emitter.volume * volume, emitter.pan);
+        }
+        let backend = &mut self.backend;
+        self.loops.retain(|sound| {
+            if loudest.contains_key(sound.as_str()) {
+                return true;
+            }
+            backend.stop_ambient(sound);
+            false
+        });
+        self.loops.extend(loudest.keys().map(|sound| sound.to_string()));
+    }
+
+    pub fn is_looping(&self, sound: &str) -> bool {
+        self.loops.contains(sound)
+    }
+
+    /// Moves every crossfade along by some amount of time.
+    pub fn update(&mut self, seconds: f32) {
+        let step = seconds / CROSSFADE_SECONDS;
+        if let Some(track) = &mut self.music {
+            track.fade = (track.fade + step).min(1.0);
+        }
+        for track in self.fading_out.iter_mut() {
+            track.fade = (track.fade - step).max(0.0);
+        }
+        let backend = &mut self.backend;
+        self.fading_out.retain(|track| {
+            if track.fade > 0.0 {
+                return true;
+            }
+            backend.stop_music(&track.name);
+            false
+        });
+        self.apply_music_volumes();
+    }
+
+    /// How loud a track is playing right now, taking its fade into account.
+    pub fn music_volume(&self, track: &str) -> f32 {
+        self.music.iter().chain(self.fading_out.iter())
+            .find(|playing| playing.name == track)
+            .map_or(0.0, |playing| playing.fade * self.volumes.master * self.volumes.music)
+    }
+
+    fn apply_music_volumes(&mut self) {
+        let volume = self.volumes.master * self.volumes.music;
+        for track in self.music.iter().chain(self.fading_out.iter()) {
+            self.backend.set_music_volume(&track.name, track.fade * volume);
+        }
+    }
+}
+
+/// Plays the sounds of whatever happened in the world since the last frame, and keeps the music in step
+/// with the map. The world is only heard while in game, menus over the top keep its music going.
+pub fn audio_update(state: &mut GameState, ctx: &mut Context) {
+    for event in state.audio.events.read(&state.ecs) {
+        if let Some(sound) = event_sound(&state.ecs, &event) {
+            state.audio.play_sound(sound);
+        }
+        if let GameEvent::SoundPlayed { sound } = event {
+            state.audio.play_ambient(&sound);
+        }
+    }
+    let music = if state.states.contains(RootState::InGame) {
+        state.ecs.fetch::<MapProperties>().get("music").map(str::to_string)
+    } else {
+        None
+    };
+    state.audio.play_music(music.as_deref());
+
+    // The camera follows the player around, so they are always in the middle of the screen
+    let heard = if state.states.contains(RootState::InGame) {
+        heard_emitters(&state.ecs)
+    } else {
+        vec![]
+    };
+    state.audio.hear_emitters(&heard);
+    state.audio.update(timer::delta(ctx).as_secs_f32());
+}
+
+fn heard_emitters(ecs: &World) -> Vec<Heard> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let emitters = ecs.read_storage::<AudioEmitter>();
+    let camera = match (&positions, &players).join().next() {
+        Some((position, _player)) => (position.x, position.y),
+        None => return vec![],
+    };
+    (&entities, &positions, &emitters).join()
+        .filter_map(|(entity, position, emitter)| {
+            let (volume, pan) = hear(emitter, (position.x - camera.0, position.y - camera.1))?;
+            Some(Heard { entity, sound: emitter.sound.clone(), looping: emitter.looping, volume, pan })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object};
+    use crate::component::*;
+    use crate::map::{self, TileType};
+    use crate::simulation;
+    use crate::Direction;
+    use std::path::Path;
+    use crate::events::{GameEvent, Subscription};
+
+    #[test]
+    fn walking_and_picking_things_up_makes_sounds() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[object("Apples", "item", (1, 0), &[("item", "apple"), ("count", "3")])]);
+        let mut events = Subscription::default();
+        events.subscribe(&game.ecs);
+        for _ in 0..10 {
+            game.press(Direction::Right);
+            game.step(5);
+        }
+        let events = events.read(&game.ecs);
+        let player = (&game.ecs.entities(), &game.ecs.read_storage::<Player>()).join().next().unwrap().0;
+        assert!(events.contains(&GameEvent::TileEntered { entity: player, tile: (1, 0), tile_type: TileType::Floor }));
+        assert!(events.contains(&GameEvent::ItemPickedUp { entity: player, id: "apple".to_string(), count: 3 }));
+        assert!(events.iter().any(|event| matches!(event, GameEvent::PlayerMoved { .. })));
+        let sounds: Vec<Sound> = events.iter().filter_map(|event| event_sound(&game.ecs, event)).collect();
+        assert_eq!(sounds, vec![Sound::Footstep(TileType::Floor), Sound::Pickup]);
+    }
+
+    #[test]
+    fn music_comes_from_the_map_and_crossfades() {
+        let game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        assert_eq!(game.ecs.fetch::<simulation::MapProperties>().get("music"), Some("village"));
+
+        let mut audio = AudioManager::silent();
+        audio.set_volumes(Volumes { master: 0.5, ..Volumes::default() });
+        audio.play_music(Some("village"));
+        audio.update(0.75);
+        assert_eq!(audio.music_volume("village"), 0.25);
+        audio.play_music(Some("cave"));
+        audio.update(0.75);
+        assert_eq!(audio.music(), Some("cave"));
+        assert_eq!(audio.music_volume("cave"), 0.25);
+        assert_eq!(audio.music_volume("village"), 0.0);
+        audio.play_music(None);
+        audio.update(5.0);
+        assert_eq!(audio.music(), None);
+        assert_eq!(audio.music_volume("cave"), 0.0);
+    }
+
+    #[test]
+    fn emitters_fall_off_with_distance_and_pan_to_their_side() {
+        let emitter = AudioEmitter { sound: "birdsong".to_string(), looping: true, range: 256.0, volume: 0.8 };
+        assert_eq!(hear(&emitter, (0.0, 0.0)), Some((0.8, 0.0)));
+        let (near, near_pan) = hear(&emitter, (-64.0, 0.0)).unwrap();
+        let (far, far_pan) = hear(&emitter, (-128.0, 0.0)).unwrap();
+        assert!(far < near && near < 0.8);
+        assert!(far_pan < near_pan && near_pan < 0.0);
+        assert_eq!(hear(&emitter, (0.0, 256.0)), None);
+        assert!(hear(&emitter, (200.0, 0.0)).unwrap().1 > 0.0);
+    }
+
+    #[test]
+    fn looping_emitters_play_while_in_range() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        let birds = game.ecs.create_entity().with(Position { x: 0.0, y: 0.0 }).build();
+        let heard = |volume| Heard { entity: birds, sound: "birdsong".to_string(), looping: true, volume, pan: 0.0 };
+        let mut audio = AudioManager::silent();
+        audio.hear_emitters(&[heard(0.2), heard(0.5)]);
+        assert!(audio.is_looping("birdsong"));
+        audio.hear_emitters(&[]);
+        assert!(!audio.is_looping("birdsong"));
+
+        // Reloading keeps the sounds placed on the map
+        game.reload();
+        let emitters = game.ecs.read_storage::<AudioEmitter>();
+        assert!((&emitters).join().any(|emitter| emitter.sound == "birdsong" && emitter.looping));
+        assert!((&emitters).join().any(|emitter| emitter.sound == "whistle" && !emitter.looping));
+    }
+}
@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+/// How many ticks make up a minute of in-game time, so that a whole day takes twelve minutes.
+pub const TICKS_PER_MINUTE: u64 = 30;
+pub const MINUTES_PER_DAY: u64 = 24 * 60;
+/// New games start on the first morning.
+const START_MINUTE: u64 = 8 * 60;
+
+/// The colour the world is tinted by through the day, by hour. The tint between two hours
+/// blends from one to the next, and the last hour wraps around into the first.
+const AMBIENT: [(f32, [f32; 3]); 7] = [
+    (0.0, [0.16, 0.18, 0.36]),
+    (4.5, [0.16, 0.18, 0.36]),
+    (6.5, [0.85, 0.62, 0.55]),
+    (8.0, [1.0, 1.0, 1.0]),
+    (17.0, [1.0, 1.0, 1.0]),
+    (19.0, [0.92, 0.58, 0.45]),
+    (21.0, [0.16, 0.18, 0.36]),
+];
+
+/// The time in the game world, counted in ticks since midnight before the first day.
+/// It only ever moves forwards.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Clock {
+    pub ticks: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock { ticks: START_MINUTE * TICKS_PER_MINUTE }
+    }
+}
+
+impl Clock {
+    pub fn advance(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Counting from the first day, which is day 1.
+    pub fn day(&self) -> u64 {
+        self.ticks / TICKS_PER_MINUTE / MINUTES_PER_DAY + 1
+    }
+
+    pub fn minute_of_day(&self) -> u64 {
+        self.ticks / TICKS_PER_MINUTE % MINUTES_PER_DAY
+    }
+
+    pub fn hour(&self) -> u64 {
+        self.minute_of_day() / 60
+    }
+
+    pub fn minute(&self) -> u64 {
+        self.minute_of_day() % 60
+    }
+
+    /// Moves forwards to the next time the clock shows the given time of day,
+    /// which may be later today or tomorrow. Hours past a day and minutes past an hour wrap around.
+    pub fn set_time(&mut self, hour: u64, minute: u64) {
+        let target = (hour % 24) * 60 + minute % 60;
+        let minutes = (target + MINUTES_PER_DAY - self.minute_of_day()) % MINUTES_PER_DAY;
+        self.ticks = (self.ticks / TICKS_PER_MINUTE + minutes) * TICKS_PER_MINUTE;
+    }
+
+    /// The time of day in hours, including how far through the hour it is.
+    pub fn hours(&self) -> f32 {
+        (self.ticks % (MINUTES_PER_DAY * TICKS_PER_MINUTE)) as f32 / (60 * TICKS_PER_MINUTE) as f32
+    }
+
+    /// The colour the whole world is tinted by right now, white at the height of the day.
+    pub fn ambient(&self) -> [f32; 3] {
+        let hours = self.hours();
+        let next = AMBIENT.iter().position(|(hour, _tint)| *hour > hours).unwrap_or(AMBIENT.len());
+        let (from_hour, from) = AMBIENT[next - 1];
+        let (to_hour, to) = match AMBIENT.get(next) {
+            Some(keyframe) => *keyframe,
+            None => (AMBIENT[0].0 + 24.0, AMBIENT[0].1),
+        };
+        let blend = (hours - from_hour) / (to_hour - from_hour);
+        [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * blend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, with_scripts};
+    use crate::component::*;
+    use crate::map;
+    use std::path::Path;
+    use specs::prelude::*;
+    use crate::script::Scripts;
+    use crate::TL_PX;
+
+    #[test]
+    fn the_clock_runs_through_the_day_and_tints_the_world() {
+        let mut game = Headless::new(Headless::floor(4, 4));
+        let morning = *game.ecs.fetch::<Clock>();
+        assert_eq!((morning.day(), morning.hour(), morning.minute()), (1, 8, 0));
+        assert_eq!(morning.ambient(), [1.0, 1.0, 1.0]);
+
+        game.step(TICKS_PER_MINUTE as u32 * 90);
+        let later = *game.ecs.fetch::<Clock>();
+        assert_eq!((later.hour(), later.minute()), (9, 30));
+
+        // Setting an earlier time of day waits for it to come around again tomorrow
+        let mut clock = later;
+        clock.set_time(2, 0);
+        assert_eq!((clock.day(), clock.hour(), clock.minute()), (2, 2, 0));
+        let night = clock.ambient();
+        clock.set_time(6, 0);
+        let dawn = clock.ambient();
+        clock.set_time(12, 0);
+        assert_eq!(clock.day(), 2);
+        assert_eq!(clock.ambient(), [1.0, 1.0, 1.0]);
+        for channel in 0..3 {
+            assert!(night[channel] < dawn[channel] && dawn[channel] < 1.0, "{:?} {:?}", night, dawn);
+        }
+        // Late in the evening blends back towards the night it wraps around into
+        clock.set_time(23, 0);
+        assert_eq!(clock.ambient(), night);
+    }
+
+    #[test]
+    fn scripts_see_and_set_the_time_and_lights_are_saved() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        with_scripts(&mut game, "clock", &[
+            ("dusk", r#"
+                fn run() {
+                    if hour() == 8 && day() == 1 {
+                        set_time(21, 30);
+                    }
+                    message("It is " + hour() + ":" + minute());
+                }
+            "#),
+        ]);
+        game.spawn(&[object("Torch", "light", (3, 3), &[("light", "2"), ("light_color", "#ffff8040"), ("light_intensity", "0.5")])]);
+        game.ecs.fetch_mut::<Scripts>().call("dusk", "run", "");
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["It is 21:30".to_string()]);
+
+        game.reload();
+        let clock = *game.ecs.fetch::<Clock>();
+        assert_eq!((clock.day(), clock.hour(), clock.minute()), (1, 21, 30));
+        let players = game.ecs.read_storage::<Player>();
+        let lights = game.ecs.read_storage::<LightSource>();
+        assert!((&players, &lights).join().next().is_some(), "the player lost their lantern");
+        let positions = game.ecs.read_storage::<Position>();
+        let (torch, _position) = (&lights, &positions).join()
+            .find(|(_light, position)| (map::to_tl(position.x), map::to_tl(position.y)) == (3, 3))
+            .expect("the torch was not saved");
+        assert_eq!(torch.radius, 2.0 * TL_PX as f32);
+        assert_eq!(torch.color, [1.0, 128.0 / 255.0, 64.0 / 255.0]);
+        assert_eq!(torch.intensity, 0.5);
+    }
+}
@@ -0,0 +1,175 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::Point2;
+use crate::events::{GameEvent, GameEvents};
+
+/// Whatever is knocked back slows down to a tenth of its velocity every tick, so a push of some
+/// velocity ends up moving it about a ninth as far in total. Knockback is given in pixels, so it is
+/// scaled back up by this much before being handed to the player or npc being pushed.
+const KNOCKBACK_SCALE: f32 = 9.0;
+
+/// Resolves hitboxes overlapping hurtboxes of the other faction into damage and knockback,
+/// and counts down everything in combat that only lasts a while.
+pub struct CombatSystem {}
+
+impl<'a> System<'a> for CombatSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Hitbox>,
+        ReadStorage<'a, Hurtbox>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Player>,
+        WriteStorage<'a, Knockback>,
+        WriteStorage<'a, PathFollow>,
+        WriteStorage<'a, Attacking>,
+        Write<'a, GameEvents>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, positions, mut hitboxes, hurtboxes, mut healths, mut players, mut knockbacks, mut paths, mut attacks, mut events) = data;
+
+        // Invulnerability from earlier hits wears off before anything new can land
+        for health in (&mut healths).join() {
+            health.iframes = health.iframes.saturating_sub(1);
+        }
+
+        for (hitbox_position, hitbox) in (&positions, &mut hitboxes).join() {
+            for (target, target_position, hurtbox, health) in (&entities, &positions, &hurtboxes, &mut healths).join() {
+                if hurtbox.faction == hitbox.faction || health.iframes > 0 || hitbox.hit.contains(&target) {
+                    continue;
+                }
+                if !overlaps(hitbox_position, hitbox.width, hitbox.height, target_position, hurtbox.width, hurtbox.height) {
+                    continue;
+                }
+
+                health.current -= hitbox.damage;
+                health.iframes = health.iframes_on_hit;
+                if hitbox.lifetime.is_some() {
+                    hitbox.hit.push(target);
+                }
+                events.single_write(GameEvent::Hit {
+                    entity: target,
+                    x: (hitbox_position.x + target_position.x) / 2.0,
+                    y: (hitbox_position.y + target_position.y) / 2.0,
+                });
+
+                // Being hit knocks the target straight away from the middle of the hitbox
+                let dx = target_position.x - hitbox_position.x;
+                let dy = target_position.y - hitbox_position.y;
+                let distance = (dx * dx + dy * dy).sqrt();
+                if distance < f32::EPSILON {
+                    continue;
+                }
+                let push = Point2::new(dx / distance * hitbox.knockback, dy / distance * hitbox.knockback);
+                // Whatever the target was walking towards, it is not going there now
+                paths.remove(target);
+                match players.get_mut(target) {
+                    Some(player) => {
+                        player.velocity.x += push.x * KNOCKBACK_SCALE;
+                        player.velocity.y += push.y * KNOCKBACK_SCALE;
+                    }
+                    None => {
+                        knockbacks.insert(target, Knockback { velocity: Point2::new(push.x * KNOCKBACK_SCALE, push.y * KNOCKBACK_SCALE) })
+                            .expect("could not insert knockback");
+                    }
+                }
+            }
+        }
+
+        // Swings only hurt for a few ticks before disappearing
+        for (entity, hitbox) in (&entities, &mut hitboxes).join() {
+            if let Some(lifetime) = &mut hitbox.lifetime {
+                *lifetime = lifetime.saturating_sub(1);
+                if *lifetime == 0 {
+                    entities.delete(entity).expect("could not delete expired hitbox");
+                }
+            }
+        }
+
+        let mut finished_attacks = vec![];
+        for (entity, attack) in (&entities, &mut attacks).join() {
+            attack.ticks = attack.ticks.saturating_sub(1);
+            if attack.ticks == 0 {
+                finished_attacks.push(entity);
+            }
+        }
+        for entity in finished_attacks {
+            attacks.remove(entity);
+        }
+    }
+}
+
+/// Whether two boxes centered on positions overlap.
+fn overlaps(a: &Position, a_width: f32, a_height: f32, b: &Position, b_width: f32, b_height: f32) -> bool {
+    (a.x - b.x).abs() * 2.0 < a_width + b_width && (a.y - b.y).abs() * 2.0 < a_height + b_height
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::{Headless, object, enemy};
+    use crate::simulation;
+    use crate::input::{Action, Input};
+    use crate::Direction;
+
+    #[test]
+    fn attacking_hurts_and_knocks_back_what_the_player_faces() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[enemy("Slime", (0, 1), "3")]);
+        game.press(Direction::Down);
+        game.step(1);
+        game.input(Input::Action(Action::Attack));
+        game.step(10);
+        assert_eq!(game.npc_health("Slime"), Some(2));
+        let (_x, y) = game.npc_position("Slime");
+        assert!(y > 40.0, "slime was not knocked back, it is at y = {}", y);
+    }
+
+    #[test]
+    fn a_swing_hits_each_target_once() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[enemy("Slime", (1, 0), "3")]);
+        game.press(Direction::Right);
+        game.input(Input::Action(Action::Attack));
+        // Attacking again mid swing does nothing
+        game.step(2);
+        game.input(Input::Action(Action::Attack));
+        game.step(simulation::ATTACK_TICKS);
+        assert_eq!(game.npc_health("Slime"), Some(2));
+    }
+
+    #[test]
+    fn enemies_die_when_out_of_health() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[enemy("Slime", (1, 0), "1")]);
+        game.press(Direction::Right);
+        game.input(Input::Action(Action::Attack));
+        game.step(2);
+        assert_eq!(game.npc_health("Slime"), None);
+    }
+
+    #[test]
+    fn enemies_chase_and_hurt_the_player() {
+        let mut game = Headless::new(Headless::floor(20, 20));
+        game.spawn(&[object("Slime", "enemy", (6, 0), &[("sight", "8")])]);
+        game.step(120);
+        let (x, _y) = game.npc_position("Slime");
+        assert!(x < 64.0, "slime did not chase the player, it is at x = {}", x);
+        assert!(game.player_health() < 6);
+    }
+
+    #[test]
+    fn invulnerability_stops_repeated_hits() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.input(Input::MoveTo(3, 3));
+        game.step(120);
+        game.spawn(&[enemy("Slime", (4, 3), "3")]);
+        game.step(1);
+        assert_eq!(game.player_health(), 5);
+        // Contact keeps going, but the player is invulnerable for a second after each hit
+        game.step(30);
+        assert_eq!(game.player_health(), 5);
+        let (x, _y) = game.player_position();
+        assert!(x < 96.0, "player was not knocked back, they are at x = {}", x);
+    }
+}
@@ -1,17 +1,20 @@
+use std::collections::VecDeque;
 use specs::prelude::*;
 use specs_derive::Component;
+use serde::{Deserialize, Serialize};
 use ggez::nalgebra as na;
 use crate::Direction;
+use crate::map::MapObject;
 
 type Point2 = na::Point2<f32>;
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Renderable {
 }
 
@@ -28,3 +31,270 @@ pub struct Viewport {
     pub tiles: Vec<(i32, i32, i32, i32, i32, i32)>,
     pub dirty: bool
 }
+
+/// Tiles for the entity to walk through in order, ending at its destination.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct PathFollow {
+    pub path: VecDeque<(i32, i32)>,
+}
+
+/// A non player character, drawn using the same sprite sheet as the player.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Npc {
+    pub name: String,
+    pub direction: Direction,
+    pub animation_index: u8,
+}
+
+/// Stops other entities from walking onto the tile this entity stands on.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct BlocksTile {}
+
+/// Something the player can talk to by facing it and pressing interact.
+/// `dialogue` is the id of the dialogue tree to open.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Interactable {
+    pub dialogue: String,
+}
+
+/// How an npc decides where to walk next.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Behaviour {
+    /// Stand still forever.
+    Idle,
+    /// Walk to random tiles no further than `radius` tiles from `origin`, pausing in between.
+    Wander { origin: (i32, i32), radius: i32 },
+    /// Walk between the tiles of a route in order, starting over once the end is reached.
+    Patrol { route: Vec<(i32, i32)>, next: usize },
+    /// Run at the player whenever they come within `sight` tiles.
+    Chase { sight: i32 },
+}
+
+/// Drives an npc around using its behaviour.
+/// `wait` counts down the ticks left to pause once the current path is walked.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Ai {
+    pub behaviour: Behaviour,
+    pub wait: u32,
+}
+
+/// An item lying in the world, waiting to be picked up.
+/// Items are picked up by walking over them, unless `auto_pickup` is off,
+/// in which case the player has to interact with them.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Item {
+    pub id: String,
+    pub count: u32,
+    pub auto_pickup: bool,
+}
+
+/// Some number of the same item, taking up one inventory slot.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub id: String,
+    pub count: u32,
+}
+
+/// Items being carried around, in the order they were picked up.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Inventory {
+    pub slots: Vec<ItemStack>,
+    pub capacity: usize,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Inventory {
+        Inventory { slots: vec![], capacity }
+    }
+
+    /// Adds items, topping up existing stacks before starting new ones.
+    /// Returns how many did not fit.
+    pub fn add(&mut self, id: &str, count: u32, max_stack: u32) -> u32 {
+        let mut remaining = count;
+        for stack in self.slots.iter_mut().filter(|stack| stack.id == id) {
+            let added = remaining.min(max_stack.saturating_sub(stack.count));
+            stack.count += added;
+            remaining -= added;
+        }
+        while remaining > 0 && self.slots.len() < self.capacity {
+            let added = remaining.min(max_stack);
+            self.slots.push(ItemStack { id: id.to_string(), count: added });
+            remaining -= added;
+        }
+        remaining
+    }
+
+    /// Takes up to `count` items out of a slot, freeing the slot once it is empty.
+    pub fn take(&mut self, slot: usize, count: u32) -> Option<ItemStack> {
+        let stack = self.slots.get_mut(slot)?;
+        let taken = count.min(stack.count);
+        stack.count -= taken;
+        let id = stack.id.clone();
+        if stack.count == 0 {
+            self.slots.remove(slot);
+        }
+        Some(ItemStack { id, count: taken })
+    }
+
+    pub fn count(&self, id: &str) -> u32 {
+        self.slots.iter().filter(|stack| stack.id == id).map(|stack| stack.count).sum()
+    }
+}
+
+/// Which side something fights on. Hitboxes only ever hurt the other side.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Faction {
+    Friendly,
+    Hostile,
+}
+
+/// How much damage something can take before it dies.
+/// After being hurt it cannot be hurt again until `iframes` has counted back down to zero.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+    pub iframes: u32,
+    /// How many ticks of invulnerability each hit grants.
+    pub iframes_on_hit: u32,
+}
+
+impl Health {
+    pub fn new(max: i32, iframes_on_hit: u32) -> Health {
+        Health { current: max, max, iframes: 0, iframes_on_hit }
+    }
+}
+
+/// The area around an entity where it can be hurt, centered on its position.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Hurtbox {
+    pub faction: Faction,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// An area which hurts anything of the other faction that overlaps it, centered on its position.
+/// Hitboxes with a lifetime, like swings of a weapon, hit each target at most once.
+/// Hitboxes without one, like the body of an enemy, keep hurting for as long as they touch.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Hitbox {
+    pub faction: Faction,
+    pub width: f32,
+    pub height: f32,
+    pub damage: i32,
+    /// Roughly how many pixels a hit pushes the target away by.
+    pub knockback: f32,
+    pub lifetime: Option<u32>,
+    /// Only filled in by hitboxes with a lifetime, which are never saved.
+    #[serde(skip)]
+    pub hit: Vec<Entity>,
+}
+
+/// Pushes an entity which does not steer itself, like an npc, after being hit.
+/// The player is pushed through its own velocity instead.
+#[derive(Component, Debug)]
+pub struct Knockback {
+    pub velocity: Point2,
+}
+
+/// Counts down the ticks of an attack in progress. No new attack can start until it is over.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Attacking {
+    pub ticks: u32,
+}
+
+/// Makes a sound heard from wherever the entity is, louder and more off to one side
+/// depending on where it is from the middle of the screen.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct AudioEmitter {
+    /// Name of a sound in the ambient sound directory.
+    pub sound: String,
+    /// Looping sounds play for as long as they are in range,
+    /// others play once every time they come into range.
+    pub looping: bool,
+    /// How far away the sound can be heard from, in pixels.
+    pub range: f32,
+    pub volume: f32,
+}
+
+/// When a trigger goes off, going by where the player is relative to its zone.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TriggerWhen {
+    Enter,
+    Exit,
+    /// The player interacts while standing in the zone or facing into it.
+    Interact,
+}
+
+impl TriggerWhen {
+    /// The function of a script which is called when a trigger goes off at this time.
+    pub fn function(self) -> &'static str {
+        match self {
+            TriggerWhen::Enter => "on_enter",
+            TriggerWhen::Exit => "on_exit",
+            TriggerWhen::Interact => "on_interact",
+        }
+    }
+}
+
+/// Something a trigger does when it goes off.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TriggerAction {
+    /// Shows a message to the player.
+    Message(String),
+    /// Sets one of the named flags remembering what the player has done.
+    SetFlag(String),
+    /// Moves the player onto a tile, on a different map if one is given.
+    Warp { map: Option<String>, tile: (i32, i32) },
+    /// Plays a sound out of the ambient sound directory.
+    PlaySound(String),
+    /// Spawns an object which was placed on the map to be spawned later rather than straight away.
+    Spawn(MapObject),
+    /// Starts the quest with the given id.
+    StartQuest(String),
+    /// Calls the function of a script named after when the trigger went off, such as `on_enter`.
+    Script(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trigger {
+    pub when: TriggerWhen,
+    pub action: TriggerAction,
+    /// Triggers which only go off once are spent afterwards, others go off every time.
+    pub once: bool,
+    pub spent: bool,
+}
+
+/// A rectangle of tiles which does things as the player walks in and out of it or interacts with it.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct TriggerZone {
+    pub name: String,
+    /// The first and last tiles covered by the zone, both inclusive.
+    pub area: ((i32, i32), (i32, i32)),
+    pub triggers: Vec<Trigger>,
+    /// Whether the player was inside the zone last tick, to tell entering from leaving.
+    pub occupied: bool,
+}
+
+impl TriggerZone {
+    pub fn contains(&self, tile: (i32, i32)) -> bool {
+        let ((min_x, min_y), (max_x, max_y)) = self.area;
+        tile.0 >= min_x && tile.0 <= max_x && tile.1 >= min_y && tile.1 <= max_y
+    }
+}
+
+/// Calls functions of a script when things happen to the entity,
+/// such as `on_interact` when the player interacts with it.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Scripted {
+    pub script: String,
+}
+
+/// Brightens the world around an entity once it gets dark, fading out to nothing `radius` pixels away.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct LightSource {
+    pub radius: f32,
+    pub color: [f32; 3],
+    /// How strongly the light shines at its center, from 0.0 to 1.0.
+    pub intensity: f32,
+}
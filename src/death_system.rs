@@ -0,0 +1,83 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::map;
+use crate::simulation::Notifications;
+use crate::events::{GameEvent, GameEvents};
+use crate::Point2;
+
+/// Where the player comes back to life, as a tile.
+#[derive(Default, Debug)]
+pub struct SpawnPoint(pub (i32, i32));
+
+/// How long the player is left alone after coming back to life.
+const RESPAWN_IFRAMES: u32 = 120;
+
+/// Removes anything which has run out of health.
+/// The player is never removed, they respawn with full health instead.
+pub struct DeathSystem {}
+
+impl<'a> System<'a> for DeathSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, SpawnPoint>,
+        WriteExpect<'a, Notifications>,
+        Write<'a, GameEvents>,
+        ReadStorage<'a, Npc>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Player>,
+        WriteStorage<'a, PathFollow>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, spawn, mut notifications, mut events, npcs, mut positions, mut healths, mut players, mut paths) = data;
+        let (spawn_x, spawn_y) = spawn.0;
+
+        for (entity, position, health) in (&entities, &mut positions, &mut healths).join() {
+            if health.current > 0 {
+                continue;
+            }
+            let player = match players.get_mut(entity) {
+                Some(player) => player,
+                None => {
+                    if let Some(npc) = npcs.get(entity) {
+                        notifications.push(format!("Defeated {}", npc.name));
+                    }
+                    entities.delete(entity).expect("could not delete dead entity");
+                    continue;
+                }
+            };
+
+            notifications.push("You were knocked out!".to_string());
+            position.x = map::to_px(spawn_x) as f32;
+            position.y = map::to_px(spawn_y) as f32;
+            player.velocity = Point2::origin();
+            health.current = health.max;
+            health.iframes = RESPAWN_IFRAMES;
+            paths.remove(entity);
+            events.single_write(GameEvent::PlayerMoved { entity, x: position.x, y: position.y });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, enemy};
+
+    #[test]
+    fn player_respawns_with_full_health() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.ecs.insert(SpawnPoint((3, 3)));
+        {
+            let players = game.ecs.read_storage::<Player>();
+            let mut healths = game.ecs.write_storage::<Health>();
+            let (_player, health) = (&players, &mut healths).join().next().unwrap();
+            health.current = 1;
+        }
+        game.spawn(&[enemy("Slime", (1, 0), "3")]);
+        game.step(1);
+        assert_eq!(game.player_tile(), (3, 3));
+        assert_eq!(game.player_health(), 6);
+    }
+}
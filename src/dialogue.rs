@@ -0,0 +1,373 @@
+use crate::{GameState, Point2, RootState, WIDTH_PX, HEIGHT_PX};
+use crate::input::Action;
+use crate::simulation::TICKS_PER_SECOND;
+use ggez::{graphics, Context, GameResult, timer};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Dialogue trees live here, one ron file per tree named after its id.
+pub const DIALOGUE_DIR: &str = "assets/dialogue";
+
+/// How many ticks the typewriter waits before revealing the next character.
+const TICKS_PER_CHARACTER: u32 = 2;
+
+const BOX_MARGIN_PX: f32 = 16.0;
+const BOX_PADDING_PX: f32 = 12.0;
+const BOX_HEIGHT_PX: f32 = 180.0;
+const SPEAKER_FONT_SIZE: f32 = 22.0;
+const TEXT_FONT_SIZE: f32 = 20.0;
+const CHOICE_SPACING_PX: f32 = 24.0;
+
+/// One line of a conversation, along with where the conversation can go from here.
+/// A line with choices branches on whichever one is picked, otherwise it carries on to `next`.
+/// The conversation ends once there is nowhere left to go.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueNode {
+    pub speaker: String,
+    pub text: String,
+    #[serde(default)]
+    pub choices: Vec<DialogueChoice>,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueChoice {
+    pub text: String,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// A whole conversation, as it is written in a dialogue file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueTree {
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+#[derive(Debug)]
+pub enum DialogueError {
+    Io(io::Error),
+    Parse(ron::Error),
+    /// A line leads to another line which does not exist.
+    MissingNode { from: String, to: String },
+}
+
+impl fmt::Display for DialogueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DialogueError::Io(e) => write!(f, "could not read dialogue: {}", e),
+            DialogueError::Parse(e) => write!(f, "could not parse dialogue: {}", e),
+            DialogueError::MissingNode { from, to } => write!(f, "{} leads to missing line {}", from, to),
+        }
+    }
+}
+
+impl DialogueTree {
+    /// Loads the dialogue tree with the given id from the dialogue directory.
+    pub fn load(id: &str) -> Result<DialogueTree, DialogueError> {
+        DialogueTree::load_file(&DialogueTree::path(id))
+    }
+
+    pub fn path(id: &str) -> PathBuf {
+        Path::new(DIALOGUE_DIR).join(format!("{}.ron", id))
+    }
+
+    pub fn load_file(path: &Path) -> Result<DialogueTree, DialogueError> {
+        let contents = fs::read_to_string(path).map_err(DialogueError::Io)?;
+        contents.parse()
+    }
+
+    /// Makes sure that every line the conversation can reach actually exists,
+    /// so that a typo in a data file is caught on load rather than halfway through talking.
+    fn validate(&self) -> Result<(), DialogueError> {
+        let missing = |from: &str, to: &str| DialogueError::MissingNode { from: from.to_string(), to: to.to_string() };
+        if !self.nodes.contains_key(&self.start) {
+            return Err(missing("start", &self.start));
+        }
+        for (id, node) in self.nodes.iter() {
+            let targets = node.next.iter().chain(node.choices.iter().filter_map(|choice| choice.next.as_ref()));
+            for target in targets {
+                if !self.nodes.contains_key(target) {
+                    return Err(missing(id, target));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for DialogueTree {
+    type Err = DialogueError;
+
+    fn from_str(s: &str) -> Result<DialogueTree, DialogueError> {
+        let tree: DialogueTree = ron::de::from_str(s).map_err(DialogueError::Parse)?;
+        tree.validate()?;
+        Ok(tree)
+    }
+}
+
+/// A conversation in progress.
+/// Text is revealed a character at a time, and confirming before it is all
+/// shown skips straight to the end of the line instead of moving on.
+#[derive(Debug)]
+pub struct Dialogue {
+    tree: DialogueTree,
+    current: String,
+    revealed: usize,
+    ticks: u32,
+    selected: usize,
+}
+
+impl Dialogue {
+    pub fn new(tree: DialogueTree) -> Dialogue {
+        let current = tree.start.clone();
+        Dialogue { tree, current, revealed: 0, ticks: 0, selected: 0 }
+    }
+
+    pub fn node(&self) -> &DialogueNode {
+        &self.tree.nodes[&self.current]
+    }
+
+    /// The part of the current line which the typewriter has revealed so far.
+    pub fn revealed_text(&self) -> &str {
+        let text = &self.node().text;
+        match text.char_indices().nth(self.revealed) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        }
+    }
+
+    pub fn is_revealed(&self) -> bool {
+        self.revealed >= self.node().text.chars().count()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Advances the typewriter by a single tick.
+    pub fn tick(&mut self) {
+        if self.is_revealed() {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks >= TICKS_PER_CHARACTER {
+            self.ticks = 0;
+            self.revealed += 1;
+        }
+    }
+
+    /// Moves the highlighted choice up or down, wrapping around at either end.
+    pub fn select(&mut self, offset: i32) {
+        let choices = self.node().choices.len() as i32;
+        if choices > 0 {
+            self.selected = (self.selected as i32 + offset).rem_euclid(choices) as usize;
+        }
+    }
+
+    /// Finishes revealing the current line, or moves past it once it is fully shown.
+    /// Returns false once the conversation is over.
+    pub fn confirm(&mut self) -> bool {
+        if !self.is_revealed() {
+            self.revealed = self.node().text.chars().count();
+            return true;
+        }
+        let node = self.node();
+        let next = match node.choices.get(self.selected) {
+            Some(choice) => choice.next.clone(),
+            None => node.next.clone(),
+        };
+        match next {
+            Some(next) => {
+                self.current = next;
+                self.revealed = 0;
+                self.ticks = 0;
+                self.selected = 0;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Starts talking, pausing the world until the conversation is over.
+/// A dialogue which cannot be loaded is reported and otherwise ignored.
+pub fn open_dialogue(state: &mut GameState, id: &str) {
+    match DialogueTree::load(id) {
+        Ok(tree) => {
+            state.dialogue = Some(Dialogue::new(tree));
+            state.states.push(RootState::Dialogue);
+        }
+        Err(e) => println!("Could not open dialogue {}, {}", id, e),
+    }
+}
+
+fn close_dialogue(state: &mut GameState) {
+    state.dialogue = None;
+    state.states.pop();
+}
+
+pub fn dialogue_input(state: &mut GameState, _ctx: &mut Context, action: Action) {
+    let dialogue = match &mut state.dialogue {
+        Some(dialogue) => dialogue,
+        None => return close_dialogue(state),
+    };
+    let finished = match action {
+        Action::MoveUp => {
+            dialogue.select(-1);
+            false
+        }
+        Action::MoveDown => {
+            dialogue.select(1);
+            false
+        }
+        Action::Interact => !dialogue.confirm(),
+        // Walking away from a conversation is always allowed
+        Action::Pause => true,
+        _ => false,
+    };
+    if finished {
+        close_dialogue(state);
+    }
+}
+
+/// Only the typewriter moves while talking, the world itself stays paused.
+pub fn dialogue_update(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    while timer::check_update_time(ctx, TICKS_PER_SECOND) {
+        if let Some(dialogue) = &mut state.dialogue {
+            dialogue.tick();
+        }
+    }
+    Ok(())
+}
+
+/// Draws the dialogue box over the bottom of the frozen world.
+pub fn dialogue_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    if let Some(dialogue) = &state.dialogue {
+        render_dialogue_box(ctx, state.font, dialogue)?;
+    }
+    Ok(())
+}
+
+fn render_dialogue_box(ctx: &mut Context, font: graphics::Font, dialogue: &Dialogue) -> GameResult<()> {
+    let box_rect = graphics::Rect::new(
+        BOX_MARGIN_PX,
+        HEIGHT_PX as f32 - BOX_HEIGHT_PX - BOX_MARGIN_PX,
+        WIDTH_PX as f32 - BOX_MARGIN_PX * 2.0,
+        BOX_HEIGHT_PX,
+    );
+    let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), box_rect, [0.0, 0.0, 0.0, 0.8].into())?;
+    graphics::draw(ctx, &background, graphics::DrawParam::default())?;
+    let border = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), box_rect, graphics::WHITE)?;
+    graphics::draw(ctx, &border, graphics::DrawParam::default())?;
+
+    let left = box_rect.x + BOX_PADDING_PX;
+    let mut top = box_rect.y + BOX_PADDING_PX;
+    let node = dialogue.node();
+
+    let speaker = graphics::Text::new((node.speaker.as_str(), font, SPEAKER_FONT_SIZE));
+    graphics::draw(ctx, &speaker, (Point2::new(left, top), [1.0, 0.85, 0.4, 1.0].into()))?;
+    top += SPEAKER_FONT_SIZE + 6.0;
+
+    let mut text = graphics::Text::new((dialogue.revealed_text(), font, TEXT_FONT_SIZE));
+    text.set_bounds(Point2::new(box_rect.w - BOX_PADDING_PX * 2.0, f32::INFINITY), graphics::Align::Left);
+    graphics::draw(ctx, &text, (Point2::new(left, top), graphics::WHITE))?;
+
+    // Choices only show up once the line has been read
+    if dialogue.is_revealed() {
+        let (_width, text_height) = text.dimensions(ctx);
+        top += text_height as f32 + 8.0;
+        for (index, choice) in node.choices.iter().enumerate() {
+            let (marker, color) = if index == dialogue.selected() {
+                ("> ", [1.0, 0.85, 0.4, 1.0])
+            } else {
+                ("   ", [0.8, 0.8, 0.8, 1.0])
+            };
+            let choice_text = graphics::Text::new((format!("{}{}", marker, choice.text), font, TEXT_FONT_SIZE));
+            graphics::draw(ctx, &choice_text, (Point2::new(left, top), color.into()))?;
+            top += CHOICE_SPACING_PX;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const BRANCHING_DIALOGUE: &str = r#"(
+        start: "hello",
+        nodes: {
+            "hello": (
+                speaker: "Elder",
+                text: "Hi",
+                choices: [
+                    (text: "Bye", next: None),
+                    (text: "More", next: Some("more")),
+                ],
+            ),
+            "more": (speaker: "Elder", text: "Ok", next: Some("end")),
+            "end": (speaker: "Elder", text: "Done"),
+        },
+    )"#;
+
+    #[test]
+    fn dialogue_follows_the_chosen_branch() {
+        let tree: DialogueTree = BRANCHING_DIALOGUE.parse().unwrap();
+        let mut talk = Dialogue::new(tree);
+        assert_eq!(talk.revealed_text(), "");
+        talk.select(-1);
+        assert_eq!(talk.selected(), 1);
+        assert!(talk.confirm(), "confirming while typing should only reveal the line");
+        assert!(talk.is_revealed());
+        assert!(talk.confirm());
+        assert_eq!(talk.node().text, "Ok");
+        assert!(talk.confirm());
+        assert!(talk.confirm());
+        assert_eq!(talk.node().text, "Done");
+        assert!(talk.confirm());
+        assert!(!talk.confirm());
+    }
+
+    #[test]
+    fn typewriter_reveals_text_over_time() {
+        let tree: DialogueTree = BRANCHING_DIALOGUE.parse().unwrap();
+        let mut talk = Dialogue::new(tree);
+        talk.tick();
+        talk.tick();
+        assert_eq!(talk.revealed_text(), "H");
+        for _ in 0..10 {
+            talk.tick();
+        }
+        assert_eq!(talk.revealed_text(), "Hi");
+        assert!(talk.is_revealed());
+    }
+
+    #[test]
+    fn dialogue_leading_nowhere_is_rejected() {
+        let text = BRANCHING_DIALOGUE.replace("Some(\"end\")", "Some(\"nowhere\")");
+        match text.parse::<DialogueTree>() {
+            Err(DialogueError::MissingNode { from, to }) => {
+                assert_eq!(from, "more");
+                assert_eq!(to, "nowhere");
+            }
+            other => panic!("expected a missing node, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn shipped_dialogue_files_load() {
+        for entry in fs::read_dir(DIALOGUE_DIR).unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(e) = DialogueTree::load_file(&path) {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::map::TileType;
+use crate::screen_effects::ScreenEffect;
+use specs::prelude::*;
+use specs::shrev::EventChannel;
+
+/// Something that happened in the world. Systems publish these for anything that wants
+/// to react to them, without having to know who is listening.
+#[derive(Clone, PartialEq, Debug)]
+pub enum GameEvent {
+    /// A player ended up somewhere else, whether by walking or by being moved.
+    PlayerMoved { entity: Entity, x: f32, y: f32 },
+    /// Something walked onto a different tile.
+    TileEntered { entity: Entity, tile: (i32, i32), tile_type: TileType },
+    ItemPickedUp { entity: Entity, id: String, count: u32 },
+    /// Something was hurt by a hitbox, at the point between the two where they met.
+    Hit { entity: Entity, x: f32, y: f32 },
+    /// A player talked to the npc with the given name.
+    TalkedTo { entity: Entity, npc: String },
+    /// A player walked into the trigger zone with the given name.
+    ZoneEntered { entity: Entity, zone: String },
+    /// Something asked for a sound out of the ambient sound directory to be played.
+    SoundPlayed { sound: String },
+    /// A different map became the current map, found at `path`.
+    MapChanged { path: String },
+    /// Something asked for an effect over the whole screen.
+    ScreenEffect(ScreenEffect),
+}
+
+pub type GameEvents = EventChannel<GameEvent>;
+
+/// Where a listener outside of the world has read up to in its events.
+/// Every world has its own events, so listening has to start over whenever the world is replaced.
+#[derive(Default)]
+pub struct Subscription(Option<ReaderId<GameEvent>>);
+
+impl Subscription {
+    /// Starts listening to a world, only hearing about events from now on.
+    pub fn subscribe(&mut self, ecs: &World) {
+        self.0 = Some(ecs.fetch_mut::<GameEvents>().register_reader());
+    }
+
+    /// Every event since the last time, subscribing first if this has not yet.
+    pub fn read(&mut self, ecs: &World) -> Vec<GameEvent> {
+        if self.0.is_none() {
+            self.subscribe(ecs);
+        }
+        let reader = self.0.as_mut().expect("just subscribed");
+        ecs.fetch::<GameEvents>().read(reader).cloned().collect()
+    }
+}
+
+pub fn publish(ecs: &World, event: GameEvent) {
+    ecs.fetch_mut::<GameEvents>().single_write(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::map;
+    use crate::simulation;
+    use std::path::Path;
+
+    #[test]
+    fn changing_maps_is_published() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        let mut events = Subscription::default();
+        events.subscribe(&game.ecs);
+        game.step(1);
+        assert!(events.read(&game.ecs).is_empty());
+        simulation::load_map(&mut game.ecs, Path::new(map::BASIC_MAP_PATH));
+        assert_eq!(events.read(&game.ecs), vec![GameEvent::MapChanged { path: map::BASIC_MAP_PATH.to_string() }]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::map::{self, MapTile};
+
+/// Turns the one octant worked out by `cast_light`, running up and to the left of the origin,
+/// into each of the eight octants around it.
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1), (0, 1, 1, 0), (0, -1, 1, 0), (-1, 0, 0, 1),
+    (-1, 0, 0, -1), (0, -1, -1, 0), (0, 1, -1, 0), (1, 0, 0, -1),
+];
+
+/// Finds every tile which can be seen from the origin within a radius, using recursive shadowcasting.
+/// Tiles which block sight can be seen themselves, but nothing behind them can.
+pub fn field_of_view(map: &BTreeMap<(i32, i32), MapTile>, origin: (i32, i32), radius: i32) -> BTreeSet<(i32, i32)> {
+    let mut visible = BTreeSet::new();
+    visible.insert(origin);
+    for octant in OCTANTS.iter() {
+        let mut scan = Scan { map, visible: &mut visible, origin, radius, octant: *octant };
+        scan.cast_light(1, 1.0, 0.0);
+    }
+    visible
+}
+
+/// Everything about scanning an octant which stays the same as the scan splits around shadows.
+struct Scan<'a> {
+    map: &'a BTreeMap<(i32, i32), MapTile>,
+    visible: &'a mut BTreeSet<(i32, i32)>,
+    origin: (i32, i32),
+    radius: i32,
+    octant: (i32, i32, i32, i32),
+}
+
+impl Scan<'_> {
+    /// Scans the octant outwards row by row from `row`, between the slopes `start` and `end`.
+    /// Every run of tiles blocking sight splits the scan, with the rows beyond it scanned again
+    /// on either side of the shadow it casts.
+    fn cast_light(&mut self, row: i32, mut start: f32, end: f32) {
+        if start < end {
+            return;
+        }
+        let (origin, radius) = (self.origin, self.radius);
+        let (xx, xy, yx, yy) = self.octant;
+        let mut next_start = start;
+        for distance in row..=radius {
+            let dy = -distance;
+            let mut blocked = false;
+            for dx in -distance..=0 {
+                // The slopes of the left and right edges of the tile as seen from the origin
+                let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right_slope {
+                    continue;
+                }
+                if end > left_slope {
+                    break;
+                }
+                let tile = (origin.0 + dx * xx + dy * xy, origin.1 + dx * yx + dy * yy);
+                if dx * dx + dy * dy <= radius * radius {
+                    self.visible.insert(tile);
+                }
+                let blocks_sight = map::tile_type_at(self.map, tile.0, tile.1).blocks_sight();
+                if blocked {
+                    if blocks_sight {
+                        next_start = right_slope;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if blocks_sight && distance < radius {
+                    blocked = true;
+                    self.cast_light(distance + 1, start, left_slope);
+                    next_start = right_slope;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::map::{MapTile, TileType};
+
+    #[test]
+    fn walls_cast_shadows_over_what_is_behind_them() {
+        let mut map = Headless::floor(30, 30);
+        for y in 0..30 {
+            map.insert((10, y), MapTile { tile_id: 2, tile_type: TileType::Wall });
+        }
+        map.insert((8, 15), MapTile { tile_id: 2, tile_type: TileType::Wall });
+        let visible = field_of_view(&map, (5, 15), 8);
+        assert!(visible.contains(&(5, 15)));
+        assert!(visible.contains(&(8, 15)), "a wall can be seen itself");
+        assert!(!visible.contains(&(9, 15)), "the pillar should hide the tile behind it");
+        assert!(visible.contains(&(9, 13)));
+        assert!(visible.contains(&(10, 12)));
+        assert!(!visible.contains(&(11, 12)), "nothing past the wall should be seen");
+        // Sight reaches the same distance in every direction
+        assert!(visible.contains(&(5, 7)) && visible.contains(&(5, 23)) && visible.contains(&(0, 15)));
+        assert!(!visible.contains(&(5, 6)) && !visible.contains(&(5, 24)));
+        assert!(!visible.contains(&(0, 8)), "corners are out of reach");
+        // Everything between the player and the wall is in sight, apart from the shadow of the pillar
+        for x in 0..=10 {
+            for y in 13..=17 {
+                if y != 15 || x < 9 {
+                    assert!(visible.contains(&(x, y)), "{:?} should be visible", (x, y));
+                }
+            }
+        }
+    }
+}
@@ -1,82 +1,159 @@
-use crate::{GameState, Direction, Point2, Player, Position, Renderable, Viewport};
-use crate::map::{MapTile, TileSheet, TileType};
+use crate::{GameState, Direction, Point2, Item, Npc, Player, Position, Renderable, Viewport};
+use crate::{Attacking, Faction, Health, Hurtbox};
+use crate::map::{self, MapTile, TileSheet, TileType};
 use crate::{WIDTH_PX, HEIGHT_PX, TL_PX};
-use crate::viewport_system::ViewportSystem;
-use crate::movement_system::MovementSystem;
-use crate::animation_system::AnimationSystem;
+use crate::simulation::{self, MapPath, MapProperties, PendingDialogue, Ticks, ATTACK_TICKS, TICKS_PER_SECOND};
+use crate::script;
+use crate::viewport_system;
+use crate::visibility_system::{self, Explored, TileVisibility, Visibility};
+use crate::dialogue;
+use crate::inventory;
+use crate::quest_log;
+use crate::lighting;
+use crate::particles;
+use crate::screen_effects;
+use crate::pause::{self, Routed};
+use crate::settings;
+use crate::hud::{self, HudWidget};
+use crate::input::{Action, Input};
 use std::{collections::{BTreeMap}};
 use ggez::{graphics, Context, GameResult, event, timer, graphics::Rect};
-use ggez::event::KeyCode;
 use specs::prelude::*;
 
-const DESIRED_FPS: u32 = 60;
-const PLAYER_MOVE_SPEED_TPS: f32 = 1.0;
+/// Tint applied to npcs while they borrow the players sprite sheet.
+const NPC_TINT: [f32; 4] = [0.75, 0.85, 1.0, 1.0];
+const ENEMY_TINT: [f32; 4] = [1.0, 0.45, 0.45, 1.0];
+/// Tiles the player has explored but cannot see right now, on maps covered in fog.
+const REMEMBERED_TINT: [f32; 4] = [0.35, 0.35, 0.45, 1.0];
+/// The frames of the walking animation used for each part of a swing.
+const ATTACK_FRAMES: [u8; 3] = [1, 3, 0];
+const ATTACK_LUNGE_PX: f32 = 6.0;
+const SLASH_RADIUS_PX: f32 = 26.0;
 
-pub fn in_game_input(state: &mut GameState, ctx: &mut Context, keycode: KeyCode) {
-    // Here we attempt to convert the Keycode into a Direction using the helper
-    // we defined earlier.
-    if let Some(dir) = Direction::from_keycode(keycode) {
-        try_move_player(dir, &state.ecs);
+pub fn in_game_input(state: &mut GameState, ctx: &mut Context, input: Input) {
+    // While a replay is driving the game the only thing we listen to is quitting.
+    if state.replay.is_some() {
+        if input == Input::Action(Action::Pause) {
+            event::quit(ctx);
+        }
+        return;
     }
 
-    match keycode {
-        KeyCode::Escape => event::quit(ctx),
-        KeyCode::Key0 => state.show_fps = !state.show_fps,
-        _ => (), // Do nothing
+    // Pausing freezes the world without changing it, so it is not recorded
+    if pause::route_input(&mut state.states, &mut state.pause_menu, &state.ecs, input) != Routed::Played {
+        return;
+    }
+
+    if let Some(recorder) = &mut state.recorder {
+        recorder.record(state.ecs.fetch::<Ticks>().0, input);
     }
+    follow_input(state, ctx, input);
 }
 
-pub fn in_game_update(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
-    if timer::check_update_time(ctx, DESIRED_FPS) {
-        let _seconds = 1.0 / (DESIRED_FPS as f32);
+/// Everything an input does outside of the world, once the world has been given it.
+fn follow_input(state: &mut GameState, ctx: &mut Context, input: Input) {
+    match input {
+        Input::Action(Action::Interact) => {
+            // Conversations have no effect on the world yet, so replays skip straight past them
+            if let Some(id) = simulation::interact(&state.ecs) {
+                if state.replay.is_none() {
+                    dialogue::open_dialogue(state, &id);
+                }
+            }
+        }
+        // Screens which pause the world are skipped by replays, only their inputs matter
+        Input::Action(Action::Inventory) if state.replay.is_none() => inventory::open_inventory(state),
+        Input::Action(Action::QuestLog) if state.replay.is_none() => quest_log::open_quest_log(state),
+        Input::Action(Action::QuickSave) if state.replay.is_none() => {
+            let message = match state.save_game() {
+                Ok(()) => format!("Saved to slot {}", state.save_slot + 1),
+                Err(e) => {
+                    println!("Could not save the game: {}", e);
+                    "Could not save the game".to_string()
+                }
+            };
+            state.hud.toasts.push(message);
+        }
+        // Only replays get this far with a pause, which is recorded when the player quits to the title
+        Input::Action(Action::Pause) => event::quit(ctx),
+        Input::Action(Action::ToggleFps) => settings::toggle_hud_widget(state, HudWidget::Fps),
+        Input::Action(Action::ToggleMinimap) => settings::toggle_hud_widget(state, HudWidget::Minimap),
+        _ => (), // Do nothing
+    }
+}
 
-        let mut viewport_system = ViewportSystem{};
-        let mut movement_system = MovementSystem{};
-        viewport_system.run_now(&state.ecs);
-        movement_system.run_now(&state.ecs);
+/// Picks the map tile under a point on the screen, using what the player can currently see.
+pub fn pick_tile(state: &GameState, screen_x: f32, screen_y: f32) -> Option<(i32, i32)> {
+    let players = state.ecs.read_storage::<Player>();
+    let viewports = state.ecs.read_storage::<Viewport>();
+    let (_player, viewport) = (&players, &viewports).join().next()?;
+    viewport_system::screen_to_tile(viewport, screen_x, screen_y)
+}
 
-        // Something about rebalancing the new / old entities, not exactly sure
-        state.ecs.maintain();
+pub fn in_game_update(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    // Scripts are picked up again as they are edited, which only matters while working on the game
+    if cfg!(debug_assertions) {
+        script::hot_reload(&state.ecs);
     }
 
-    if timer::check_update_time(ctx, 8) {
-        let mut animation_system = AnimationSystem{};
-        animation_system.run_now(&state.ecs);
-    }
+    // The simulation only ever advances in fixed steps, running as many
+    // ticks as needed to catch up with the time that has passed.
+    while timer::check_update_time(ctx, TICKS_PER_SECOND) {
+        // Replayed inputs are applied right before the tick they were recorded on,
+        // which is exactly when live input would have been handled.
+        let tick = state.ecs.fetch::<Ticks>().0;
+        let due = match &mut state.replay {
+            Some(replay) => replay.pop_due(tick),
+            None => vec![],
+        };
+        for input in due {
+            simulation::apply_input(input, &state.ecs);
+            follow_input(state, ctx, input);
+        }
+        simulation::tick(&mut state.ecs);
+        hud::hud_tick(state);
+        state.particles.tick(&state.ecs);
+        state.screen_effects.tick(&state.ecs);
 
-    Ok(())
+        // Like talking to npcs, conversations started by scripts are skipped by replays
+        let dialogue = state.ecs.fetch_mut::<PendingDialogue>().0.take();
+        if let Some(id) = dialogue {
+            if state.replay.is_none() {
+                dialogue::open_dialogue(state, &id);
+                break;
+            }
+        }
+    }
+    hud::hud_update(state, ctx)
 }
 
+/// Draws everything in game. Overlays like the inventory draw over the top of it afterwards.
 pub fn in_game_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
-    graphics::clear(ctx, [0.6, 0.6, 0.6, 1.0].into());
-    render_tiles(ctx, &state)?;
-    render_player(ctx, &state)?;
-    if state.show_fps {
-        render_fps(ctx)?;
-    }
-    // Finally we call graphics::present to cycle the gpu's framebuffer and display
-    // the new frame we just drew and then yield the thread until the next update.
-    graphics::present(ctx)?;
-    ggez::timer::yield_now();
-    Ok(())
+    render_world(ctx, state)
 }
 
-fn try_move_player(direction: Direction, ecs: &World) {
-    let delta = match direction {
-        Direction::Up => (0.0, -1.0 * (PLAYER_MOVE_SPEED_TPS * TL_PX as f32)),
-        Direction::Left => (-1.0 * (PLAYER_MOVE_SPEED_TPS * TL_PX as f32), 0.0),
-        Direction::Down => (0.0, PLAYER_MOVE_SPEED_TPS * TL_PX as f32),
-        Direction::Right => (PLAYER_MOVE_SPEED_TPS * TL_PX as f32, 0.0),
+#[allow(clippy::needless_borrow)]
+fn render_world(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    // Anything not yet explored on a map covered in fog is left black
+    let background = if visibility_system::has_fog(&state.ecs.fetch::<MapProperties>()) {
+        [0.0, 0.0, 0.0, 1.0]
+    } else {
+        [0.6, 0.6, 0.6, 1.0]
     };
-    let mut positions = ecs.write_storage::<Position>();
-    let mut players = ecs.write_storage::<Player>();
-    let mut viewports = ecs.write_storage::<Viewport>();
-    for (player, _position, viewport) in (&mut players, &mut positions, &mut viewports).join() {
-        player.direction = direction;
-        player.velocity.x += delta.0;
-        player.velocity.y += delta.1;
-        viewport.dirty = true;
-    }
+    graphics::clear(ctx, background.into());
+    // Shaking moves the world around on screen, while the lighting and hud stay put over it
+    screen_effects::begin_shake(ctx, &state.screen_effects)?;
+    render_tiles(ctx, &state)?;
+    render_items(ctx, state)?;
+    render_npcs(ctx, state)?;
+    render_player(ctx, &state)?;
+    particles::render_particles(ctx, state)?;
+    screen_effects::end_shake(ctx)?;
+    lighting::render_lighting(ctx, state)?;
+    screen_effects::render_grade(ctx, state)?;
+    hud::render_hud(ctx, state)?;
+    screen_effects::render_cover(ctx, state)?;
+    Ok(())
 }
 
 /// Renders any map tiles visible to the players viewport to the screen
@@ -91,8 +168,19 @@ fn render_tiles(ctx: &mut Context, state: &GameState) -> GameResult<()> {
     // Render each tile using the given pixel positions
     let map = state.ecs.fetch::<BTreeMap<(i32, i32), MapTile>>();
     let tilesheet = state.ecs.fetch::<TileSheet>();
+    let fog = visibility_system::has_fog(&state.ecs.fetch::<MapProperties>());
+    let visibility = state.ecs.fetch::<Visibility>();
+    let explored = state.ecs.fetch::<Explored>();
+    let explored = explored.0.get(&state.ecs.fetch::<MapPath>().0);
 
     for (tile_x, tile_y, _view_x, _view_y, screen_x, screen_y) in viewport.tiles.iter() {
+        // Tiles seen before but out of sight now are remembered darker, those never seen are not drawn at all
+        let visible = if fog { visibility.of((*tile_x, *tile_y), explored) } else { TileVisibility::Visible };
+        let color = match visible {
+            TileVisibility::Visible => graphics::WHITE,
+            TileVisibility::Explored => REMEMBERED_TINT.into(),
+            TileVisibility::Hidden => continue,
+        };
 
         // Retrieve the appropriate tile type from the map using the tile coordinates
         let map_tile = match map.get(&(*tile_x, *tile_y)) {
@@ -135,7 +223,8 @@ fn render_tiles(ctx: &mut Context, state: &GameState) -> GameResult<()> {
         let drawparams = graphics::DrawParam::new()
             .src(Rect::new(tile_rectangle[0], tile_rectangle[1], tile_rectangle[2], tile_rectangle[3]))
             .dest(Point2::new(*screen_x as f32, *screen_y as f32))
-            .offset(Point2::new(0.5, 0.5));
+            .offset(Point2::new(0.5, 0.5))
+            .color(color);
         graphics::draw(ctx, &state.tilesheet, drawparams)?;
     }
     Ok(())
@@ -149,46 +238,156 @@ fn render_player(ctx: &mut Context, state: &GameState) -> GameResult<()> {
     let positions = state.ecs.read_storage::<Position>();
     let renderables = state.ecs.read_storage::<Renderable>();
     let players = state.ecs.read_storage::<Player>();
-    for (_pos, _render, player) in (&positions, &renderables, &players).join() {
-
-        // TODO: Create subrectangle referencing the part of the sprite sheet containing the desired sprite to render
-        let horizontal_index = player.animation_index;
-        let vertical_index = match player.direction {
-            Direction::Down => 0,
-            Direction::Left => 1,
-            Direction::Right => 2,
-            Direction::Up => 3
+    let attacks = state.ecs.read_storage::<Attacking>();
+    let healths = state.ecs.read_storage::<Health>();
+    for (_pos, _render, player, attack, health) in (&positions, &renderables, &players, attacks.maybe(), healths.maybe()).join() {
+        let center = Point2::new((WIDTH_PX / 2) as f32, (HEIGHT_PX / 2) as f32);
+        let (dx, dy) = player.direction.offset();
+
+        // Swinging plays the stepping frames of the row the player is facing, lunging forward as it goes
+        let (animation_index, dest) = match attack {
+            Some(attack) => {
+                let progress = 1.0 - attack.ticks as f32 / ATTACK_TICKS as f32;
+                let frame = ATTACK_FRAMES[((progress * ATTACK_FRAMES.len() as f32) as usize).min(ATTACK_FRAMES.len() - 1)];
+                let lunge = (progress * std::f32::consts::PI).sin() * ATTACK_LUNGE_PX;
+                (frame, Point2::new(center.x + dx as f32 * lunge, center.y + dy as f32 * lunge))
+            }
+            None => (player.animation_index, center),
         };
-        let desired_sprite_subrectangle = [
-            horizontal_index as f32 / 10 as f32,
-            vertical_index as f32 / 5 as f32,
-            0.1,
-            0.2,
-        ];
         let drawparams = graphics::DrawParam::new()
-            .src(Rect::new(desired_sprite_subrectangle[0], desired_sprite_subrectangle[1], desired_sprite_subrectangle[2], desired_sprite_subrectangle[3]))
-            .dest(Point2::new((WIDTH_PX / 2) as f32, (HEIGHT_PX / 2) as f32))
-            .offset(Point2::new(0.5, 0.5));
+            .src(sprite_rect(player.direction, animation_index))
+            .dest(dest)
+            .offset(Point2::new(0.5, 0.5))
+            .color(flicker(health).into());
         graphics::draw(ctx,
             &state.player_sprite_sheet,
             drawparams)?;
+
+        if let Some(attack) = attack {
+            render_slash(ctx, center, player.direction, attack)?;
+        }
     }
     Ok(())
 }
 
-/// Unobtrusively renders the rolling average frames per second.
-fn render_fps(ctx: &mut Context) -> GameResult<()> {
-    let fps = timer::fps(ctx);
-    let fps_text = graphics::Text::new(format!("FPS: {:.2}", fps));
-    graphics::draw(ctx, &fps_text, (Point2::new(0.0, 0.0), graphics::BLACK))?;
+/// Draws the arc of a swing in front of whoever is swinging, sweeping across during the swing.
+fn render_slash(ctx: &mut Context, center: Point2, direction: Direction, attack: &Attacking) -> GameResult<()> {
+    let progress = 1.0 - attack.ticks as f32 / ATTACK_TICKS as f32;
+    if progress > 0.6 {
+        return Ok(());
+    }
+    let facing = match direction {
+        Direction::Right => 0.0,
+        Direction::Down => 0.5,
+        Direction::Left => 1.0,
+        Direction::Up => 1.5,
+    } * std::f32::consts::PI;
+    // The arc grows from one side of the player to the other, a third of a turn in all
+    let sweep = 2.0 * std::f32::consts::FRAC_PI_3 * (progress / 0.6).max(0.1);
+    let points: Vec<Point2> = (0..=8)
+        .map(|step| facing - std::f32::consts::FRAC_PI_3 + sweep * step as f32 / 8.0)
+        .map(|angle| Point2::new(center.x + angle.cos() * SLASH_RADIUS_PX, center.y + angle.sin() * SLASH_RADIUS_PX))
+        .collect();
+    let slash = graphics::Mesh::new_polyline(ctx, graphics::DrawMode::stroke(3.0), &points, [1.0, 1.0, 1.0, 0.8].into())?;
+    graphics::draw(ctx, &slash, graphics::DrawParam::default())
+}
+
+/// Anything that was just hurt blinks until it can be hurt again.
+fn flicker(health: Option<&Health>) -> [f32; 4] {
+    match health {
+        Some(health) if health.iframes > 0 && (health.iframes / 4) % 2 == 0 => [1.0, 1.0, 1.0, 0.3],
+        _ => [1.0, 1.0, 1.0, 1.0],
+    }
+}
+
+/// Renders items lying around in the world, underneath everything that walks.
+fn render_items(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let positions = state.ecs.read_storage::<Position>();
+    let renderables = state.ecs.read_storage::<Renderable>();
+    let players = state.ecs.read_storage::<Player>();
+    let items = state.ecs.read_storage::<Item>();
+    let player_position = match (&positions, &players).join().next() {
+        Some((position, _player)) => (position.x, position.y),
+        None => return Ok(()),
+    };
 
-    // let delta = timer::delta(ctx);
-    // let delta_text = graphics::Text::new(format!("Delta: {:.2}ms", delta.as_millis()));
-    // graphics::draw(ctx, &delta_text, (Point2::new(0.0, 12.0), graphics::BLACK))?;
+    for (position, _render, item) in (&positions, &renderables, &items).join() {
+        if !in_sight(state, position) {
+            continue;
+        }
+        let sprite = match state.item_sprites.get(&item.id) {
+            Some(sprite) => sprite,
+            None => continue,
+        };
+        if let Some(dest) = to_screen(position, player_position) {
+            let drawparams = graphics::DrawParam::new()
+                .dest(dest)
+                .offset(Point2::new(0.5, 0.5));
+            graphics::draw(ctx, sprite, drawparams)?;
+        }
+    }
+    Ok(())
+}
 
-    // let ticks = timer::ticks(ctx);
-    // let ticks_text = graphics::Text::new(format!("Ticks: {}", ticks));
-    // graphics::draw(ctx, &ticks_text, (Point2::new(0.0, 24.0), graphics::BLACK))?;
+/// Renders npcs relative to the player, who is always in the middle of the screen.
+/// Npcs share the players sprite sheet for now, tinted so they can be told apart.
+fn render_npcs(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let positions = state.ecs.read_storage::<Position>();
+    let renderables = state.ecs.read_storage::<Renderable>();
+    let players = state.ecs.read_storage::<Player>();
+    let npcs = state.ecs.read_storage::<Npc>();
+    let hurtboxes = state.ecs.read_storage::<Hurtbox>();
+    let healths = state.ecs.read_storage::<Health>();
+    let player_position = match (&positions, &players).join().next() {
+        Some((position, _player)) => (position.x, position.y),
+        None => return Ok(()),
+    };
 
+    for (position, _render, npc, hurtbox, health) in (&positions, &renderables, &npcs, hurtboxes.maybe(), healths.maybe()).join() {
+        if !in_sight(state, position) {
+            continue;
+        }
+        if let Some(dest) = to_screen(position, player_position) {
+            let tint = match hurtbox {
+                Some(hurtbox) if hurtbox.faction == Faction::Hostile => ENEMY_TINT,
+                _ => NPC_TINT,
+            };
+            let alpha = flicker(health)[3];
+            let drawparams = graphics::DrawParam::new()
+                .src(sprite_rect(npc.direction, npc.animation_index))
+                .dest(dest)
+                .offset(Point2::new(0.5, 0.5))
+                .color([tint[0], tint[1], tint[2], alpha].into());
+            graphics::draw(ctx,
+                &state.player_sprite_sheet,
+                drawparams)?;
+        }
+    }
     Ok(())
 }
+
+/// Whether something is on a tile the player can see, which on maps without fog is everywhere.
+fn in_sight(state: &GameState, position: &Position) -> bool {
+    let tile = (map::to_tl(position.x), map::to_tl(position.y));
+    visibility_system::tile_visibility(&state.ecs, tile) == TileVisibility::Visible
+}
+
+/// Works out where something in the world appears on screen, given that the player
+/// is always drawn in the middle. Anything entirely off screen has no position.
+fn to_screen(position: &Position, player_position: (f32, f32)) -> Option<Point2> {
+    viewport_system::world_to_screen(position.x, position.y, player_position, TL_PX as f32)
+}
+
+/// Picks the frame out of the sprite sheet for a character facing a direction.
+/// Each row of the sheet is one direction, with the walking animation running along it.
+#[allow(clippy::unnecessary_cast)]
+fn sprite_rect(direction: Direction, animation_index: u8) -> Rect {
+    let horizontal_index = animation_index;
+    let vertical_index = match direction {
+        Direction::Down => 0,
+        Direction::Left => 1,
+        Direction::Right => 2,
+        Direction::Up => 3
+    };
+    Rect::new(horizontal_index as f32 / 10 as f32, vertical_index as f32 / 5 as f32, 0.1, 0.2)
+}
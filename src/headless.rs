@@ -0,0 +1,273 @@
+//! Drives the simulation without a window, graphics context or real input,
+//! so that gameplay can be exercised by `cargo test` on machines without a GPU.
+
+use crate::component::*;
+use crate::map::{self, MapObject, MapTile, TileType};
+use crate::simulation::{self, Notifications, Ticks};
+use crate::replay::Replay;
+use crate::spawner;
+use crate::save;
+use crate::item::{self, ItemDefs};
+use crate::quest::QuestDefs;
+use crate::input::{Action, Input};
+use crate::script::Scripts;
+use crate::particles::{self, ParticleDefs, Particles};
+use crate::states::{RootState, States};
+use crate::pause;
+use crate::widget::Menu;
+use crate::{Direction, TL_PX};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use specs::prelude::*;
+
+pub struct Headless {
+    pub ecs: World,
+    /// Starts out in game, and like the real game only advances the simulation while nothing is over it.
+    pub states: States,
+    pause_menu: Menu,
+    /// Directories written for the test, which are removed again along with the game.
+    scratch_dirs: Vec<PathBuf>,
+}
+
+impl Drop for Headless {
+    fn drop(&mut self) {
+        for dir in self.scratch_dirs.iter() {
+            // Whatever is left behind is only clutter in the temp directory, so it is not worth failing over
+            if let Err(e) = fs::remove_dir_all(dir) {
+                println!("Could not clean up {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+impl Headless {
+    /// Creates a world using the given map with the player standing at the origin.
+    pub fn new(map: BTreeMap<(i32, i32), MapTile>) -> Headless {
+        let mut ecs = simulation::create_world();
+        ecs.insert(map);
+        Headless::from_world(ecs)
+    }
+
+    /// Creates a world using a tiled map loaded from disk, along with the items it places.
+    pub fn with_map_file(path: &Path) -> Headless {
+        let mut ecs = simulation::create_world();
+        ecs.insert(ItemDefs::load(Path::new(item::ITEM_DEFS_PATH)).expect("could not load item definitions"));
+        simulation::load_map(&mut ecs, path);
+        Headless::from_world(ecs)
+    }
+
+    /// Plays in a world which has already been set up, such as one loaded from a save.
+    pub fn from_world(ecs: World) -> Headless {
+        Headless { ecs, states: States::new(RootState::InGame), pause_menu: pause::pause_menu(), scratch_dirs: vec![] }
+    }
+
+    /// Builds a rectangle of floor tiles spanning from the origin to the given size.
+    pub fn floor(width: i32, height: i32) -> BTreeMap<(i32, i32), MapTile> {
+        let mut map = BTreeMap::new();
+        for x in 0..width {
+            for y in 0..height {
+                map.insert((x, y), MapTile { tile_id: 1, tile_type: TileType::Floor });
+            }
+        }
+        map
+    }
+
+    pub fn press(&mut self, direction: Direction) {
+        simulation::try_move_player(direction, &self.ecs);
+    }
+
+    /// Applies an input exactly as if it had come from the keyboard or a gamepad, pausing included.
+    pub fn input(&mut self, input: Input) {
+        pause::route_input(&mut self.states, &mut self.pause_menu, &self.ecs, input);
+    }
+
+    /// Loads the item definitions that ship with the game.
+    pub fn with_items(mut self) -> Headless {
+        let defs = ItemDefs::load(Path::new(item::ITEM_DEFS_PATH)).expect("could not load item definitions");
+        self.ecs.insert(defs);
+        self
+    }
+
+    /// Counts how many of an item the player is carrying.
+    pub fn carried(&self, id: &str) -> u32 {
+        let players = self.ecs.read_storage::<Player>();
+        let inventories = self.ecs.read_storage::<Inventory>();
+        let (_player, inventory) = (&players, &inventories).join().next().expect("No player inventory found");
+        inventory.count(id)
+    }
+
+    /// Counts how many of an item are lying around in the world.
+    pub fn lying_around(&self, id: &str) -> u32 {
+        let items = self.ecs.read_storage::<Item>();
+        (&items).join().filter(|item| item.id == id).map(|item| item.count).sum()
+    }
+
+    pub fn player_health(&self) -> i32 {
+        let players = self.ecs.read_storage::<Player>();
+        let healths = self.ecs.read_storage::<Health>();
+        let (_player, health) = (&players, &healths).join().next().expect("No player health found");
+        health.current
+    }
+
+    /// The health of an npc, or nothing once it has died.
+    pub fn npc_health(&self, name: &str) -> Option<i32> {
+        let npcs = self.ecs.read_storage::<Npc>();
+        let healths = self.ecs.read_storage::<Health>();
+        (&npcs, &healths).join()
+            .find(|(npc, _health)| npc.name == name)
+            .map(|(_npc, health)| health.current)
+    }
+
+    pub fn npc_position(&self, name: &str) -> (f32, f32) {
+        let npcs = self.ecs.read_storage::<Npc>();
+        let positions = self.ecs.read_storage::<Position>();
+        let (_npc, position) = (&npcs, &positions).join()
+            .find(|(npc, _position)| npc.name == name)
+            .expect("No npc with that name found");
+        (position.x, position.y)
+    }
+
+    /// Takes every message the world has for the player since last time.
+    pub fn notifications(&mut self) -> Vec<String> {
+        self.ecs.fetch_mut::<Notifications>().drain()
+    }
+
+    /// Talks to whatever the player is facing, returning the id of its dialogue.
+    pub fn interact(&mut self) -> Option<String> {
+        simulation::interact(&self.ecs)
+    }
+
+    pub fn step(&mut self, ticks: u32) {
+        if !self.states.simulating() {
+            return;
+        }
+        for _ in 0..ticks {
+            simulation::tick(&mut self.ecs);
+        }
+    }
+
+    /// Feeds a replay through the simulation until it quits or runs out of inputs.
+    /// Inputs are applied right before the tick they were recorded on, just like the real game.
+    pub fn play(&mut self, replay: &mut Replay) {
+        loop {
+            let tick = self.ecs.fetch::<Ticks>().0;
+            for input in replay.pop_due(tick) {
+                if input == Input::Action(Action::Pause) {
+                    return;
+                }
+                self.input(input);
+            }
+            if replay.is_finished() {
+                return;
+            }
+            self.step(1);
+        }
+    }
+
+    pub fn player_position(&self) -> (f32, f32) {
+        let players = self.ecs.read_storage::<Player>();
+        let positions = self.ecs.read_storage::<Position>();
+        let (_player, position) = (&players, &positions).join().next().expect("No player found");
+        (position.x, position.y)
+    }
+
+    pub fn player_tile(&self) -> (i32, i32) {
+        let (x, y) = self.player_position();
+        (map::to_tl(x), map::to_tl(y))
+    }
+
+    /// Spawns objects exactly as if they had been placed on the map in tiled.
+    pub fn spawn(&mut self, objects: &[MapObject]) {
+        self.ecs.fetch_mut::<simulation::MapObjects>().0.extend_from_slice(objects);
+        spawner::spawn_map_objects(&mut self.ecs, objects);
+    }
+
+    /// Saves the world and loads it back into a fresh one, like quitting and continuing would.
+    pub fn reload(&mut self) {
+        let saved = save::save_to_string(&mut self.ecs).expect("could not save");
+        let defs = (*self.ecs.fetch::<ItemDefs>()).clone();
+        let quest_defs = (*self.ecs.fetch::<QuestDefs>()).clone();
+        self.ecs = save::load_from_str(&saved).expect("could not load");
+        self.ecs.insert(defs);
+        self.ecs.insert(quest_defs);
+    }
+
+    pub fn npc_tile(&self, name: &str) -> (i32, i32) {
+        let npcs = self.ecs.read_storage::<Npc>();
+        let positions = self.ecs.read_storage::<Position>();
+        let (_npc, position) = (&npcs, &positions).join()
+            .find(|(npc, _position)| npc.name == name)
+            .expect("No npc with that name found");
+        (map::to_tl(position.x), map::to_tl(position.y))
+    }
+}
+
+/// Builds an object standing on a tile, the way the map loader would.
+pub fn object(name: &str, kind: &str, tile: (i32, i32), properties: &[(&str, &str)]) -> MapObject {
+    MapObject {
+        name: name.to_string(),
+        kind: kind.to_string(),
+        x: map::to_px(tile.0) as f32,
+        y: map::to_px(tile.1) as f32,
+        width: 0.0,
+        height: 0.0,
+        points: vec![],
+        closed: false,
+        properties: properties.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>(),
+    }
+}
+
+/// Spawns an enemy which cannot see the player until they are right next to each other.
+pub fn enemy(name: &str, tile: (i32, i32), health: &str) -> MapObject {
+    object(name, "enemy", tile, &[("health", health), ("sight", "0"), ("damage", "1")])
+}
+
+/// Builds a trigger zone covering a rectangle of tiles, the way the map loader would.
+pub fn trigger(name: &str, first: (i32, i32), size: (i32, i32), properties: &[(&str, &str)]) -> MapObject {
+    let half_tile = TL_PX as f32 / 2.0;
+    MapObject {
+        x: map::to_px(first.0) as f32 - half_tile,
+        y: map::to_px(first.1) as f32 - half_tile,
+        width: map::to_px(size.0) as f32,
+        height: map::to_px(size.1) as f32,
+        ..object(name, "trigger", first, properties)
+    }
+}
+
+/// Writes scripts into a directory of their own and has the world load scripts from there.
+/// The directory lasts as long as the game, since scripts are loaded as they are needed.
+pub fn with_scripts(game: &mut Headless, test: &str, scripts: &[(&str, &str)]) -> PathBuf {
+    let dir = scratch_dir(game, &format!("tiny-topdown-game-{}", test));
+    for (id, source) in scripts {
+        fs::write(dir.join(format!("{}.rhai", id)), source).unwrap();
+    }
+    game.ecs.insert(Scripts::new(&dir));
+    dir
+}
+
+/// Writes quests into a directory of their own for a test and loads them into the world.
+pub fn with_quests(game: &mut Headless, test: &str, quests: &[(&str, &str)]) {
+    let dir = scratch_dir(game, &format!("tiny-topdown-game-quests-{}", test));
+    for (id, source) in quests {
+        fs::write(dir.join(format!("{}.ron", id)), source).unwrap();
+    }
+    game.ecs.insert(QuestDefs::load(&dir).expect("could not load quests"));
+}
+
+/// Creates an empty directory in the temp directory, which is removed again once the game is dropped.
+fn scratch_dir(game: &mut Headless, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    // Start from nothing, in case an earlier run was stopped before it could clean up
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    game.scratch_dirs.push(dir.clone());
+    dir
+}
+
+/// Particles made from the emitters that ship with the game.
+pub fn shipped_particles() -> Particles {
+    Particles::new(ParticleDefs::load(Path::new(particles::PARTICLE_DEFS_PATH)).expect("could not load particles"))
+}
@@ -0,0 +1,396 @@
+use crate::{GameState, Point2};
+use crate::component::{Health, Inventory, Player, Position};
+use crate::map::{self, MapTile, TileType};
+use crate::events::{GameEvent, Subscription};
+use crate::simulation::{MapProperties, Notifications};
+use crate::visibility_system;
+use ggez::{graphics, Context, GameResult, timer};
+use specs::prelude::*;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+const MARGIN_PX: f32 = 12.0;
+const HEART_SCALE: f32 = 2.0;
+const HEART_SPACING_PX: f32 = 34.0;
+/// Each heart holds this much health, so hearts can be half full.
+const HEALTH_PER_HEART: i32 = 2;
+const SLOT_SIZE_PX: f32 = 48.0;
+const MINIMAP_PX_PER_TILE: f32 = 1.5;
+const MINIMAP_DOT_PX: f32 = 4.0;
+const TOAST_FONT_SIZE: f32 = 20.0;
+const TOAST_SPACING_PX: f32 = 30.0;
+/// How long a toast stays up, and how much of that it spends fading out, in ticks.
+const TOAST_TICKS: u32 = 180;
+const TOAST_FADE_TICKS: u32 = 30;
+const MAX_TOASTS: usize = 4;
+
+/// Each part of the hud, all of which can be shown or hidden on their own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HudWidget {
+    Health,
+    SelectedItem,
+    Toasts,
+    Minimap,
+    Fps,
+}
+
+#[derive(Debug)]
+pub struct Toast {
+    pub message: String,
+    pub ticks: u32,
+}
+
+/// Short messages that pop up and disappear on their own.
+/// Only the newest few are kept, older ones make room for them.
+#[derive(Default, Debug)]
+pub struct Toasts {
+    toasts: VecDeque<Toast>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, message: String) {
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast { message, ticks: TOAST_TICKS });
+    }
+
+    pub fn tick(&mut self) {
+        for toast in self.toasts.iter_mut() {
+            toast.ticks = toast.ticks.saturating_sub(1);
+        }
+        self.toasts.retain(|toast| toast.ticks > 0);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Toast> {
+        self.toasts.iter()
+    }
+}
+
+/// The minimap as an image, along with the tile in its top left corner.
+struct Minimap {
+    image: graphics::Image,
+    origin: (i32, i32),
+}
+
+/// Everything drawn over the top of the world while playing.
+pub struct Hud {
+    hidden: HashSet<HudWidget>,
+    /// The item shown in the selected item slot, picked in the inventory screen.
+    pub selected_item: Option<String>,
+    pub toasts: Toasts,
+    minimap: Option<Minimap>,
+    events: Subscription,
+    heart_full: graphics::Image,
+    heart_half: graphics::Image,
+    heart_empty: graphics::Image,
+}
+
+impl Hud {
+    pub fn new(ctx: &mut Context) -> GameResult<Hud> {
+        let mut heart_full = graphics::Image::new(ctx, "/hud/heart_full.png")?;
+        let mut heart_half = graphics::Image::new(ctx, "/hud/heart_half.png")?;
+        let mut heart_empty = graphics::Image::new(ctx, "/hud/heart_empty.png")?;
+        // Pixel art has to stay crisp when scaled up
+        for heart in [&mut heart_full, &mut heart_half, &mut heart_empty].iter_mut() {
+            heart.set_filter(graphics::FilterMode::Nearest);
+        }
+        Ok(Hud {
+            hidden: HashSet::new(),
+            selected_item: None,
+            toasts: Toasts::default(),
+            minimap: None,
+            events: Subscription::default(),
+            heart_full,
+            heart_half,
+            heart_empty,
+        })
+    }
+
+    /// Starts listening for changes to a different world.
+    pub fn listen(&mut self, ecs: &World) {
+        self.events.subscribe(ecs);
+    }
+
+    pub fn is_shown(&self, widget: HudWidget) -> bool {
+        !self.hidden.contains(&widget)
+    }
+
+    pub fn set_shown(&mut self, widget: HudWidget, shown: bool) {
+        if shown {
+            self.hidden.remove(&widget);
+        } else {
+            self.hidden.insert(widget);
+        }
+    }
+
+    pub fn toggle(&mut self, widget: HudWidget) {
+        if !self.hidden.remove(&widget) {
+            self.hidden.insert(widget);
+        }
+    }
+
+    /// Redraws the minimap, which has to happen whenever a different map is loaded.
+    pub fn refresh_minimap(&mut self, ctx: &mut Context, map: &BTreeMap<(i32, i32), MapTile>) -> GameResult<()> {
+        self.minimap = match minimap_pixels(map) {
+            Some(minimap) => {
+                let mut image = graphics::Image::from_rgba8(ctx, minimap.width, minimap.height, &minimap.pixels)?;
+                image.set_filter(graphics::FilterMode::Nearest);
+                Some(Minimap { image, origin: minimap.origin })
+            }
+            None => None,
+        };
+        Ok(())
+    }
+}
+
+/// The map coloured in one pixel per tile, ready to be turned into an image.
+#[derive(Debug)]
+pub struct MinimapPixels {
+    pub width: u16,
+    pub height: u16,
+    /// The tile in the top left corner.
+    pub origin: (i32, i32),
+    /// Rgba pixels, row by row.
+    pub pixels: Vec<u8>,
+}
+
+/// Colours each tile of the map by its type, or returns nothing for an empty map.
+pub fn minimap_pixels(map: &BTreeMap<(i32, i32), MapTile>) -> Option<MinimapPixels> {
+    let min_x = map.keys().map(|(x, _y)| *x).min()?;
+    let max_x = map.keys().map(|(x, _y)| *x).max()?;
+    let min_y = map.keys().map(|(_x, y)| *y).min()?;
+    let max_y = map.keys().map(|(_x, y)| *y).max()?;
+    let width = (max_x - min_x + 1) as u16;
+    let height = (max_y - min_y + 1) as u16;
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let color = match map::tile_type_at(map, x, y) {
+                TileType::Floor => [96, 160, 72, 255],
+                TileType::Wall => [48, 40, 36, 255],
+                TileType::Missing => [0, 0, 0, 0],
+            };
+            pixels.extend_from_slice(&color);
+        }
+    }
+    Some(MinimapPixels { width, height, origin: (min_x, min_y), pixels })
+}
+
+/// Turns everything that happened in the world since last time into toasts, and ages the old ones.
+/// Runs once per tick, so toasts stay up for the same time no matter the frame rate.
+pub fn hud_tick(state: &mut GameState) {
+    for message in state.ecs.fetch_mut::<Notifications>().drain() {
+        state.hud.toasts.push(message);
+    }
+    state.hud.toasts.tick();
+}
+
+/// Catches up with anything in the world that changes what the hud shows, like moving to another map.
+pub fn hud_update(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    for event in state.hud.events.read(&state.ecs) {
+        if let GameEvent::MapChanged { .. } = event {
+            state.hud.refresh_minimap(ctx, &state.ecs.fetch())?;
+        }
+    }
+    Ok(())
+}
+
+/// Draws every widget which is shown, anchored to the edges of the screen.
+pub fn render_hud(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let screen = graphics::screen_coordinates(ctx);
+    let hud = &state.hud;
+    if hud.is_shown(HudWidget::Health) {
+        render_hearts(ctx, state, screen)?;
+    }
+    if hud.is_shown(HudWidget::SelectedItem) {
+        render_selected_item(ctx, state, screen)?;
+    }
+    // The minimap would give away the whole of a map covered in fog
+    if hud.is_shown(HudWidget::Minimap) && !visibility_system::has_fog(&state.ecs.fetch::<MapProperties>()) {
+        render_minimap(ctx, state, screen)?;
+    }
+    if hud.is_shown(HudWidget::Toasts) {
+        render_toasts(ctx, state, screen)?;
+    }
+    if hud.is_shown(HudWidget::Fps) {
+        render_fps(ctx, screen)?;
+    }
+    Ok(())
+}
+
+/// Hearts along the top left, each one worth two points of health.
+fn render_hearts(ctx: &mut Context, state: &GameState, screen: graphics::Rect) -> GameResult<()> {
+    let players = state.ecs.read_storage::<Player>();
+    let healths = state.ecs.read_storage::<Health>();
+    let health = match (&players, &healths).join().next() {
+        Some((_player, health)) => health,
+        None => return Ok(()),
+    };
+
+    let hearts = (health.max + HEALTH_PER_HEART - 1) / HEALTH_PER_HEART;
+    for heart in 0..hearts {
+        let filled = health.current - heart * HEALTH_PER_HEART;
+        let image = if filled >= HEALTH_PER_HEART {
+            &state.hud.heart_full
+        } else if filled > 0 {
+            &state.hud.heart_half
+        } else {
+            &state.hud.heart_empty
+        };
+        let drawparams = graphics::DrawParam::new()
+            .dest(Point2::new(screen.x + MARGIN_PX + heart as f32 * HEART_SPACING_PX, screen.y + MARGIN_PX))
+            .scale([HEART_SCALE, HEART_SCALE]);
+        graphics::draw(ctx, image, drawparams)?;
+    }
+    Ok(())
+}
+
+/// A slot in the top right showing the item picked in the inventory screen, and how many are left.
+fn render_selected_item(ctx: &mut Context, state: &GameState, screen: graphics::Rect) -> GameResult<()> {
+    let slot = graphics::Rect::new(screen.x + screen.w - MARGIN_PX - SLOT_SIZE_PX, screen.y + MARGIN_PX, SLOT_SIZE_PX, SLOT_SIZE_PX);
+    let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), slot, [0.0, 0.0, 0.0, 0.5].into())?;
+    graphics::draw(ctx, &background, graphics::DrawParam::default())?;
+    let border = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), slot, graphics::WHITE)?;
+    graphics::draw(ctx, &border, graphics::DrawParam::default())?;
+
+    let id = match &state.hud.selected_item {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let players = state.ecs.read_storage::<Player>();
+    let inventories = state.ecs.read_storage::<Inventory>();
+    let count = (&players, &inventories).join().next().map_or(0, |(_player, inventory)| inventory.count(id));
+    if count == 0 {
+        return Ok(());
+    }
+
+    if let Some(sprite) = state.item_sprites.get(id) {
+        let drawparams = graphics::DrawParam::new()
+            .dest(Point2::new(slot.x + slot.w / 2.0, slot.y + slot.h / 2.0))
+            .offset(Point2::new(0.5, 0.5));
+        graphics::draw(ctx, sprite, drawparams)?;
+    }
+    if count > 1 {
+        let text = graphics::Text::new((count.to_string(), state.font, 16.0));
+        let (width, height) = text.dimensions(ctx);
+        let dest = Point2::new(slot.x + slot.w - width as f32 - 3.0, slot.y + slot.h - height as f32 - 1.0);
+        graphics::draw(ctx, &text, (dest, graphics::WHITE))?;
+    }
+    Ok(())
+}
+
+/// The whole map in the bottom right, with a dot where the player is.
+fn render_minimap(ctx: &mut Context, state: &GameState, screen: graphics::Rect) -> GameResult<()> {
+    let minimap = match &state.hud.minimap {
+        Some(minimap) => minimap,
+        None => return Ok(()),
+    };
+    let width = minimap.image.width() as f32 * MINIMAP_PX_PER_TILE;
+    let height = minimap.image.height() as f32 * MINIMAP_PX_PER_TILE;
+    let area = graphics::Rect::new(screen.x + screen.w - MARGIN_PX - width, screen.y + screen.h - MARGIN_PX - height, width, height);
+
+    let drawparams = graphics::DrawParam::new()
+        .dest(Point2::new(area.x, area.y))
+        .scale([MINIMAP_PX_PER_TILE, MINIMAP_PX_PER_TILE])
+        .color([1.0, 1.0, 1.0, 0.8].into());
+    graphics::draw(ctx, &minimap.image, drawparams)?;
+    let border = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), area, graphics::WHITE)?;
+    graphics::draw(ctx, &border, graphics::DrawParam::default())?;
+
+    let positions = state.ecs.read_storage::<Position>();
+    let players = state.ecs.read_storage::<Player>();
+    if let Some((position, _player)) = (&positions, &players).join().next() {
+        // Positions are measured from the middle of tiles, the minimap from their corners
+        let tile_x = position.x / map::to_px(1) as f32 + 0.5 - minimap.origin.0 as f32;
+        let tile_y = position.y / map::to_px(1) as f32 + 0.5 - minimap.origin.1 as f32;
+        let dot = graphics::Rect::new(
+            area.x + tile_x * MINIMAP_PX_PER_TILE - MINIMAP_DOT_PX / 2.0,
+            area.y + tile_y * MINIMAP_PX_PER_TILE - MINIMAP_DOT_PX / 2.0,
+            MINIMAP_DOT_PX,
+            MINIMAP_DOT_PX,
+        );
+        let dot = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), dot, [1.0, 0.9, 0.2, 1.0].into())?;
+        graphics::draw(ctx, &dot, graphics::DrawParam::default())?;
+    }
+    Ok(())
+}
+
+/// Toasts stack down from the top middle of the screen, newest at the bottom, fading out as they expire.
+fn render_toasts(ctx: &mut Context, state: &GameState, screen: graphics::Rect) -> GameResult<()> {
+    for (index, toast) in state.hud.toasts.iter().enumerate() {
+        let alpha = (toast.ticks as f32 / TOAST_FADE_TICKS as f32).min(1.0);
+        let text = graphics::Text::new((toast.message.as_str(), state.font, TOAST_FONT_SIZE));
+        let (width, height) = text.dimensions(ctx);
+        let x = screen.x + (screen.w - width as f32) / 2.0;
+        let y = screen.y + MARGIN_PX + index as f32 * TOAST_SPACING_PX;
+
+        let background = graphics::Rect::new(x - 8.0, y - 2.0, width as f32 + 16.0, height as f32 + 4.0);
+        let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), background, [0.0, 0.0, 0.0, 0.6 * alpha].into())?;
+        graphics::draw(ctx, &background, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &text, (Point2::new(x, y), [1.0, 1.0, 1.0, alpha].into()))?;
+    }
+    Ok(())
+}
+
+fn render_fps(ctx: &mut Context, screen: graphics::Rect) -> GameResult<()> {
+    let fps = timer::fps(ctx);
+    let fps_text = graphics::Text::new(format!("FPS: {:.2}", fps));
+    let (_width, height) = fps_text.dimensions(ctx);
+    let dest = Point2::new(screen.x + MARGIN_PX, screen.y + screen.h - MARGIN_PX - height as f32);
+    graphics::draw(ctx, &fps_text, (dest, graphics::BLACK))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, enemy};
+    use crate::map::{MapTile, TileType};
+    use crate::simulation;
+    use crate::input::{Action, Input};
+    use crate::Direction;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn picking_things_up_and_defeating_enemies_is_announced() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[
+            object("Apples", "item", (0, 0), &[("item", "apple"), ("count", "2")]),
+            enemy("Slime", (1, 0), "1"),
+        ]);
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["Picked up Apple x2".to_string()]);
+        game.press(Direction::Right);
+        game.input(Input::Action(Action::Attack));
+        game.step(2);
+        assert!(game.notifications().contains(&"Defeated Slime".to_string()));
+    }
+
+    #[test]
+    fn toasts_expire_and_make_room_for_new_ones() {
+        let mut toasts = Toasts::default();
+        for number in 0..6 {
+            toasts.push(number.to_string());
+        }
+        let shown: Vec<&str> = toasts.iter().map(|toast| toast.message.as_str()).collect();
+        assert_eq!(shown, vec!["2", "3", "4", "5"]);
+        for _ in 0..simulation::TICKS_PER_SECOND * 3 {
+            toasts.tick();
+        }
+        assert_eq!(toasts.iter().count(), 0);
+    }
+
+    #[test]
+    fn minimap_colours_tiles_by_type() {
+        let mut map = Headless::floor(3, 2);
+        map.insert((1, 1), MapTile { tile_id: 2, tile_type: TileType::Wall });
+        let minimap = minimap_pixels(&map).unwrap();
+        assert_eq!((minimap.width, minimap.height, minimap.origin), (3, 2, (0, 0)));
+        assert_eq!(minimap.pixels.len(), 3 * 2 * 4);
+        let pixel = |x: usize, y: usize| &minimap.pixels[(y * 3 + x) * 4..(y * 3 + x) * 4 + 4];
+        assert_ne!(pixel(0, 0), pixel(1, 1));
+        assert_eq!(pixel(0, 0), pixel(2, 1));
+        assert!(minimap_pixels(&BTreeMap::new()).is_none());
+    }
+}
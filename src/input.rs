@@ -0,0 +1,456 @@
+use crate::Direction;
+use ggez::event::{Axis, Button, KeyCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The key bindings that ship with the game.
+pub const KEY_BINDINGS_PATH: &str = "assets/config/keybindings.ron";
+
+const DEFAULT_STICK_DEADZONE: f32 = 0.2;
+
+/// Everything the player can ask the game to do.
+/// Game code only ever reacts to actions, never to the keys behind them,
+/// so that every action can be rebound.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Interact,
+    Attack,
+    Pause,
+    ToggleFps,
+    ToggleMinimap,
+    Inventory,
+    QuestLog,
+    QuickSave,
+}
+
+impl Action {
+    pub const ALL: [Action; 12] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Interact,
+        Action::Attack,
+        Action::Pause,
+        Action::ToggleFps,
+        Action::ToggleMinimap,
+        Action::Inventory,
+        Action::QuestLog,
+        Action::QuickSave,
+    ];
+
+    /// Returns the direction a movement action points in.
+    pub fn direction(self) -> Option<Direction> {
+        match self {
+            Action::MoveUp => Some(Direction::Up),
+            Action::MoveDown => Some(Direction::Down),
+            Action::MoveLeft => Some(Direction::Left),
+            Action::MoveRight => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    /// Actions the game cannot be played without, which always keep at least one key.
+    pub fn is_required(self) -> bool {
+        matches!(self, Action::Interact | Action::Pause)
+    }
+
+    /// The keys bound to each action when nothing else has been configured.
+    fn default_keys(self) -> Vec<KeyCode> {
+        match self {
+            Action::MoveUp => vec![KeyCode::Up, KeyCode::W],
+            Action::MoveDown => vec![KeyCode::Down, KeyCode::S],
+            Action::MoveLeft => vec![KeyCode::Left, KeyCode::A],
+            Action::MoveRight => vec![KeyCode::Right, KeyCode::D],
+            Action::Interact => vec![KeyCode::Return, KeyCode::Space, KeyCode::E],
+            Action::Attack => vec![KeyCode::X, KeyCode::J],
+            Action::Pause => vec![KeyCode::Escape],
+            Action::ToggleFps => vec![KeyCode::Key0],
+            Action::ToggleMinimap => vec![KeyCode::M],
+            Action::Inventory => vec![KeyCode::I, KeyCode::Tab],
+            Action::QuestLog => vec![KeyCode::Q],
+            Action::QuickSave => vec![KeyCode::F5],
+        }
+    }
+
+    /// The gamepad buttons bound to each action when nothing else has been configured.
+    fn default_buttons(self) -> Vec<Button> {
+        match self {
+            Action::MoveUp => vec![Button::DPadUp],
+            Action::MoveDown => vec![Button::DPadDown],
+            Action::MoveLeft => vec![Button::DPadLeft],
+            Action::MoveRight => vec![Button::DPadRight],
+            Action::Interact => vec![Button::South],
+            Action::Attack => vec![Button::West],
+            Action::Pause => vec![Button::Start],
+            Action::ToggleFps => vec![Button::Select],
+            Action::ToggleMinimap => vec![],
+            Action::Inventory => vec![Button::North],
+            Action::QuestLog => vec![],
+            Action::QuickSave => vec![],
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        Action::ALL.iter()
+            .find(|action| action.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown action '{}'", s))
+    }
+}
+
+/// Everything that can reach the in game state, from any input device.
+/// Discrete presses become actions, the analog stick steers the player
+/// with a direction whose length is how far the stick is pushed,
+/// and clicking on the map walks the player to the clicked tile.
+/// Using and dropping items comes from the inventory screen, by inventory slot.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    Action(Action),
+    Stick(f32, f32),
+    MoveTo(i32, i32),
+    UseItem(usize),
+    DropItem(usize),
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Action(action) => write!(f, "{}", action),
+            Input::Stick(x, y) => write!(f, "Stick {} {}", x, y),
+            Input::MoveTo(x, y) => write!(f, "MoveTo {} {}", x, y),
+            Input::UseItem(slot) => write!(f, "UseItem {}", slot),
+            Input::DropItem(slot) => write!(f, "DropItem {}", slot),
+        }
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Input, String> {
+        match s.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["Stick", x, y] => {
+                let x = x.parse().map_err(|e| format!("invalid stick x '{}': {}", x, e))?;
+                let y = y.parse().map_err(|e| format!("invalid stick y '{}': {}", y, e))?;
+                Ok(Input::Stick(x, y))
+            }
+            ["MoveTo", x, y] => {
+                let x = x.parse().map_err(|e| format!("invalid tile x '{}': {}", x, e))?;
+                let y = y.parse().map_err(|e| format!("invalid tile y '{}': {}", y, e))?;
+                Ok(Input::MoveTo(x, y))
+            }
+            ["UseItem", slot] => Ok(Input::UseItem(slot.parse().map_err(|e| format!("invalid slot '{}': {}", slot, e))?)),
+            ["DropItem", slot] => Ok(Input::DropItem(slot.parse().map_err(|e| format!("invalid slot '{}': {}", slot, e))?)),
+            [action] => Ok(Input::Action(action.parse()?)),
+            _ => Err(format!("unknown input '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InputMapError {
+    Io(io::Error),
+    Parse(ron::Error),
+    /// The same key or button was bound to more than one action, so it was left with the first.
+    Conflict { binding: String, first: Action, second: Action },
+    /// Rebinding would have left an action the game cannot do without without any keys.
+    Unbound(Action),
+}
+
+impl fmt::Display for InputMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputMapError::Io(e) => write!(f, "could not read key bindings: {}", e),
+            InputMapError::Parse(e) => write!(f, "could not parse key bindings: {}", e),
+            InputMapError::Conflict { binding, first, second } =>
+                write!(f, "{} is bound to both {} and {}, keeping it for {}", binding, first, second, first),
+            InputMapError::Unbound(action) => write!(f, "{} would be left without a key", action),
+        }
+    }
+}
+
+/// The bindings as they are written in the config file.
+#[derive(Deserialize)]
+#[serde(default)]
+struct InputConfig {
+    keys: HashMap<Action, Vec<KeyCode>>,
+    buttons: HashMap<Action, Vec<Button>>,
+    stick_deadzone: f32,
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        InputConfig {
+            keys: HashMap::new(),
+            buttons: HashMap::new(),
+            stick_deadzone: DEFAULT_STICK_DEADZONE,
+        }
+    }
+}
+
+/// Maps keys and gamepad buttons onto the actions they trigger.
+/// Any number of keys can trigger an action, but each key triggers at most one action.
+#[derive(Debug)]
+pub struct InputMap {
+    keys: HashMap<Action, Vec<KeyCode>>,
+    key_actions: HashMap<KeyCode, Action>,
+    buttons: HashMap<Action, Vec<Button>>,
+    button_actions: HashMap<Button, Action>,
+    pub stick_deadzone: f32,
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        let (input_map, conflicts) = InputMap::new(InputConfig::default());
+        assert!(conflicts.is_empty(), "default key bindings conflict");
+        input_map
+    }
+}
+
+impl InputMap {
+    /// Builds an input map from the given bindings, along with a conflict for every binding left out
+    /// because an earlier action already has it. Actions which are not mentioned keep their default bindings.
+    fn new(config: InputConfig) -> (InputMap, Vec<InputMapError>) {
+        let ((keys, key_actions), mut conflicts) = bind(&config.keys, Action::default_keys);
+        let ((buttons, button_actions), button_conflicts) = bind(&config.buttons, Action::default_buttons);
+        conflicts.extend(button_conflicts);
+        let input_map = InputMap {
+            keys,
+            key_actions,
+            buttons,
+            button_actions,
+            stick_deadzone: config.stick_deadzone.clamp(0.0, 0.95),
+        };
+        (input_map, conflicts)
+    }
+
+    /// Loads bindings from a ron file mapping each action to lists of keys and buttons.
+    /// A binding which conflicts with another is left out and reported alongside, so the rest of the file still applies.
+    pub fn load(path: &Path) -> Result<(InputMap, Vec<InputMapError>), InputMapError> {
+        let contents = fs::read_to_string(path).map_err(InputMapError::Io)?;
+        let config = ron::de::from_str(&contents).map_err(InputMapError::Parse)?;
+        Ok(InputMap::new(config))
+    }
+
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.key_actions.get(&key).copied()
+    }
+
+    pub fn button_action(&self, button: Button) -> Option<Action> {
+        self.button_actions.get(&button).copied()
+    }
+
+    /// Binds an action to exactly the given keys. Keys taken away from other actions are swapped
+    /// for the ones this action had, so that the other actions are not left without them. When keys
+    /// are taken from several actions, the keys given up are handed out to each of them in turn.
+    /// Nothing changes if a required action would still end up without any keys.
+    pub fn set_keys(&mut self, action: Action, keys: &[KeyCode]) -> Result<(), InputMapError> {
+        let given_up: Vec<KeyCode> = self.keys(action).iter().filter(|key| !keys.contains(key)).copied().collect();
+        let mut rebound = self.keys.clone();
+        let mut losers = vec![];
+        for other in Action::ALL.iter().copied().filter(|other| *other != action) {
+            let other_keys = rebound.entry(other).or_default();
+            let before = other_keys.len();
+            other_keys.retain(|key| !keys.contains(key));
+            if other_keys.len() < before {
+                losers.push(other);
+            }
+        }
+        for (key, loser) in given_up.into_iter().zip(losers.iter().cycle()) {
+            rebound.entry(*loser).or_default().push(key);
+        }
+        rebound.insert(action, keys.to_vec());
+        if let Some(unbound) = Action::ALL.iter().find(|action| action.is_required() && rebound[action].is_empty()) {
+            return Err(InputMapError::Unbound(*unbound));
+        }
+
+        self.key_actions = rebound.iter()
+            .flat_map(|(action, keys)| keys.iter().map(move |key| (*key, *action)))
+            .collect();
+        self.keys = rebound;
+        Ok(())
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.keys.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn buttons(&self, action: Action) -> &[Button] {
+        self.buttons.get(&action).map_or(&[], |buttons| buttons.as_slice())
+    }
+}
+
+/// Bindings from each action to its inputs, along with the reverse lookup.
+type Bindings<T> = (HashMap<Action, Vec<T>>, HashMap<T, Action>);
+
+/// Resolves the bindings for every action, falling back to the defaults, and builds the reverse lookup.
+/// Anything bound twice stays with the action that comes first, and is reported as a conflict.
+fn bind<T: Copy + Eq + Hash + fmt::Debug>(
+    configured: &HashMap<Action, Vec<T>>,
+    defaults: fn(Action) -> Vec<T>,
+) -> (Bindings<T>, Vec<InputMapError>) {
+    let mut bindings = HashMap::new();
+    let mut actions = HashMap::new();
+    let mut conflicts = vec![];
+    for action in Action::ALL.iter().copied() {
+        let wanted = match configured.get(&action) {
+            Some(action_bindings) => action_bindings.clone(),
+            None => defaults(action),
+        };
+        let mut action_bindings = vec![];
+        for binding in wanted {
+            match actions.get(&binding).copied() {
+                Some(first) if first != action => {
+                    conflicts.push(InputMapError::Conflict { binding: format!("{:?}", binding), first, second: action });
+                }
+                Some(_) => (),
+                None => {
+                    actions.insert(binding, action);
+                    action_bindings.push(binding);
+                }
+            }
+        }
+        bindings.insert(action, action_bindings);
+    }
+    ((bindings, actions), conflicts)
+}
+
+/// Tracks the left analog stick of the gamepad, which arrives one axis at a time.
+#[derive(Default, Debug)]
+pub struct Stick {
+    x: f32,
+    y: f32,
+    steering: (f32, f32),
+}
+
+impl Stick {
+    /// Updates the stick from a raw axis event.
+    /// Returns the new steering input once it has actually changed, with the deadzone already applied.
+    pub fn update(&mut self, axis: Axis, value: f32, deadzone: f32) -> Option<Input> {
+        match axis {
+            Axis::LeftStickX => self.x = value,
+            // Gamepads report up as positive, but up is negative on screen
+            Axis::LeftStickY => self.y = -value,
+            _ => return None,
+        }
+        let steering = apply_deadzone(self.x, self.y, deadzone);
+        if steering == self.steering {
+            return None;
+        }
+        self.steering = steering;
+        Some(Input::Stick(steering.0, steering.1))
+    }
+}
+
+/// Applies a radial deadzone to the stick position.
+/// Anything inside the deadzone is treated as centered, and the remaining range
+/// is stretched back out so that pushing past the deadzone starts from zero.
+pub fn apply_deadzone(x: f32, y: f32, deadzone: f32) -> (f32, f32) {
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+    let scaled = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+    (x / magnitude * scaled, y / magnitude * scaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use ggez::event::{Axis, Button, KeyCode};
+
+    #[test]
+    fn gamepad_buttons_trigger_the_same_actions_as_keys() {
+        let input_map = InputMap::default();
+        assert_eq!(input_map.button_action(Button::DPadRight), Some(Action::MoveRight));
+        assert_eq!(input_map.button_action(Button::South), input_map.action(KeyCode::Return));
+
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.input(Input::Action(input_map.button_action(Button::DPadRight).unwrap()));
+        game.step(10);
+        assert!(game.player_position().0 > 0.0);
+    }
+
+    #[test]
+    fn conflicting_bindings_are_left_out_and_reported() {
+        let config: InputConfig = ron::de::from_str("(keys: { Interact: [E], Attack: [E, K], ToggleFps: [F] })").unwrap();
+        let (input_map, conflicts) = InputMap::new(config);
+        assert_eq!(conflicts.len(), 1);
+        assert!(matches!(&conflicts[0], InputMapError::Conflict { binding, first: Action::Interact, second: Action::Attack } if binding == "E"));
+        // Everything else in the file still applies
+        assert_eq!(input_map.action(KeyCode::E), Some(Action::Interact));
+        assert_eq!(input_map.keys(Action::Attack), &[KeyCode::K]);
+        assert_eq!(input_map.action(KeyCode::F), Some(Action::ToggleFps));
+        assert_eq!(input_map.keys(Action::MoveUp), &[KeyCode::Up, KeyCode::W]);
+
+        let (_input_map, conflicts) = InputMap::load(Path::new(KEY_BINDINGS_PATH)).expect("could not load key bindings");
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+    }
+
+    #[test]
+    fn stick_inside_deadzone_does_not_move_player() {
+        let mut stick = Stick::default();
+        assert_eq!(stick.update(Axis::LeftStickX, 0.1, 0.2), None);
+        assert_eq!(stick.update(Axis::LeftStickY, -0.1, 0.2), None);
+        assert_eq!(stick.update(Axis::RightStickX, 1.0, 0.2), None);
+
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.step(30);
+        assert_eq!(game.player_position(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stick_moves_player_in_proportion_to_how_far_it_is_pushed() {
+        let distance = |value: f32| {
+            let mut stick = Stick::default();
+            let mut game = Headless::new(Headless::floor(20, 20));
+            // Pushing down on the stick reports a negative value, which is down the screen for us
+            let input = stick.update(Axis::LeftStickY, -value, 0.2).expect("stick should have moved");
+            game.input(input);
+            game.step(60);
+            let (x, y) = game.player_position();
+            assert_eq!(x, 0.0);
+            y
+        };
+        let half = distance(0.6);
+        let full = distance(1.0);
+        assert!(half > 0.0);
+        assert!(full > half * 1.5, "full: {}, half: {}", full, half);
+    }
+
+    #[test]
+    fn releasing_stick_stops_player() {
+        let mut stick = Stick::default();
+        let mut game = Headless::new(Headless::floor(20, 20));
+        game.input(stick.update(Axis::LeftStickX, 1.0, 0.2).unwrap());
+        game.step(30);
+        game.input(stick.update(Axis::LeftStickX, 0.0, 0.2).unwrap());
+        game.step(10);
+        let stopped = game.player_position();
+        game.step(30);
+        assert!(stopped.0 > 0.0);
+        assert_eq!(game.player_position(), stopped);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{GameState, Point2, RootState};
+use crate::component::{Inventory, ItemStack, Player};
+use crate::game;
+use crate::widget;
+use crate::input::{Action, Input};
+use crate::item::ItemDefs;
+use crate::simulation::TICKS_PER_SECOND;
+use ggez::{graphics, Context, GameResult, timer};
+use specs::prelude::*;
+
+const PANEL_WIDTH_PX: f32 = 600.0;
+const PANEL_HEIGHT_PX: f32 = 470.0;
+const PANEL_PADDING_PX: f32 = 16.0;
+const ROW_HEIGHT_PX: f32 = 28.0;
+const TITLE_FONT_SIZE: f32 = 24.0;
+const TEXT_FONT_SIZE: f32 = 20.0;
+const ICON_SCALE: f32 = 0.75;
+const HIGHLIGHT: [f32; 4] = [1.0, 0.85, 0.4, 1.0];
+const FADED: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// What can be done with the selected item.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ItemCommand {
+    Use,
+    Drop,
+    Inspect,
+}
+
+impl ItemCommand {
+    pub const ALL: [ItemCommand; 3] = [ItemCommand::Use, ItemCommand::Drop, ItemCommand::Inspect];
+
+    fn label(self) -> &'static str {
+        match self {
+            ItemCommand::Use => "Use",
+            ItemCommand::Drop => "Drop",
+            ItemCommand::Inspect => "Inspect",
+        }
+    }
+}
+
+/// The inventory screen, showing the players items over the paused world.
+/// Picking an item opens a menu of commands for it.
+#[derive(Default, Debug)]
+pub struct InventoryScreen {
+    selected: usize,
+    command: Option<usize>,
+    message: Option<String>,
+}
+
+/// Opens the inventory with the item shown in the hud already highlighted.
+pub fn open_inventory(state: &mut GameState) {
+    let selected = state.hud.selected_item.as_ref()
+        .and_then(|id| player_slots(state).iter().position(|stack| &stack.id == id))
+        .unwrap_or(0);
+    state.inventory = Some(InventoryScreen { selected, ..InventoryScreen::default() });
+    state.states.push(RootState::Inventory);
+}
+
+/// Closes the inventory, leaving whichever item was highlighted in the hud.
+fn close_inventory(state: &mut GameState) {
+    if let Some(screen) = &state.inventory {
+        if let Some(stack) = player_slots(state).get(screen.selected) {
+            state.hud.selected_item = Some(stack.id.clone());
+        }
+    }
+    state.inventory = None;
+    state.states.pop();
+}
+
+/// A copy of what the player is carrying, so that the screen never holds on to the world.
+fn player_slots(state: &GameState) -> Vec<ItemStack> {
+    let players = state.ecs.read_storage::<Player>();
+    let inventories = state.ecs.read_storage::<Inventory>();
+    (&players, &inventories).join()
+        .next()
+        .map_or(vec![], |(_player, inventory)| inventory.slots.clone())
+}
+
+pub fn inventory_input(state: &mut GameState, ctx: &mut Context, action: Action) {
+    let slots = player_slots(state);
+    let screen = match &mut state.inventory {
+        Some(screen) => screen,
+        None => return close_inventory(state),
+    };
+
+    match (screen.command, action) {
+        (_, Action::Inventory) => close_inventory(state),
+        (None, Action::Pause) => close_inventory(state),
+        (None, Action::MoveUp) => screen.selected = wrap(screen.selected as i32 - 1, slots.len()),
+        (None, Action::MoveDown) => screen.selected = wrap(screen.selected as i32 + 1, slots.len()),
+        (None, Action::Interact) if screen.selected < slots.len() => screen.command = Some(0),
+        (Some(_), Action::Pause) => screen.command = None,
+        (Some(command), Action::MoveUp) => screen.command = Some(wrap(command as i32 - 1, ItemCommand::ALL.len())),
+        (Some(command), Action::MoveDown) => screen.command = Some(wrap(command as i32 + 1, ItemCommand::ALL.len())),
+        (Some(command), Action::Interact) => {
+            screen.command = None;
+            if let Some(stack) = slots.get(screen.selected) {
+                run_command(state, ctx, ItemCommand::ALL[command], stack);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Wraps a selection around either end of a list.
+fn wrap(index: i32, len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        index.rem_euclid(len as i32) as usize
+    }
+}
+
+/// Carries out a command on the selected item.
+/// Anything that changes the world goes through the same path as every other input,
+/// so that it ends up in recordings and is replayed on the right tick.
+fn run_command(state: &mut GameState, ctx: &mut Context, command: ItemCommand, stack: &ItemStack) {
+    let (name, description, use_message, consumable) = match state.ecs.fetch::<ItemDefs>().get(&stack.id) {
+        Some(def) => (def.name.clone(), def.description.clone(), def.use_message.clone(), def.consumable),
+        None => return,
+    };
+    let slot = state.inventory.as_ref().map_or(0, |screen| screen.selected);
+
+    let message = match command {
+        ItemCommand::Use => match use_message {
+            Some(use_message) => {
+                if consumable {
+                    game::in_game_input(state, ctx, Input::UseItem(slot));
+                }
+                use_message
+            }
+            None => format!("You can't use the {} right now.", name),
+        },
+        ItemCommand::Drop => {
+            game::in_game_input(state, ctx, Input::DropItem(slot));
+            format!("You drop the {}.", name)
+        }
+        ItemCommand::Inspect => description,
+    };
+
+    let remaining = player_slots(state).len();
+    if let Some(screen) = &mut state.inventory {
+        screen.message = Some(message);
+        screen.selected = screen.selected.min(remaining.saturating_sub(1));
+    }
+}
+
+/// The world stays paused while the inventory is open.
+pub fn inventory_update(_state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    while timer::check_update_time(ctx, TICKS_PER_SECOND) {}
+    Ok(())
+}
+
+pub fn inventory_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    if let Some(screen) = &state.inventory {
+        render_inventory(ctx, state, screen)?;
+    }
+    Ok(())
+}
+
+fn render_inventory(ctx: &mut Context, state: &GameState, screen: &InventoryScreen) -> GameResult<()> {
+    let players = state.ecs.read_storage::<Player>();
+    let inventories = state.ecs.read_storage::<Inventory>();
+    let defs = state.ecs.fetch::<ItemDefs>();
+    let inventory = match (&players, &inventories).join().next() {
+        Some((_player, inventory)) => inventory,
+        None => return Ok(()),
+    };
+
+    let panel = widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX);
+    widget::draw_panel(ctx, panel)?;
+
+    let left = panel.x + PANEL_PADDING_PX;
+    let mut top = panel.y + PANEL_PADDING_PX;
+    let title = graphics::Text::new((
+        format!("Inventory  {}/{}", inventory.slots.len(), inventory.capacity),
+        state.font,
+        TITLE_FONT_SIZE,
+    ));
+    graphics::draw(ctx, &title, (Point2::new(left, top), graphics::WHITE))?;
+    top += TITLE_FONT_SIZE + 12.0;
+
+    if inventory.slots.is_empty() {
+        let empty = graphics::Text::new(("Nothing here yet.", state.font, TEXT_FONT_SIZE));
+        graphics::draw(ctx, &empty, (Point2::new(left, top), FADED.into()))?;
+    }
+
+    for (index, stack) in inventory.slots.iter().enumerate() {
+        let row_top = top + index as f32 * ROW_HEIGHT_PX;
+        if let Some(sprite) = state.item_sprites.get(&stack.id) {
+            let drawparams = graphics::DrawParam::new()
+                .dest(Point2::new(left, row_top))
+                .scale([ICON_SCALE, ICON_SCALE]);
+            graphics::draw(ctx, sprite, drawparams)?;
+        }
+        let name = defs.get(&stack.id).map_or(stack.id.as_str(), |def| def.name.as_str());
+        let label = if stack.count > 1 { format!("{} x{}", name, stack.count) } else { name.to_string() };
+        let color = if index == screen.selected { HIGHLIGHT } else { FADED };
+        let text = graphics::Text::new((label, state.font, TEXT_FONT_SIZE));
+        graphics::draw(ctx, &text, (Point2::new(left + 32.0, row_top), color.into()))?;
+
+        // The commands open up next to the item they apply to
+        if let (true, Some(selected_command)) = (index == screen.selected, screen.command) {
+            let menu_left = panel.x + PANEL_WIDTH_PX / 2.0;
+            for (command_index, command) in ItemCommand::ALL.iter().enumerate() {
+                let (marker, color) = if command_index == selected_command { ("> ", HIGHLIGHT) } else { ("   ", FADED) };
+                let text = graphics::Text::new((format!("{}{}", marker, command.label()), state.font, TEXT_FONT_SIZE));
+                graphics::draw(ctx, &text, (Point2::new(menu_left, row_top + command_index as f32 * ROW_HEIGHT_PX), color.into()))?;
+            }
+        }
+    }
+
+    if let Some(message) = &screen.message {
+        let mut text = graphics::Text::new((message.as_str(), state.font, TEXT_FONT_SIZE));
+        text.set_bounds(Point2::new(PANEL_WIDTH_PX - PANEL_PADDING_PX * 2.0, f32::INFINITY), graphics::Align::Left);
+        let (_width, height) = text.dimensions(ctx);
+        let message_top = panel.y + PANEL_HEIGHT_PX - PANEL_PADDING_PX - height as f32;
+        graphics::draw(ctx, &text, (Point2::new(left, message_top), graphics::WHITE))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use crate::component::{Inventory, Item};
+use crate::simulation::Notifications;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Every item in the game is described here rather than in code.
+pub const ITEM_DEFS_PATH: &str = "assets/items/items.ron";
+
+/// Sprite drawn for items whose own sprite is missing.
+pub const MISSING_SPRITE: &str = "/missing.png";
+
+/// Describes a kind of item. Items in the world and in inventories only refer to these by id.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ItemDef {
+    pub name: String,
+    pub description: String,
+    /// Path to the image for the item, relative to the assets directory.
+    pub sprite: String,
+    /// How many of the item fit in a single inventory slot.
+    #[serde(default = "default_max_stack")]
+    pub max_stack: u32,
+    /// Shown when the item is used. Items without one cannot be used at all.
+    #[serde(default)]
+    pub use_message: Option<String>,
+    /// Whether using the item uses it up.
+    #[serde(default)]
+    pub consumable: bool,
+}
+
+fn default_max_stack() -> u32 {
+    1
+}
+
+#[derive(Debug)]
+pub enum ItemDefsError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+impl fmt::Display for ItemDefsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ItemDefsError::Io(e) => write!(f, "could not read item definitions: {}", e),
+            ItemDefsError::Parse(e) => write!(f, "could not parse item definitions: {}", e),
+        }
+    }
+}
+
+/// Every known kind of item, by id.
+#[derive(Clone, Default, Debug)]
+pub struct ItemDefs(pub HashMap<String, ItemDef>);
+
+impl ItemDefs {
+    pub fn load(path: &Path) -> Result<ItemDefs, ItemDefsError> {
+        let contents = fs::read_to_string(path).map_err(ItemDefsError::Io)?;
+        contents.parse()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ItemDef> {
+        self.0.get(id)
+    }
+
+    /// How many of an item fit in one slot. Unknown items never stack.
+    pub fn max_stack(&self, id: &str) -> u32 {
+        self.get(id).map_or(1, |def| def.max_stack.max(1))
+    }
+}
+
+impl FromStr for ItemDefs {
+    type Err = ItemDefsError;
+
+    fn from_str(s: &str) -> Result<ItemDefs, ItemDefsError> {
+        ron::de::from_str(s).map(ItemDefs).map_err(ItemDefsError::Parse)
+    }
+}
+
+/// Moves as much of an item lying in the world into an inventory as will fit,
+/// letting the player know what they picked up.
+/// Returns true once the item has been picked up entirely.
+pub fn pick_up(inventory: &mut Inventory, item: &mut Item, defs: &ItemDefs, notifications: &mut Notifications) -> bool {
+    let before = item.count;
+    item.count = inventory.add(&item.id, item.count, defs.max_stack(&item.id));
+    let taken = before - item.count;
+    if taken > 0 {
+        let name = defs.get(&item.id).map_or(item.id.as_str(), |def| def.name.as_str());
+        notifications.push(if taken > 1 { format!("Picked up {} x{}", name, taken) } else { format!("Picked up {}", name) });
+    }
+    item.count == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object};
+    use crate::component::*;
+    use crate::map;
+    use crate::input::Input;
+    use std::path::Path;
+    use specs::prelude::*;
+
+    #[test]
+    fn inventory_stacks_items_up_to_its_capacity() {
+        let mut inventory = Inventory::new(2);
+        assert_eq!(inventory.add("coin", 7, 5), 0);
+        assert_eq!(inventory.slots.len(), 2);
+        assert_eq!(inventory.add("coin", 4, 5), 1);
+        assert_eq!(inventory.count("coin"), 10);
+        assert_eq!(inventory.add("apple", 1, 10), 1);
+
+        let taken = inventory.take(0, 9).unwrap();
+        assert_eq!(taken.count, 5);
+        assert_eq!(inventory.slots, vec![ItemStack { id: "coin".to_string(), count: 5 }]);
+    }
+
+    #[test]
+    fn using_consumable_items_uses_them_up() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[
+            object("Apples", "item", (0, 0), &[("item", "apple"), ("count", "2")]),
+            object("Key", "item", (0, 0), &[("item", "key")]),
+        ]);
+        game.step(1);
+        game.input(Input::UseItem(0));
+        game.input(Input::UseItem(1));
+        assert_eq!(game.carried("apple"), 1);
+        assert_eq!(game.carried("key"), 1);
+    }
+
+    #[test]
+    fn basic_map_only_places_known_items() {
+        let game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        let defs = game.ecs.fetch::<ItemDefs>();
+        let items = game.ecs.read_storage::<Item>();
+        assert!((&items).join().count() > 0);
+        for item in (&items).join() {
+            assert!(defs.get(&item.id).is_some(), "unknown item {}", item.id);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{GameState, Point2, HEIGHT_PX, WIDTH_PX};
+use crate::clock::Clock;
+use crate::component::{LightSource, Player, Position};
+use crate::viewport_system;
+use ggez::graphics::{self, BlendMode, Drawable};
+use ggez::{conf, Context, GameResult};
+use specs::prelude::*;
+
+/// Size of the glow texture every light is drawn with, scaled up or down to the radius of the light.
+const GLOW_SIZE: u16 = 128;
+
+/// Lights the world through a light map the size of the screen. The map starts out the colour of
+/// the time of day, lights are added onto it, and then it is multiplied over everything drawn so far.
+pub struct Lighting {
+    light_map: graphics::Canvas,
+    glow: graphics::Image,
+}
+
+impl Lighting {
+    pub fn new(ctx: &mut Context) -> GameResult<Lighting> {
+        let mut light_map = graphics::Canvas::new(ctx, WIDTH_PX as u16, HEIGHT_PX as u16, conf::NumSamples::One)?;
+        light_map.set_blend_mode(Some(BlendMode::Multiply));
+        let mut glow = graphics::Image::from_rgba8(ctx, GLOW_SIZE, GLOW_SIZE, &glow_pixels(GLOW_SIZE))?;
+        glow.set_blend_mode(Some(BlendMode::Add));
+        Ok(Lighting { light_map, glow })
+    }
+}
+
+/// A white disc fading out to black at its edge, smoothly so that lights have no hard rim.
+/// Lights are added onto the light map, where black adds nothing.
+pub fn glow_pixels(size: u16) -> Vec<u8> {
+    let half = size as f32 / 2.0;
+    let mut pixels = Vec::with_capacity(size as usize * size as usize * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let dx = (x as f32 + 0.5 - half) / half;
+            let dy = (y as f32 + 0.5 - half) / half;
+            let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).max(0.0);
+            let brightness = (falloff * falloff * 255.0) as u8;
+            pixels.extend_from_slice(&[brightness, brightness, brightness, 255]);
+        }
+    }
+    pixels
+}
+
+/// Where the middle of a light appears on screen, or nothing if none of it reaches the screen.
+pub fn light_on_screen(position: &Position, radius: f32, player_position: (f32, f32)) -> Option<Point2> {
+    viewport_system::world_to_screen(position.x, position.y, player_position, radius)
+}
+
+/// Tints everything drawn so far by the time of day, brightened around every light on screen.
+/// In the full light of day there is nothing to do at all.
+pub fn render_lighting(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let ambient = state.ecs.fetch::<Clock>().ambient();
+    if ambient.iter().all(|channel| *channel >= 1.0) {
+        return Ok(());
+    }
+    let positions = state.ecs.read_storage::<Position>();
+    let players = state.ecs.read_storage::<Player>();
+    let lights = state.ecs.read_storage::<LightSource>();
+    let player_position = match (&positions, &players).join().next() {
+        Some((position, _player)) => (position.x, position.y),
+        None => return Ok(()),
+    };
+    let lighting = &state.lighting;
+
+    graphics::set_canvas(ctx, Some(&lighting.light_map));
+    graphics::clear(ctx, [ambient[0], ambient[1], ambient[2], 1.0].into());
+    for (position, light) in (&positions, &lights).join() {
+        if let Some(center) = light_on_screen(position, light.radius, player_position) {
+            let scale = light.radius * 2.0 / GLOW_SIZE as f32;
+            let color = [light.color[0] * light.intensity, light.color[1] * light.intensity, light.color[2] * light.intensity, 1.0];
+            let drawparams = graphics::DrawParam::new()
+                .dest(center)
+                .offset(Point2::new(0.5, 0.5))
+                .scale([scale, scale])
+                .color(color.into());
+            graphics::draw(ctx, &lighting.glow, drawparams)?;
+        }
+    }
+    graphics::set_canvas(ctx, None);
+    graphics::draw(ctx, &lighting.light_map, graphics::DrawParam::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::*;
+    use crate::{Point2, HEIGHT_PX, WIDTH_PX};
+
+    #[test]
+    fn lights_fade_out_and_are_culled_off_screen() {
+        let pixels = glow_pixels(16);
+        let brightness = |x: usize, y: usize| pixels[(y * 16 + x) * 4];
+        assert!(brightness(8, 8) > 200);
+        assert!(brightness(8, 8) > brightness(12, 8));
+        assert_eq!(brightness(0, 0), 0);
+
+        let player = (1000.0, 1000.0);
+        let left_edge = 1000.0 - (WIDTH_PX / 2) as f32;
+        let centered = light_on_screen(&Position { x: 1000.0, y: 1000.0 }, 64.0, player);
+        assert_eq!(centered, Some(Point2::new((WIDTH_PX / 2) as f32, (HEIGHT_PX / 2) as f32)));
+        // A light off the side of the screen still counts while its glow reaches onto it
+        assert!(light_on_screen(&Position { x: left_edge - 40.0, y: 1000.0 }, 64.0, player).is_some());
+        assert!(light_on_screen(&Position { x: left_edge - 80.0, y: 1000.0 }, 64.0, player).is_none());
+    }
+}
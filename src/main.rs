@@ -1,31 +1,87 @@
 extern crate tiled;
 
 pub mod map;
+pub mod mapgen;
 pub mod menu;
 pub mod game;
 pub mod component;
 pub mod viewport_system;
+pub mod visibility_system;
 pub mod movement_system;
 pub mod animation_system;
+pub mod simulation;
+pub mod replay;
+pub mod input;
+pub mod pathfinding;
+pub mod fov;
+pub mod spawner;
+pub mod ai_system;
+pub mod dialogue;
+pub mod item;
+pub mod pickup_system;
+pub mod inventory;
+pub mod combat_system;
+pub mod death_system;
+pub mod hud;
+pub mod save;
+pub mod widget;
+pub mod pause;
+pub mod settings;
+pub mod audio;
+pub mod events;
+pub mod trigger_system;
+pub mod script;
+pub mod quest;
+pub mod quest_log;
+pub mod clock;
+pub mod lighting;
+pub mod particles;
+pub mod screen_effects;
+pub mod states;
+#[cfg(test)]
+pub mod headless;
 
 use component::*;
+use replay::{Replay, ReplayRecorder};
+use dialogue::Dialogue;
+use inventory::InventoryScreen;
+use item::ItemDefs;
+use quest::QuestDefs;
+use quest_log::QuestLog;
+use hud::Hud;
+use lighting::Lighting;
+use particles::{ParticleDefs, ParticleTextures, Particles};
+use screen_effects::ScreenEffects;
+use menu::StartMenu;
+use widget::Menu;
+use settings::{Settings, SettingsScreen};
+use audio::{AudioBackend, AudioManager};
+use save::SaveError;
+use states::{RootState, States};
+use std::collections::HashMap;
+use input::{Action, Input, InputMap, Stick};
 use std::path;
 use std::env;
-use ggez::event::{KeyCode, KeyMods};
-use ggez::{graphics, Context, ContextBuilder, GameResult};
+use ggez::event::{Axis, Button, GamepadId, KeyCode, KeyMods, MouseButton};
+use ggez::{graphics, timer, Context, ContextBuilder, GameResult};
 use ggez::event::{self, EventHandler};
 use ggez::nalgebra as na;
 use specs::prelude::*;
+use serde::{Deserialize, Serialize};
 
 type Point2 = na::Point2<f32>;
 
+/// Names the game for ggez, which keeps its user config directory under them.
+pub const GAME_ID: &str = "Tiny Topdown Game";
+pub const AUTHOR: &str = "Paul Holtz";
+
 pub const WIDTH_PX: i32 = 960;
 pub const HEIGHT_PX: i32 = 540;
 pub const TL_PX: i32 = 32;
 pub const WIDTH_TL: i32 = WIDTH_PX / TL_PX;
 pub const HEIGHT_TL: i32 = HEIGHT_PX / TL_PX;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
@@ -34,99 +90,360 @@ pub enum Direction {
 }
 
 impl Direction {
-    /// We also create a helper function that will let us convert between a
-    /// `ggez` `Keycode` and the `Direction` that it represents. Of course,
-    /// not every keycode represents a direction, so we return `None` if this
-    /// is the case.
-    pub fn from_keycode(key: KeyCode) -> Option<Direction> {
-        match key {
-            KeyCode::Up | KeyCode::W => Some(Direction::Up),
-            KeyCode::Down | KeyCode::S => Some(Direction::Down),
-            KeyCode::Left | KeyCode::A => Some(Direction::Left),
-            KeyCode::Right | KeyCode::D => Some(Direction::Right),
-            _ => None,
+    /// Picks the direction that best matches a movement vector, favouring horizontal
+    /// movement when both axes are equal. A zero vector has no direction.
+    pub fn from_vector(x: f32, y: f32) -> Option<Direction> {
+        if x == 0.0 && y == 0.0 {
+            None
+        } else if x.abs() >= y.abs() {
+            Some(if x > 0.0 { Direction::Right } else { Direction::Left })
+        } else {
+            Some(if y > 0.0 { Direction::Down } else { Direction::Up })
+        }
+    }
+
+    /// The offset to the neighbouring tile in this direction.
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
         }
     }
-}
 
-pub enum RootState {
-    StartMenu,
-    InGame,
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
 }
 
 pub struct GameState {
-    root: RootState,
+    states: States,
     ecs: World,
     tilesheet: graphics::Image,
     player_sprite_sheet: graphics::Image,
     font: graphics::Font,
-    show_fps: bool,
+    hud: Hud,
+    lighting: Lighting,
+    particles: Particles,
+    particle_textures: ParticleTextures,
+    screen_effects: ScreenEffects,
+    audio: AudioManager,
+    input_map: InputMap,
+    stick: Stick,
+    recorder: Option<ReplayRecorder>,
+    replay: Option<Replay>,
+    dialogue: Option<Dialogue>,
+    inventory: Option<InventoryScreen>,
+    quest_log: Option<QuestLog>,
+    item_sprites: HashMap<String, graphics::Image>,
+    start_menu: StartMenu,
+    pause_menu: Menu,
+    settings: Settings,
+    settings_screen: SettingsScreen,
+    /// The save slot that quick saves go into.
+    save_slot: usize,
 }
 
 impl GameState {
-    pub fn new(ctx: &mut Context) -> GameState {
-        let mut world = World::new();
-        world.register::<Position>();
-        world.register::<Renderable>();
-        world.register::<Player>();
-        world.register::<Viewport>();
-
-        world.create_entity()
-            .with(Position { x: 0.0, y: 0.0 })
-            .with(Renderable {})
-            .with(Player {
-                direction: Direction::Down,
-                velocity: Point2::new(0.0, 0.0),
-                acceleration: Point2::new(0.0, 0.0),
-                animation_index: 0,
-            })
-            .with(Viewport {
-                tiles: vec![],
-                dirty: true,
-            })
-            .build();
-
+    /// Creates the game, starting at the menu unless a replay was given.
+    /// A replay skips the menu entirely since only in game inputs are recorded.
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(ctx: &mut Context, settings: Settings, audio: Box<dyn AudioBackend>, replay: Option<Replay>) -> GameState {
         let player_sprite_sheet_image = graphics::Image::new(ctx, "/basic_guy/basic_guy_sheet.png").expect("could not load image");
         let font = graphics::Font::new(ctx, "/FiraSans-Regular.ttf").expect("could not load font");
         let tileset_image = graphics::Image::new(ctx, "/grass_tileset.png").expect("could not load image");
 
-        let (map, tilesheet) = map::load_basic_map_tmx();
-        world.insert(map);
-        world.insert(tilesheet);
+        let item_defs = match ItemDefs::load(path::Path::new(item::ITEM_DEFS_PATH)) {
+            Ok(item_defs) => item_defs,
+            Err(e) => {
+                println!("Starting without any items, {}", e);
+                ItemDefs::default()
+            }
+        };
+        let item_sprites = item_defs.0.iter()
+            .map(|(id, def)| {
+                let sprite = graphics::Image::new(ctx, &def.sprite)
+                    .or_else(|_| graphics::Image::new(ctx, item::MISSING_SPRITE))
+                    .expect("could not load image");
+                (id.clone(), sprite)
+            })
+            .collect();
+
+        let quest_defs = match QuestDefs::load(path::Path::new(quest::QUEST_DIR)) {
+            Ok(quest_defs) => quest_defs,
+            Err(e) => {
+                println!("Starting without any quests, {}", e);
+                QuestDefs::default()
+            }
+        };
+
+        let particle_defs = match ParticleDefs::load(path::Path::new(particles::PARTICLE_DEFS_PATH)) {
+            Ok(particle_defs) => particle_defs,
+            Err(e) => {
+                println!("Starting without any particles, {}", e);
+                ParticleDefs::default()
+            }
+        };
+        let particle_textures = ParticleTextures::load(ctx, &particle_defs);
+
+        let world = new_game_world(item_defs, quest_defs);
+        let mut hud = Hud::new(ctx).expect("could not load hud");
+        hud.refresh_minimap(ctx, &world.fetch()).expect("could not draw minimap");
+        hud.set_shown(hud::HudWidget::Fps, settings.show_fps);
+        hud.set_shown(hud::HudWidget::Minimap, settings.show_minimap);
+
+        let mut input_map = match InputMap::load(path::Path::new(input::KEY_BINDINGS_PATH)) {
+            Ok((input_map, conflicts)) => {
+                for conflict in conflicts {
+                    println!("Leaving out a key binding, {}", conflict);
+                }
+                input_map
+            }
+            Err(e) => {
+                println!("Falling back to default key bindings, {}", e);
+                InputMap::default()
+            }
+        };
+        settings.apply_keys(&mut input_map);
+
+        // Never record while replaying, since that could overwrite the replay being played back
+        let recorder = match replay {
+            Some(_) => None,
+            None => match ReplayRecorder::create(path::Path::new(replay::RECORDING_PATH)) {
+                Ok(recorder) => Some(recorder),
+                Err(e) => {
+                    println!("Could not start recording inputs: {}", e);
+                    None
+                }
+            },
+        };
+        let first_state = match replay {
+            Some(_) => RootState::InGame,
+            None => RootState::StartMenu,
+        };
+        let start_menu = StartMenu::default();
+        let save_slot = start_menu.slot_times.new_game_slot();
 
         GameState {
-            root: RootState::StartMenu,
+            states: States::new(first_state),
             ecs: world,
             tilesheet: tileset_image,
             player_sprite_sheet: player_sprite_sheet_image,
             font: font,
-            show_fps: true,
+            hud,
+            lighting: Lighting::new(ctx).expect("could not create light map"),
+            particles: Particles::new(particle_defs),
+            particle_textures,
+            screen_effects: ScreenEffects::default(),
+            audio: AudioManager::new(audio, settings.volumes()),
+            input_map,
+            stick: Stick::default(),
+            recorder,
+            replay,
+            dialogue: None,
+            inventory: None,
+            quest_log: None,
+            item_sprites,
+            start_menu,
+            pause_menu: pause::pause_menu(),
+            settings,
+            settings_screen: SettingsScreen::default(),
+            save_slot,
+        }
+    }
+
+    /// Throws away the current world and starts over on the first map.
+    fn new_game(&mut self, ctx: &mut Context) {
+        let item_defs = (*self.ecs.fetch::<ItemDefs>()).clone();
+        let quest_defs = (*self.ecs.fetch::<QuestDefs>()).clone();
+        self.save_slot = self.start_menu.slot_times.new_game_slot();
+        // Every new game is a fresh session, so it gets a fresh recording
+        self.recorder = match ReplayRecorder::create(path::Path::new(replay::RECORDING_PATH)) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                println!("Could not start recording inputs: {}", e);
+                None
+            }
+        };
+        self.enter_world(ctx, new_game_world(item_defs, quest_defs));
+    }
+
+    /// Leaves the game for the start menu. Anything not saved is lost.
+    fn quit_to_title(&mut self) {
+        // Quitting is recorded so that the replay stops right where the session did
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.record(self.ecs.fetch::<simulation::Ticks>().0, Input::Action(Action::Pause));
+        }
+        self.start_menu.refresh_slots();
+        self.states.reset(RootState::StartMenu);
+    }
+
+    /// Throws away the current world and carries on from a save slot instead.
+    fn load_game(&mut self, ctx: &mut Context, slot: usize) -> Result<(), SaveError> {
+        let mut world = save::load(&save::slot_path(slot))?;
+        // Item and quest definitions come from the game rather than the save, they were loaded at startup
+        world.insert((*self.ecs.fetch::<ItemDefs>()).clone());
+        world.insert((*self.ecs.fetch::<QuestDefs>()).clone());
+        self.save_slot = slot;
+        // Replays always start from a new game, so there is no point recording any further
+        if self.recorder.take().is_some() {
+            println!("Stopped recording inputs after loading a save");
+        }
+        self.enter_world(ctx, world);
+        Ok(())
+    }
+
+    fn save_game(&mut self) -> Result<(), SaveError> {
+        save::save(&mut self.ecs, &save::slot_path(self.save_slot))
+    }
+
+    fn enter_world(&mut self, ctx: &mut Context, world: World) {
+        self.ecs = world;
+        self.dialogue = None;
+        self.inventory = None;
+        self.quest_log = None;
+        self.hud.selected_item = None;
+        self.hud.refresh_minimap(ctx, &self.ecs.fetch()).expect("could not draw minimap");
+        self.hud.listen(&self.ecs);
+        self.audio.listen(&self.ecs);
+        self.particles.listen(&self.ecs);
+        self.screen_effects.listen(&self.ecs);
+        self.states.reset(RootState::InGame);
+    }
+}
+
+fn new_game_world(item_defs: ItemDefs, quest_defs: QuestDefs) -> World {
+    let mut world = simulation::create_world();
+    // Items have to be known before the map is loaded, since the map places them
+    world.insert(item_defs);
+    world.insert(quest_defs);
+    simulation::load_map(&mut world, path::Path::new(map::BASIC_MAP_PATH));
+    world
+}
+
+impl GameState {
+    /// Hands an action to whichever state is active, no matter which device it came from.
+    fn handle_action(&mut self, ctx: &mut Context, action: Action) {
+        match self.states.current() {
+            RootState::StartMenu => menu::start_menu_input(self, ctx, action),
+            RootState::InGame => game::in_game_input(self, ctx, Input::Action(action)),
+            RootState::Paused => pause::pause_input(self, action),
+            RootState::Dialogue => dialogue::dialogue_input(self, ctx, action),
+            RootState::Inventory => inventory::inventory_input(self, ctx, action),
+            RootState::QuestLog => quest_log::quest_log_input(self, action),
+            RootState::Settings => settings::settings_input(self, ctx, action),
         }
     }
 }
 
 impl EventHandler for GameState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        match self.root {
+        let result = match self.states.current() {
             RootState::StartMenu => menu::start_menu_update(self, ctx),
             RootState::InGame => game::in_game_update(self, ctx),
-        }
+            RootState::Paused | RootState::Settings => {
+                // Nothing moves while a menu is open, the time passing is simply dropped
+                while timer::check_update_time(ctx, simulation::TICKS_PER_SECOND) {}
+                Ok(())
+            }
+            RootState::Dialogue => dialogue::dialogue_update(self, ctx),
+            RootState::Inventory => inventory::inventory_update(self, ctx),
+            RootState::QuestLog => quest_log::quest_log_update(self, ctx),
+        };
+        audio::audio_update(self, ctx);
+        result
     }
 
+    /// Draws the state on top, along with every state underneath it down to the first one
+    /// which is not an overlay.
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        match self.root {
-            RootState::StartMenu => menu::start_menu_draw(self, ctx),
-            RootState::InGame => game::in_game_draw(self, ctx),
+        for index in 0..self.states.drawn().len() {
+            match self.states.drawn()[index] {
+                RootState::StartMenu => menu::start_menu_draw(self, ctx)?,
+                RootState::InGame => game::in_game_draw(self, ctx)?,
+                RootState::Paused => pause::pause_draw(self, ctx)?,
+                RootState::Dialogue => dialogue::dialogue_draw(self, ctx)?,
+                RootState::Inventory => inventory::inventory_draw(self, ctx)?,
+                RootState::QuestLog => quest_log::quest_log_draw(self, ctx)?,
+                RootState::Settings => settings::settings_draw(self, ctx)?,
+            }
         }
+        // Finally we call graphics::present to cycle the gpu's framebuffer and display
+        // the new frame we just drew and then yield the thread until the next update.
+        graphics::present(ctx)?;
+        timer::yield_now();
+        Ok(())
     }
 
     /// key_down_event gets fired when a key gets pressed.
+    /// Keys are translated into actions straight away, nothing past here knows about keys.
     fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymod: KeyMods, _repeat: bool) {
-        match self.root {
-            RootState::StartMenu => menu::start_menu_input(self, ctx, keycode),
-            RootState::InGame => game::in_game_input(self, ctx, keycode),
+        // Except while rebinding a key in the settings, where the key itself is what matters
+        if settings::is_rebinding(self) {
+            settings::rebind_key(self, keycode);
+        } else if let Some(action) = self.input_map.action(keycode) {
+            self.handle_action(ctx, action);
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, btn: Button, _id: GamepadId) {
+        if let Some(action) = self.input_map.button_action(btn) {
+            self.handle_action(ctx, action);
+        }
+    }
+
+    /// Clicking on a tile while in game walks the player over to it,
+    /// and clicking on a menu item picks it.
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        let (x, y) = window_to_screen(ctx, x, y);
+        match (button, self.states.current()) {
+            (MouseButton::Left, RootState::StartMenu) => menu::start_menu_click(self, ctx, x, y),
+            (MouseButton::Left, RootState::InGame) => {
+                if let Some((tile_x, tile_y)) = game::pick_tile(self, x, y) {
+                    game::in_game_input(self, ctx, Input::MoveTo(tile_x, tile_y));
+                }
+            }
+            (MouseButton::Left, RootState::Paused) => pause::pause_click(self, x, y),
+            (MouseButton::Left, RootState::Settings) => settings::settings_click(self, ctx, x, y),
+            _ => (),
+        }
+    }
+
+    /// Menu items light up as the mouse moves over them.
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
+        let (x, y) = window_to_screen(ctx, x, y);
+        match self.states.current() {
+            RootState::StartMenu => menu::start_menu_hover(self, x, y),
+            RootState::Paused => self.pause_menu.hover(x, y),
+            RootState::Settings => settings::settings_hover(self, x, y),
+            _ => (),
         }
     }
+
+    /// Only the left stick is used, to steer the player around while in game.
+    fn gamepad_axis_event(&mut self, ctx: &mut Context, axis: Axis, value: f32, _id: GamepadId) {
+        // The stick is forgotten everywhere else, so that pushing it around a menu
+        // does not send the player walking off once the game is back on top
+        if self.states.current() != RootState::InGame {
+            self.stick = Stick::default();
+            return;
+        }
+        if let Some(input) = self.stick.update(axis, value, self.input_map.stick_deadzone) {
+            game::in_game_input(self, ctx, input);
+        }
+    }
+}
+
+/// Mouse positions arrive in window pixels, which only match the screen when the window is not scaled.
+fn window_to_screen(ctx: &Context, x: f32, y: f32) -> (f32, f32) {
+    let (width, height) = graphics::drawable_size(ctx);
+    (x * WIDTH_PX as f32 / width, y * HEIGHT_PX as f32 / height)
 }
 
 fn main() -> GameResult {
@@ -138,13 +455,37 @@ fn main() -> GameResult {
         path::PathBuf::from("./assets")
     };
 
-    let (ctx, event_loop) = &mut ContextBuilder::new("Tiny Topdown Game", "Paul Holtz")
-        .window_setup(ggez::conf::WindowSetup::default().title("Tiny Topdown Game"))
-        .window_mode(ggez::conf::WindowMode::default().dimensions(WIDTH_PX as f32, HEIGHT_PX as f32))
-        .add_resource_path(resource_dir)
-        .build()
-		.expect("could not create ggez context");
+    let settings = settings::load_settings();
+    let builder = |audio: bool| {
+        ContextBuilder::new(GAME_ID, AUTHOR)
+            .window_setup(ggez::conf::WindowSetup::default().title("Tiny Topdown Game").vsync(settings.vsync))
+            .window_mode(settings.window())
+            .modules(ggez::conf::ModuleConf { gamepad: true, audio })
+            .add_resource_path(resource_dir.clone())
+    };
+    // Machines without a sound device still get to play, just without any sound
+    let (audio, (ctx, event_loop)) = match builder(true).build() {
+        Ok(built) => (true, built),
+        Err(e) => {
+            println!("Starting without sound, {}", e);
+            (false, builder(false).build().expect("could not create ggez context"))
+        }
+    };
+    let (ctx, event_loop) = &mut (ctx, event_loop);
+    // The game is always drawn at the same size and stretched to fill a scaled up window
+    settings::fit_to_window(ctx)?;
 
-    let state = &mut GameState::new(ctx);
+    // Passing `--replay <file>` plays back a recorded session instead of taking input
+    let replay = env::args()
+        .skip_while(|arg| arg != "--replay")
+        .nth(1)
+        .map(|replay_path| Replay::load(path::Path::new(&replay_path)).expect("could not load replay"));
+
+    let backend: Box<dyn AudioBackend> = if audio {
+        Box::new(audio::GgezBackend::new(ctx))
+    } else {
+        Box::new(audio::NullBackend)
+    };
+    let state = &mut GameState::new(ctx, settings, backend, replay);
     event::run(ctx, event_loop, state)
 }
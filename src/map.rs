@@ -1,30 +1,54 @@
 use std::collections::BTreeMap;
 
-use specs::prelude::*;
-
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
 use tiled::parse;
 use tiled::Map;
 use tiled::LayerData;
 use tiled::LayerTile;
 use tiled::Chunk;
 use tiled::Tileset;
+use tiled::Object;
+use tiled::ObjectShape;
+use tiled::PropertyValue;
 
-use crate::HEIGHT_TL;
 use crate::TL_PX;
 use crate::{WIDTH_TL};
 
-#[derive(Eq, PartialEq, Copy, Clone, Hash, Ord, PartialOrd)]
+/// The map that a new game starts on.
+pub const BASIC_MAP_PATH: &str = "assets/map/basic.tmx";
+
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Ord, PartialOrd, Debug)]
 pub enum TileType {
     Wall,
     Floor,
     Missing
 }
 
+impl TileType {
+    /// Only floor tiles can be walked on. Missing tiles sit outside of the map,
+    /// so treating them as solid keeps everything inside the map boundaries.
+    pub fn is_walkable(&self) -> bool {
+        match self {
+            TileType::Floor => true,
+            TileType::Wall | TileType::Missing => false,
+        }
+    }
+    /// Nothing can be seen through walls, or past the edges of the map.
+    pub fn blocks_sight(&self) -> bool {
+        match self {
+            TileType::Floor => false,
+            TileType::Wall | TileType::Missing => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MapTile {
     pub tile_id: u32,
     pub tile_type: TileType
@@ -38,15 +62,90 @@ pub struct TileSheet {
     pub margin: u32,
 }
 
+/// An object placed on the map in tiled, such as an npc spawn or a patrol route.
+/// Positions are converted into our pixel space, where tiles are centered on their coordinates.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MapObject {
+    pub name: String,
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Absolute points for polylines and polygons, empty for every other shape.
+    pub points: Vec<(f32, f32)>,
+    /// Whether the points form a closed polygon rather than an open polyline.
+    pub closed: bool,
+    pub properties: HashMap<String, String>,
+}
+
+impl MapObject {
+    /// The tile underneath the center of the object.
+    pub fn tile(&self) -> (i32, i32) {
+        (to_tl(self.x + self.width / 2.0), to_tl(self.y + self.height / 2.0))
+    }
+
+    /// The first and last tiles covered by a rectangle, both inclusive.
+    /// Rectangles which only cover part of a tile still take up the whole tile.
+    pub fn area(&self) -> ((i32, i32), (i32, i32)) {
+        let half_tile = TL_PX as f32 / 2.0;
+        let first = |start: f32| ((start + half_tile) / TL_PX as f32).floor() as i32;
+        let last = |start: f32, size: f32| (((start + half_tile + size) / TL_PX as f32).ceil() as i32 - 1).max(first(start));
+        ((first(self.x), first(self.y)), (last(self.x, self.width), last(self.y, self.height)))
+    }
+
+    /// The tiles underneath each point of a polyline or polygon.
+    pub fn point_tiles(&self) -> Vec<(i32, i32)> {
+        self.points.iter().map(|(x, y)| (to_tl(*x), to_tl(*y))).collect()
+    }
+
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(|value| value.as_str())
+    }
+}
+
+/// Everything loaded from a tiled map, converted into our own data structures.
+pub struct LoadedMap {
+    pub tiles: BTreeMap<(i32, i32), MapTile>,
+    pub tilesheet: TileSheet,
+    pub objects: Vec<MapObject>,
+    /// Custom properties set on the map itself, such as which music plays on it.
+    pub properties: HashMap<String, String>,
+}
+
 pub fn to_px(tl: i32) -> i32 {
     tl * TL_PX
 }
 
+/// Converts a pixel coordinate into the tile containing it.
+/// Tiles are centered on their pixel coordinate, so we round rather than floor.
+pub fn to_tl(px: f32) -> i32 {
+    (px / TL_PX as f32).round() as i32
+}
+
+/// Looks up the type of the tile at the given tile coordinate.
+/// Anything outside of the loaded map is reported as `Missing`.
+pub fn tile_type_at(map: &BTreeMap<(i32, i32), MapTile>, x: i32, y: i32) -> TileType {
+    match map.get(&(x, y)) {
+        Some(map_tile) => map_tile.tile_type,
+        None => TileType::Missing,
+    }
+}
+
+/// The closest tile to the given one which can be walked on, or nothing if the whole map is solid.
+pub fn nearest_walkable(map: &BTreeMap<(i32, i32), MapTile>, tile: (i32, i32)) -> Option<(i32, i32)> {
+    map.iter()
+        .filter(|(_tile, map_tile)| map_tile.tile_type.is_walkable())
+        .map(|(nearby, _map_tile)| *nearby)
+        .min_by_key(|nearby| ((nearby.0 - tile.0).pow(2) + (nearby.1 - tile.1).pow(2), *nearby))
+}
+
 /// Load the map from file using the tiled library.
 /// Afterwards, convert both the layer and tileset into our own data format
 /// so that we are not using tiled data structures all over the place.
-pub fn load_basic_map_tmx() -> (BTreeMap<(i32, i32), MapTile>, TileSheet) {
-    let file = File::open(&Path::new("assets/map/basic.tmx")).unwrap();
+#[allow(clippy::needless_borrow)]
+pub fn load_map_tmx(path: &Path) -> LoadedMap {
+    let file = File::open(path).unwrap();
     let reader = BufReader::new(file);
     let map = parse(reader).unwrap();
     println!("Loaded map with dimensions {}x{} and tile dimensions {}x{}", map.width, map.height, map.tile_width, map.tile_height);
@@ -70,7 +169,57 @@ pub fn load_basic_map_tmx() -> (BTreeMap<(i32, i32), MapTile>, TileSheet) {
     let first_tileset = map.tilesets.first().expect("Map parser can only process exactly one tileset");
     let first_tilesheet = load_basic_tilesheet(&first_tileset);
 
-    (basic_map, first_tilesheet)
+    let objects = map.object_groups.iter()
+        .flat_map(|group| group.objects.iter())
+        .map(load_map_object)
+        .collect();
+
+    let properties = map.properties.iter()
+        .map(|(name, value)| (name.clone(), load_property_value(value)))
+        .collect();
+
+    LoadedMap {
+        tiles: basic_map,
+        tilesheet: first_tilesheet,
+        objects,
+        properties,
+    }
+}
+
+/// Transforms an object from tiled into our internal model.
+/// Tiled measures from the top-left corner of the map, while our tiles are
+/// centered on their pixel coordinates, so everything shifts by half a tile.
+pub fn load_map_object(object: &Object) -> MapObject {
+    let half_tile = TL_PX as f32 / 2.0;
+    let (points, closed) = match &object.shape {
+        ObjectShape::Polyline { points } => (points.clone(), false),
+        ObjectShape::Polygon { points } => (points.clone(), true),
+        _ => (vec![], false),
+    };
+    MapObject {
+        name: object.name.clone(),
+        kind: object.obj_type.clone(),
+        x: object.x - half_tile,
+        y: object.y - half_tile,
+        width: object.width,
+        height: object.height,
+        points: points.iter().map(|(x, y)| (object.x + x - half_tile, object.y + y - half_tile)).collect(),
+        closed,
+        properties: object.properties.iter()
+            .map(|(name, value)| (name.clone(), load_property_value(value)))
+            .collect(),
+    }
+}
+
+/// Flattens a tiled property into a string, leaving interpretation up to whoever reads it.
+fn load_property_value(value: &PropertyValue) -> String {
+    match value {
+        PropertyValue::BoolValue(value) => value.to_string(),
+        PropertyValue::FloatValue(value) => value.to_string(),
+        PropertyValue::IntValue(value) => value.to_string(),
+        PropertyValue::ColorValue(value) => value.to_string(),
+        PropertyValue::StringValue(value) => value.clone(),
+    }
 }
 
 /// Transforms a Tileset from tiled into our internal model
@@ -86,9 +235,27 @@ pub fn load_basic_tilesheet(tileset: &Tileset) -> TileSheet {
     }
 }
 
+/// Determines the tile type for a gid using the "Type" field set on the tile in Tiled.
+/// Tiles marked as `wall` are solid, everything else can be walked on.
+pub fn load_tile_type(map: &Map, gid: u32) -> TileType {
+    for tileset in map.tilesets.iter() {
+        if gid < tileset.first_gid {
+            continue;
+        }
+        let local_id = gid - tileset.first_gid;
+        if let Some(tile) = tileset.tiles.iter().find(|tile| tile.id == local_id) {
+            if tile.tile_type.as_deref() == Some("wall") {
+                return TileType::Wall;
+            }
+        }
+    }
+    TileType::Floor
+}
+
 /// Load a finite map from tiled into the internal map structure.
 /// Tiles are linked to textures via gids to avoid sharing too many explicit references.
-pub fn load_basic_map_tmx_finite(_map: &Map, tiles: &Vec<Vec<LayerTile>>) -> BTreeMap<(i32, i32), MapTile> {
+#[allow(clippy::ptr_arg, clippy::explicit_counter_loop)]
+pub fn load_basic_map_tmx_finite(map: &Map, tiles: &Vec<Vec<LayerTile>>) -> BTreeMap<(i32, i32), MapTile> {
     let mut basic_map = BTreeMap::new();
     let mut x = 0;
     let mut y = 0;
@@ -96,7 +263,7 @@ pub fn load_basic_map_tmx_finite(_map: &Map, tiles: &Vec<Vec<LayerTile>>) -> BTr
         for tile in row.iter() {
             basic_map.insert((x, y), MapTile {
                 tile_id: tile.gid,
-                tile_type: TileType::Floor
+                tile_type: load_tile_type(map, tile.gid)
             });
             x += 1;
         }
@@ -112,26 +279,27 @@ pub fn load_basic_map_tmx_infinite(_map: &Map, _tiles: &HashMap<(i32, i32), Chun
     BTreeMap::new()
 }
 
-/// Creates a basic map with just floor. Doesn't do any objects yet.
-pub fn load_basic_map(_ecs: &mut World) -> BTreeMap<(i32, i32), MapTile> {
-    let mut map = BTreeMap::new();
-
-    for x in (-1 * WIDTH_TL)..WIDTH_TL {
-        for y in (-1 * HEIGHT_TL)..HEIGHT_TL {
-            map.insert((x, y), MapTile {
-                tile_id: 0,
-                tile_type: TileType::Floor
-            });
-        }
-    }
-    map.insert((10, 10), MapTile { tile_id: 0, tile_type: TileType::Wall });
-    map.insert((9, 9), MapTile { tile_id: 0, tile_type: TileType::Wall });
-    map.insert((6, 10), MapTile { tile_id: 0, tile_type: TileType::Wall });
-    map.insert((7, 5), MapTile { tile_id: 0, tile_type: TileType::Wall });
-    map
-}
-
 /// Transforms an xy coordinate into a packed index.
 pub fn xy_idx(x: i32, y: i32) -> usize {
     (y as usize * WIDTH_TL as usize) + x as usize
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::Direction;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    #[test]
+    fn basic_map_loads_from_disk() {
+        let mut game = Headless::with_map_file(Path::new(BASIC_MAP_PATH));
+        assert_eq!(game.ecs.fetch::<BTreeMap<(i32, i32), MapTile>>().len(), 100 * 100);
+        for _ in 0..10 {
+            game.press(Direction::Right);
+            game.step(5);
+        }
+        assert_eq!(game.player_tile(), (1, 0));
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+
+use tiled::Tileset;
+
+use crate::map::{self, LoadedMap, MapTile, TileType};
+
+/// Maps named like `generate:caves:42` are generated from a seed rather than loaded from disk,
+/// using one of `caves`, `rooms` or `overworld`. They can be warped to and saved like any other map.
+pub const GENERATED_PREFIX: &str = "generate:";
+
+/// The tileset generated maps are drawn with. The generator finds the tile for each kind of ground
+/// by the type set on it in tiled, so the tileset can be rearranged without touching the generator.
+pub const TILESET_PATH: &str = "assets/map/basic.tsx";
+
+/// The tile drawn for each kind of ground. Grass only has edges along its bottom and right,
+/// so dirt to the top or left of grass simply meets it without one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TileRoles {
+    pub grass: u32,
+    pub grass_above_dirt: u32,
+    pub grass_left_of_dirt: u32,
+    /// Grass with dirt only showing in its bottom right corner.
+    pub grass_corner: u32,
+    pub dirt: u32,
+    pub wall: u32,
+}
+
+impl TileRoles {
+    /// Finds the tiles typed `grass`, `grass_above_dirt`, `grass_left_of_dirt`, `grass_corner`, `dirt`
+    /// and `wall` in a tileset. Every one of them has to be there.
+    pub fn from_tileset(tileset: &Tileset) -> TileRoles {
+        let find = |role: &str| {
+            tileset.tiles.iter()
+                .find(|tile| tile.tile_type.as_deref() == Some(role))
+                .map(|tile| tileset.first_gid + tile.id)
+                .unwrap_or_else(|| panic!("Tileset {} has no tile typed {}", tileset.name, role))
+        };
+        TileRoles {
+            grass: find("grass"),
+            grass_above_dirt: find("grass_above_dirt"),
+            grass_left_of_dirt: find("grass_left_of_dirt"),
+            grass_corner: find("grass_corner"),
+            dirt: find("dirt"),
+            wall: find("wall"),
+        }
+    }
+}
+
+/// Loads the tileset generated maps are drawn with.
+pub fn load_tileset() -> Tileset {
+    let file = File::open(Path::new(TILESET_PATH)).unwrap();
+    tiled::parse_tileset(BufReader::new(file), 1).unwrap()
+}
+
+/// How much of a cave starts out as wall before being smoothed into caverns.
+const CAVE_FILL: f32 = 0.45;
+const CAVE_SMOOTHING: u32 = 5;
+/// Areas are split in two until they are this many tiles across, each ending up with a room.
+const MIN_AREA_TL: i32 = 10;
+const MIN_ROOM_TL: i32 = 4;
+/// Terrain lower than this is worn down to dirt, higher than `THICKET_HEIGHT` it is too overgrown to walk through.
+const DIRT_HEIGHT: f32 = 0.38;
+const THICKET_HEIGHT: f32 = 0.68;
+/// How many tiles across the largest features of the overworld are.
+const OVERWORLD_SCALE_TL: f32 = 24.0;
+const NOISE_OCTAVES: u32 = 4;
+
+/// The ways of generating a map.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Generator {
+    /// Caverns grown by a cellular automaton.
+    Caves,
+    /// Rooms joined by corridors, laid out by splitting the map up into smaller and smaller areas.
+    Rooms,
+    /// Open grassland with dirt clearings and impassable thickets, shaped by noise.
+    Overworld,
+}
+
+impl Generator {
+    fn size(self) -> (i32, i32) {
+        match self {
+            Generator::Caves | Generator::Rooms => (64, 48),
+            Generator::Overworld => (96, 96),
+        }
+    }
+
+    /// Underground maps are covered in fog, so they are explored a little at a time.
+    fn has_fog(self) -> bool {
+        match self {
+            Generator::Caves | Generator::Rooms => true,
+            Generator::Overworld => false,
+        }
+    }
+}
+
+/// Reads which generator and seed a generated map is named after, or nothing for maps on disk.
+pub fn parse_source(path: &str) -> Option<(Generator, u64)> {
+    let (generator, seed) = path.strip_prefix(GENERATED_PREFIX)?.split_once(':')?;
+    let generator = match generator {
+        "caves" => Generator::Caves,
+        "rooms" => Generator::Rooms,
+        "overworld" => Generator::Overworld,
+        _ => return None,
+    };
+    Some((generator, seed.parse().ok()?))
+}
+
+/// What every tile of a generated map is made of, before picking the tiles it is drawn with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Ground {
+    /// Too overgrown to walk through, drawn as grass.
+    Wall,
+    Grass,
+    Dirt,
+}
+
+#[derive(Clone)]
+struct Grid {
+    width: i32,
+    height: i32,
+    cells: Vec<Ground>,
+}
+
+impl Grid {
+    fn new(width: i32, height: i32, ground: Ground) -> Grid {
+        Grid { width, height, cells: vec![ground; (width * height) as usize] }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    /// Everything outside of the grid counts as wall.
+    fn get(&self, x: i32, y: i32) -> Ground {
+        if self.contains(x, y) { self.cells[(y * self.width + x) as usize] } else { Ground::Wall }
+    }
+
+    fn set(&mut self, x: i32, y: i32, ground: Ground) {
+        if self.contains(x, y) {
+            self.cells[(y * self.width + x) as usize] = ground;
+        }
+    }
+
+    fn walls_around(&self, x: i32, y: i32) -> usize {
+        (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|(dx, dy)| (*dx, *dy) != (0, 0) && self.get(x + dx, y + dy) == Ground::Wall)
+            .count()
+    }
+}
+
+/// Generates a map from a seed. The same generator and seed always give the same map.
+pub fn generate(generator: Generator, seed: u64, roles: &TileRoles) -> BTreeMap<(i32, i32), MapTile> {
+    let (width, height) = generator.size();
+    let mut rng = Pcg32::seed_from_u64(seed);
+    let mut grid = match generator {
+        Generator::Caves => caves(&mut rng, width, height),
+        Generator::Rooms => rooms(&mut rng, width, height),
+        Generator::Overworld => overworld(seed, width, height),
+    };
+    // Anywhere that cannot be walked to from the rest of the map is filled in
+    keep_largest_region(&mut grid);
+    to_tiles(&grid, roles)
+}
+
+/// Generates a map the same way as it would be loaded from tiled, with nothing placed on it.
+pub fn generate_map(generator: Generator, seed: u64) -> LoadedMap {
+    let mut properties = HashMap::new();
+    if generator.has_fog() {
+        properties.insert("fog".to_string(), "true".to_string());
+    }
+    let tileset = load_tileset();
+    LoadedMap {
+        tiles: generate(generator, seed, &TileRoles::from_tileset(&tileset)),
+        tilesheet: map::load_basic_tilesheet(&tileset),
+        objects: vec![],
+        properties,
+    }
+}
+
+/// Scatters walls at random and then smooths them out, each tile becoming wall when most of its
+/// neighbours are, until only caverns are left. The edge of the map is always wall.
+fn caves(rng: &mut Pcg32, width: i32, height: i32) -> Grid {
+    let mut grid = Grid::new(width, height, Ground::Wall);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            if rng.gen::<f32>() >= CAVE_FILL {
+                grid.set(x, y, Ground::Dirt);
+            }
+        }
+    }
+    for _ in 0..CAVE_SMOOTHING {
+        let before = grid.clone();
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                match before.walls_around(x, y) {
+                    0..=3 => grid.set(x, y, Ground::Dirt),
+                    4 => (),
+                    _ => grid.set(x, y, Ground::Wall),
+                }
+            }
+        }
+    }
+    grid
+}
+
+/// A rectangle of tiles, from its top left corner.
+#[derive(Clone, Copy, Debug)]
+struct Area {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+/// Splits the map up into smaller and smaller areas with a room in each, joining the rooms
+/// on either side of every split with a corridor.
+fn rooms(rng: &mut Pcg32, width: i32, height: i32) -> Grid {
+    let mut grid = Grid::new(width, height, Ground::Wall);
+    split(rng, &mut grid, Area { x: 1, y: 1, width: width - 2, height: height - 2 });
+    grid
+}
+
+/// Digs out the rooms of an area, returning a tile in one of them for corridors to lead to.
+fn split(rng: &mut Pcg32, grid: &mut Grid, area: Area) -> (i32, i32) {
+    let across = area.width >= area.height;
+    let length = if across { area.width } else { area.height };
+    if length < MIN_AREA_TL * 2 {
+        return dig_room(rng, grid, area);
+    }
+    let at = rng.gen_range(MIN_AREA_TL, length - MIN_AREA_TL + 1);
+    let (first, second) = if across {
+        (Area { width: at, ..area }, Area { x: area.x + at, width: area.width - at, ..area })
+    } else {
+        (Area { height: at, ..area }, Area { y: area.y + at, height: area.height - at, ..area })
+    };
+    let from = split(rng, grid, first);
+    let to = split(rng, grid, second);
+    dig_corridor(rng, grid, from, to);
+    if rng.gen() { from } else { to }
+}
+
+/// Digs a room somewhere inside an area, leaving at least a tile of wall around it.
+fn dig_room(rng: &mut Pcg32, grid: &mut Grid, area: Area) -> (i32, i32) {
+    let width = rng.gen_range(MIN_ROOM_TL, (area.width - 1).max(MIN_ROOM_TL + 1));
+    let height = rng.gen_range(MIN_ROOM_TL, (area.height - 1).max(MIN_ROOM_TL + 1));
+    let x = area.x + rng.gen_range(0, (area.width - width).max(1));
+    let y = area.y + rng.gen_range(0, (area.height - height).max(1));
+    for room_y in y..y + height {
+        for room_x in x..x + width {
+            grid.set(room_x, room_y, Ground::Dirt);
+        }
+    }
+    (x + width / 2, y + height / 2)
+}
+
+/// Digs a corridor between two tiles, going across and then down or down and then across.
+fn dig_corridor(rng: &mut Pcg32, grid: &mut Grid, from: (i32, i32), to: (i32, i32)) {
+    let corner = if rng.gen() { (to.0, from.1) } else { (from.0, to.1) };
+    for (start, end) in [(from, corner), (corner, to)] {
+        for x in start.0.min(end.0)..=start.0.max(end.0) {
+            for y in start.1.min(end.1)..=start.1.max(end.1) {
+                grid.set(x, y, Ground::Dirt);
+            }
+        }
+    }
+}
+
+/// Lays grass over rolling terrain, worn down to dirt in the hollows and overgrown on the heights.
+fn overworld(seed: u64, width: i32, height: i32) -> Grid {
+    let mut grid = Grid::new(width, height, Ground::Grass);
+    for y in 0..height {
+        for x in 0..width {
+            let terrain = fractal_noise(seed, x as f32 / OVERWORLD_SCALE_TL, y as f32 / OVERWORLD_SCALE_TL);
+            if terrain < DIRT_HEIGHT {
+                grid.set(x, y, Ground::Dirt);
+            } else if terrain > THICKET_HEIGHT {
+                grid.set(x, y, Ground::Wall);
+            }
+        }
+    }
+    grid
+}
+
+/// A value between 0.0 and 1.0 for every point of a grid, always the same for the same seed and point.
+fn lattice(seed: u64, x: i32, y: i32) -> f32 {
+    // Mixes the bits of the seed and point thoroughly, as in splitmix64
+    let mut hash = seed ^ ((x as u32 as u64) << 32) ^ (y as u32 as u64);
+    hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smoothly blends between the values of the four grid points around a point.
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (cell_x, cell_y) = (x.floor() as i32, y.floor() as i32);
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (blend_x, blend_y) = (smooth(x - cell_x as f32), smooth(y - cell_y as f32));
+    let top = lattice(seed, cell_x, cell_y) + (lattice(seed, cell_x + 1, cell_y) - lattice(seed, cell_x, cell_y)) * blend_x;
+    let bottom = lattice(seed, cell_x, cell_y + 1) + (lattice(seed, cell_x + 1, cell_y + 1) - lattice(seed, cell_x, cell_y + 1)) * blend_x;
+    top + (bottom - top) * blend_y
+}
+
+/// Layers noise at finer and finer scales over each other, for terrain with both broad shapes and small details.
+fn fractal_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (mut total, mut weight, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for octave in 0..NOISE_OCTAVES {
+        total += value_noise(seed.wrapping_add(octave as u64), x * frequency, y * frequency) * amplitude;
+        weight += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    total / weight
+}
+
+/// Fills in every open area apart from the largest, so that every open tile left can be walked to.
+fn keep_largest_region(grid: &mut Grid) {
+    let mut region = vec![usize::MAX; grid.cells.len()];
+    let mut sizes = vec![];
+    for start in 0..grid.cells.len() {
+        if grid.cells[start] == Ground::Wall || region[start] != usize::MAX {
+            continue;
+        }
+        let id = sizes.len();
+        let mut size = 0;
+        let mut open = VecDeque::from(vec![start]);
+        region[start] = id;
+        while let Some(index) = open.pop_front() {
+            size += 1;
+            let (x, y) = (index as i32 % grid.width, index as i32 / grid.width);
+            for (dx, dy) in [(0, -1), (0, 1), (-1, 0), (1, 0)] {
+                let (next_x, next_y) = (x + dx, y + dy);
+                let next = (next_y * grid.width + next_x) as usize;
+                if grid.get(next_x, next_y) != Ground::Wall && region[next] == usize::MAX {
+                    region[next] = id;
+                    open.push_back(next);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    // The first of the largest regions is kept, so that ties always go the same way
+    let largest = (0..sizes.len()).rev().max_by_key(|id| sizes[*id]);
+    for (cell, region) in grid.cells.iter_mut().zip(region) {
+        if *cell != Ground::Wall && Some(region) != largest {
+            *cell = Ground::Wall;
+        }
+    }
+}
+
+/// Picks the tile every part of the grid is drawn with, using the edged grass tiles wherever dirt meets it.
+fn to_tiles(grid: &Grid, roles: &TileRoles) -> BTreeMap<(i32, i32), MapTile> {
+    let mut tiles = BTreeMap::new();
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let ground = grid.get(x, y);
+            let dirt = |dx: i32, dy: i32| grid.get(x + dx, y + dy) == Ground::Dirt;
+            let tile_id = match ground {
+                Ground::Dirt => roles.dirt,
+                Ground::Wall => roles.wall,
+                Ground::Grass if dirt(0, 1) => roles.grass_above_dirt,
+                Ground::Grass if dirt(1, 0) => roles.grass_left_of_dirt,
+                Ground::Grass if dirt(1, 1) => roles.grass_corner,
+                Ground::Grass => roles.grass,
+            };
+            let tile_type = if ground == Ground::Wall { TileType::Wall } else { TileType::Floor };
+            tiles.insert((x, y), MapTile { tile_id, tile_type });
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::map::{self, MapTile, TileType};
+    use crate::simulation;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use crate::visibility_system::{self, TileVisibility};
+
+    /// Every walkable tile of a map which can be walked to from the first one.
+    fn walkable_from_first(map: &BTreeMap<(i32, i32), MapTile>) -> usize {
+        let start = map.iter().find(|(_tile, map_tile)| map_tile.tile_type.is_walkable()).map(|(tile, _map_tile)| *tile);
+        let mut reached = std::collections::BTreeSet::new();
+        let mut open: Vec<(i32, i32)> = start.into_iter().collect();
+        while let Some((x, y)) = open.pop() {
+            if !map::tile_type_at(map, x, y).is_walkable() || !reached.insert((x, y)) {
+                continue;
+            }
+            open.extend_from_slice(&[(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]);
+        }
+        reached.len()
+    }
+
+    #[test]
+    fn generated_maps_are_the_same_for_a_seed_and_can_be_walked_all_over() {
+        let roles = TileRoles::from_tileset(&load_tileset());
+        let floors = [roles.grass, roles.grass_above_dirt, roles.grass_left_of_dirt, roles.grass_corner, roles.dirt];
+        assert!(!floors.contains(&roles.wall), "walls are drawn like something walkable");
+        for generator in [Generator::Caves, Generator::Rooms, Generator::Overworld] {
+            let map = generate(generator, 42, &roles);
+            assert_eq!(map, generate(generator, 42, &roles), "{:?} differs for the same seed", generator);
+            assert_ne!(map, generate(generator, 43, &roles), "{:?} ignores its seed", generator);
+
+            let walkable = map.values().filter(|map_tile| map_tile.tile_type.is_walkable()).count();
+            assert!(walkable > map.len() / 5, "{:?} has only {} walkable tiles", generator, walkable);
+            assert_eq!(walkable_from_first(&map), walkable, "{:?} has tiles which cannot be walked to", generator);
+
+            // Walls have a tile of their own, and grass picks up an edge wherever dirt is below it, to its right or off its corner
+            for (&(x, y), map_tile) in map.iter() {
+                let dirt = |dx: i32, dy: i32| map.get(&(x + dx, y + dy)).is_some_and(|tile| tile.tile_id == roles.dirt);
+                let expected = match () {
+                    _ if map_tile.tile_type == TileType::Wall => roles.wall,
+                    _ if map_tile.tile_id == roles.dirt => roles.dirt,
+                    _ if dirt(0, 1) => roles.grass_above_dirt,
+                    _ if dirt(1, 0) => roles.grass_left_of_dirt,
+                    _ if dirt(1, 1) => roles.grass_corner,
+                    _ => roles.grass,
+                };
+                assert_eq!(map_tile.tile_id, expected, "{:?} has the wrong tile at {:?}", generator, (x, y));
+                assert_eq!(map_tile.tile_type.is_walkable(), map_tile.tile_type == TileType::Floor);
+            }
+        }
+
+        assert_eq!(parse_source("generate:caves:7"), Some((Generator::Caves, 7)));
+        assert_eq!(parse_source("generate:volcano:7"), None);
+        assert_eq!(parse_source(map::BASIC_MAP_PATH), None);
+        assert_eq!(generate_map(Generator::Rooms, 1).properties.get("fog").map(String::as_str), Some("true"));
+        assert!(!generate_map(Generator::Overworld, 1).properties.contains_key("fog"));
+    }
+
+    #[test]
+    fn warping_to_a_generated_map_lands_somewhere_walkable_and_survives_saving() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.step(1);
+        simulation::warp_player(&mut game.ecs, Some("generate:caves:7"), (0, 0));
+        game.step(1);
+        let tile = game.player_tile();
+        assert_eq!(game.ecs.fetch::<simulation::MapPath>().0, "generate:caves:7");
+        assert!(map::tile_type_at(&game.ecs.fetch(), tile.0, tile.1).is_walkable());
+        assert_eq!(visibility_system::tile_visibility(&game.ecs, tile), TileVisibility::Visible);
+        assert_eq!(visibility_system::tile_visibility(&game.ecs, (60, 44)), TileVisibility::Hidden);
+
+        let tiles = (*game.ecs.fetch::<BTreeMap<(i32, i32), MapTile>>()).clone();
+        game.reload();
+        assert_eq!(*game.ecs.fetch::<BTreeMap<(i32, i32), MapTile>>(), tiles);
+        assert_eq!(game.player_tile(), tile);
+
+        simulation::warp_player(&mut game.ecs, Some("generate:volcano:7"), (0, 0));
+        assert_eq!(game.ecs.fetch::<simulation::MapPath>().0, "generate:caves:7", "unknown generators are missing maps");
+    }
+}
@@ -1,14 +1,176 @@
-use crate::GameState;
-use crate::RootState;
-use crate::Point2;
+use crate::{GameState, Point2, WIDTH_PX};
+use crate::audio::Sound;
+use crate::save::{SlotTimes, SAVE_SLOTS};
+use crate::settings;
+use crate::widget::Menu;
 use ggez::{graphics, Context, GameResult, event, timer};
-use ggez::event::KeyCode;
+use crate::input::Action;
+use std::time::SystemTime;
 
-pub fn start_menu_input(state: &mut GameState, ctx: &mut Context, keycode: KeyCode) {
-    match keycode {
-        KeyCode::Return => state.root = RootState::InGame,
-        KeyCode::Escape => event::quit(ctx),
-        _ => (),
+const MENU_WIDTH_PX: f32 = 320.0;
+const MENU_TOP_PX: f32 = 260.0;
+const TEXT_FONT_SIZE: f32 = 20.0;
+const FADED: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+const CREDITS: &[&str] = &[
+    "Tiny Topdown Game by Paul Holtz",
+    "Built with ggez, specs and tiled",
+    "Fira Sans by Mozilla",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StartOption {
+    NewGame,
+    Continue,
+    LoadSlot,
+    Settings,
+    Credits,
+    Quit,
+}
+
+impl StartOption {
+    pub const ALL: [StartOption; 6] = [
+        StartOption::NewGame,
+        StartOption::Continue,
+        StartOption::LoadSlot,
+        StartOption::Settings,
+        StartOption::Credits,
+        StartOption::Quit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            StartOption::NewGame => "New Game",
+            StartOption::Continue => "Continue",
+            StartOption::LoadSlot => "Load Slot",
+            StartOption::Settings => "Settings",
+            StartOption::Credits => "Credits",
+            StartOption::Quit => "Quit",
+        }
+    }
+}
+
+/// Which page of the start menu is showing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StartPage {
+    Options,
+    Slots,
+    Credits,
+}
+
+/// The start menu, along with the pages it opens for picking a save slot and reading the credits.
+#[derive(Debug)]
+pub struct StartMenu {
+    page: StartPage,
+    options: Menu,
+    /// One item per save slot, followed by going back.
+    slots: Menu,
+    credits: Menu,
+    message: Option<String>,
+    /// The save slots as they were on disk when last refreshed.
+    pub slot_times: SlotTimes,
+}
+
+impl StartMenu {
+    pub fn new() -> StartMenu {
+        let left = (WIDTH_PX as f32 - MENU_WIDTH_PX) / 2.0;
+        let labels: Vec<&str> = StartOption::ALL.iter().map(|option| option.label()).collect();
+        let options = Menu::new(&labels, Point2::new(left, MENU_TOP_PX), MENU_WIDTH_PX);
+        let mut slot_labels = vec![""; SAVE_SLOTS];
+        slot_labels.push("Back");
+        let slots = Menu::new(&slot_labels, Point2::new(left, MENU_TOP_PX), MENU_WIDTH_PX);
+        let credits_top = MENU_TOP_PX + (CREDITS.len() + 1) as f32 * TEXT_FONT_SIZE * 1.5;
+        let credits = Menu::new(&["Back"], Point2::new(left, credits_top), MENU_WIDTH_PX);
+        let mut menu = StartMenu { page: StartPage::Options, options, slots, credits, message: None, slot_times: SlotTimes::default() };
+        menu.refresh_slots();
+        menu
+    }
+
+    /// Catches up with the save slots on disk, which change whenever the game is saved.
+    pub fn refresh_slots(&mut self) {
+        self.slot_times = SlotTimes::scan();
+        for (slot, time) in self.slot_times.times.iter().copied().enumerate() {
+            let saved = time.map_or("Empty".to_string(), saved_ago);
+            self.slots.set_label(slot, format!("Slot {}  {}", slot + 1, saved));
+            self.slots.set_enabled(slot, time.is_some());
+        }
+        let any_saved = self.slot_times.latest_slot().is_some();
+        self.options.set_enabled(StartOption::Continue as usize, any_saved);
+        self.options.set_enabled(StartOption::LoadSlot as usize, any_saved);
+    }
+
+    fn page_menu(&mut self) -> &mut Menu {
+        match self.page {
+            StartPage::Options => &mut self.options,
+            StartPage::Slots => &mut self.slots,
+            StartPage::Credits => &mut self.credits,
+        }
+    }
+}
+
+impl Default for StartMenu {
+    fn default() -> StartMenu {
+        StartMenu::new()
+    }
+}
+
+pub fn start_menu_input(state: &mut GameState, ctx: &mut Context, action: Action) {
+    state.start_menu.message = None;
+    if action == Action::Pause {
+        go_back(state, ctx);
+    } else if let Some(index) = state.start_menu.page_menu().input(action) {
+        choose(state, ctx, index);
+    }
+}
+
+pub fn start_menu_hover(state: &mut GameState, x: f32, y: f32) {
+    state.start_menu.page_menu().hover(x, y);
+}
+
+pub fn start_menu_click(state: &mut GameState, ctx: &mut Context, x: f32, y: f32) {
+    state.start_menu.message = None;
+    if let Some(index) = state.start_menu.page_menu().click(x, y) {
+        choose(state, ctx, index);
+    }
+}
+
+/// Leaves the page that is showing, quitting from the first page.
+fn go_back(state: &mut GameState, ctx: &mut Context) {
+    match state.start_menu.page {
+        StartPage::Options => event::quit(ctx),
+        StartPage::Slots | StartPage::Credits => state.start_menu.page = StartPage::Options,
+    }
+}
+
+fn choose(state: &mut GameState, ctx: &mut Context, index: usize) {
+    state.audio.play_sound(Sound::MenuClick);
+    match state.start_menu.page {
+        StartPage::Options => match StartOption::ALL[index] {
+            StartOption::NewGame => state.new_game(ctx),
+            StartOption::Continue => {
+                if let Some(slot) = state.start_menu.slot_times.latest_slot() {
+                    load_slot(state, ctx, slot);
+                }
+            }
+            StartOption::LoadSlot => {
+                let menu = &mut state.start_menu;
+                menu.refresh_slots();
+                menu.slots.select(menu.slot_times.latest_slot().unwrap_or(0));
+                menu.page = StartPage::Slots;
+            }
+            StartOption::Settings => settings::open_settings(state),
+            StartOption::Credits => state.start_menu.page = StartPage::Credits,
+            StartOption::Quit => event::quit(ctx),
+        },
+        StartPage::Slots if index < SAVE_SLOTS => load_slot(state, ctx, index),
+        StartPage::Slots | StartPage::Credits => go_back(state, ctx),
+    }
+}
+
+fn load_slot(state: &mut GameState, ctx: &mut Context, slot: usize) {
+    if let Err(e) = state.load_game(ctx, slot) {
+        println!("Could not load slot {}: {}", slot + 1, e);
+        state.start_menu.message = Some(format!("Could not load slot {}", slot + 1));
     }
 }
 
@@ -22,10 +184,36 @@ pub fn start_menu_update(_state: &mut GameState, ctx: &mut Context) -> GameResul
 
 pub fn start_menu_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
     graphics::clear(ctx, [0.0, 0.0, 0.0, 1.0].into());
-    let menu_pos = Point2::new(380.0, 260.0);
     let menu_title = graphics::Text::new(("Tiny Topdown Game", state.font, 32.0));
-    graphics::draw(ctx, &menu_title, (menu_pos, 0.0, graphics::WHITE))?;
-    graphics::present(ctx)?;
-    ggez::timer::yield_now();
+    let title_left = (WIDTH_PX as f32 - menu_title.width(ctx) as f32) / 2.0;
+    graphics::draw(ctx, &menu_title, (Point2::new(title_left, 180.0), 0.0, graphics::WHITE))?;
+
+    let font = state.font;
+    let menu = &mut state.start_menu;
+    if menu.page == StartPage::Credits {
+        for (line, credit) in CREDITS.iter().enumerate() {
+            let text = graphics::Text::new((*credit, font, TEXT_FONT_SIZE));
+            let left = (WIDTH_PX as f32 - text.width(ctx) as f32) / 2.0;
+            graphics::draw(ctx, &text, (Point2::new(left, MENU_TOP_PX + line as f32 * TEXT_FONT_SIZE * 1.5), FADED.into()))?;
+        }
+    }
+    let page_menu = menu.page_menu();
+    page_menu.draw(ctx, font)?;
+    let bottom = page_menu.bottom();
+    if let Some(message) = &menu.message {
+        let text = graphics::Text::new((message.as_str(), font, TEXT_FONT_SIZE));
+        graphics::draw(ctx, &text, (Point2::new((WIDTH_PX as f32 - MENU_WIDTH_PX) / 2.0, bottom + TEXT_FONT_SIZE), FADED.into()))?;
+    }
     Ok(())
 }
+
+/// Roughly how long ago a slot was saved to, for telling slots apart.
+fn saved_ago(time: SystemTime) -> String {
+    let minutes = SystemTime::now().duration_since(time).map_or(0, |elapsed| elapsed.as_secs() / 60);
+    match minutes {
+        0 => "saved just now".to_string(),
+        1..=59 => format!("saved {} min ago", minutes),
+        60..=1439 => format!("saved {} h ago", minutes / 60),
+        _ => format!("saved {} days ago", minutes / 1440),
+    }
+}
@@ -1,30 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
 use specs::prelude::*;
 use crate::component::*;
+use crate::map::{self, MapTile};
+use crate::{Direction, Point2};
+use crate::events::{GameEvent, GameEvents};
+
+/// How far an entity walking along a path moves each tick, in pixels.
+const PATH_FOLLOW_SPEED: f32 = 2.0;
 
 pub struct MovementSystem {}
 
 impl<'a> System<'a> for MovementSystem {
     type SystemData = (
         Entities<'a>,
+        ReadExpect<'a, BTreeMap<(i32, i32), MapTile>>,
         WriteStorage<'a, Position>,
-        WriteStorage<'a, Player>
+        WriteStorage<'a, Player>,
+        WriteStorage<'a, Npc>,
+        WriteStorage<'a, PathFollow>,
+        ReadStorage<'a, BlocksTile>,
+        WriteStorage<'a, Knockback>,
+        Write<'a, GameEvents>
     );
 
     fn run(&mut self, data : Self::SystemData) {
-        let (entities, mut position, mut player) = data;
+        let (entities, map, mut position, mut player, mut npc, mut path, blocks, mut knockback, mut events) = data;
+        let mut finished_paths = Vec::new();
+
+        // Every tile with something standing on it, kept up to date as things move
+        let mut occupied: HashMap<(i32, i32), Entity> = (&entities, &position, &blocks).join()
+            .map(|(entity, position, _blocks)| ((map::to_tl(position.x), map::to_tl(position.y)), entity))
+            .collect();
+
+        for (entity, position, player, path) in (&entities, &mut position, &mut player, (&mut path).maybe()).join() {
+            // Acceleration is applied continuously, for example while an analog stick is held
+            player.velocity += player.acceleration.coords;
 
-        for (_entity, position, player) in (&entities, &mut position, &mut player).join() {
             // Burn down velocity using built-in friction rules (for now)
             // This requires clamping to prevent values from going wild
             player.velocity *= 0.1;
             player.velocity.x = unsigned_zeroing_clamp(player.velocity.x, 0.1, 50.0);
             player.velocity.y = unsigned_zeroing_clamp(player.velocity.y, 0.1, 50.0);
 
-            // Move the player according to their velocity in units per second
-            position.x += player.velocity.x;
-            position.y += player.velocity.y;
+            // Walking along a path takes over from friction entirely
+            if let Some(path) = path {
+                match follow_path(position, path) {
+                    Some(step) => {
+                        player.velocity = step;
+                        if let Some(direction) = Direction::from_vector(step.x, step.y) {
+                            player.direction = direction;
+                        }
+                    }
+                    None => {
+                        player.velocity = Point2::origin();
+                        finished_paths.push(entity);
+                    }
+                }
+            }
+
+            let before = (position.x, position.y);
+            let (blocked_x, blocked_y) = step_entity(entity, position, player.velocity, &map, &mut occupied);
+            if (position.x, position.y) != before {
+                events.single_write(GameEvent::PlayerMoved { entity, x: position.x, y: position.y });
+            }
+            enter_tile(entity, before, position, &map, &mut events);
+            if blocked_x {
+                player.velocity.x = 0.0;
+            }
+            if blocked_y {
+                player.velocity.y = 0.0;
+            }
+            if blocked_x || blocked_y {
+                finished_paths.push(entity);
+            }
+        }
+
+        // Npcs only ever move by walking along paths handed to them by their ai
+        for (entity, position, npc, path) in (&entities, &mut position, &mut npc, &mut path).join() {
+            match follow_path(position, path) {
+                Some(step) => {
+                    if let Some(direction) = Direction::from_vector(step.x, step.y) {
+                        npc.direction = direction;
+                    }
+                    let before = (position.x, position.y);
+                    let (blocked_x, blocked_y) = step_entity(entity, position, step, &map, &mut occupied);
+                    enter_tile(entity, before, position, &map, &mut events);
+                    if blocked_x || blocked_y {
+                        finished_paths.push(entity);
+                    }
+                }
+                None => finished_paths.push(entity),
+            }
+        }
+
+        // Paths are dropped once walked, or as soon as something blocks them
+        for entity in finished_paths {
+            path.remove(entity);
+        }
+
+        // Knockback wears off under the same friction as the players velocity
+        let mut finished_knockbacks = Vec::new();
+        for (entity, position, knockback) in (&entities, &mut position, &mut knockback).join() {
+            knockback.velocity *= 0.1;
+            knockback.velocity.x = unsigned_zeroing_clamp(knockback.velocity.x, 0.1, 50.0);
+            knockback.velocity.y = unsigned_zeroing_clamp(knockback.velocity.y, 0.1, 50.0);
+            step_entity(entity, position, knockback.velocity, &map, &mut occupied);
+            if knockback.velocity.x == 0.0 && knockback.velocity.y == 0.0 {
+                finished_knockbacks.push(entity);
+            }
         }
+        for entity in finished_knockbacks {
+            knockback.remove(entity);
+        }
+    }
+}
+
+/// Moves an entity by a step, checking each axis against the map and the tiles other entities stand on.
+/// Each axis is checked separately so that walking diagonally into a wall still slides along it.
+/// Returns whether movement along the x and y axes was blocked.
+fn step_entity(
+    entity: Entity,
+    position: &mut Position,
+    step: Point2,
+    map: &BTreeMap<(i32, i32), MapTile>,
+    occupied: &mut HashMap<(i32, i32), Entity>,
+) -> (bool, bool) {
+    let start_tile = (map::to_tl(position.x), map::to_tl(position.y));
+    let can_enter = |tile: (i32, i32), occupied: &HashMap<(i32, i32), Entity>| {
+        map::tile_type_at(map, tile.0, tile.1).is_walkable()
+            && !matches!(occupied.get(&tile), Some(occupant) if *occupant != entity)
+    };
+
+    let next_x = position.x + step.x;
+    let blocked_x = !can_enter((map::to_tl(next_x), map::to_tl(position.y)), occupied);
+    if !blocked_x {
+        position.x = next_x;
+    }
+
+    let next_y = position.y + step.y;
+    let blocked_y = !can_enter((map::to_tl(position.x), map::to_tl(next_y)), occupied);
+    if !blocked_y {
+        position.y = next_y;
+    }
+
+    let end_tile = (map::to_tl(position.x), map::to_tl(position.y));
+    if end_tile != start_tile && occupied.get(&start_tile) == Some(&entity) {
+        occupied.remove(&start_tile);
+        occupied.insert(end_tile, entity);
+    }
+    (blocked_x, blocked_y)
+}
+
+/// Works out the step towards the next tile on the path, dropping tiles as they are reached.
+/// The final step onto a tile lands exactly on its center. Returns `None` once the path is walked.
+fn follow_path(position: &Position, path: &mut PathFollow) -> Option<Point2> {
+    let (tile_x, tile_y) = *path.path.front()?;
+    let dx = map::to_px(tile_x) as f32 - position.x;
+    let dy = map::to_px(tile_y) as f32 - position.y;
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance <= PATH_FOLLOW_SPEED {
+        path.path.pop_front();
+        return Some(Point2::new(dx, dy));
     }
+    Some(Point2::new(dx / distance * PATH_FOLLOW_SPEED, dy / distance * PATH_FOLLOW_SPEED))
 }
 
 /// Prevents the given value from going outside of the range.
@@ -42,3 +180,72 @@ pub fn unsigned_zeroing_clamp(value: f32, min: f32, max: f32) -> f32 {
     }
     clamped_value
 }
+
+/// Lets everyone know when a step took something onto a different tile.
+fn enter_tile(entity: Entity, before: (f32, f32), after: &Position, map: &BTreeMap<(i32, i32), MapTile>, events: &mut GameEvents) {
+    let tile = (map::to_tl(after.x), map::to_tl(after.y));
+    if tile != (map::to_tl(before.0), map::to_tl(before.1)) {
+        events.single_write(GameEvent::TileEntered { entity, tile, tile_type: map::tile_type_at(map, tile.0, tile.1) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::{Headless, object};
+    use crate::map::{MapTile, TileType};
+    use crate::Direction;
+
+    #[test]
+    fn pressing_right_moves_player_right() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.press(Direction::Right);
+        game.step(10);
+        let (x, y) = game.player_position();
+        assert!((x - 3.52).abs() < 0.001, "player ended up at x = {}", x);
+        assert_eq!(y, 0.0);
+    }
+
+    #[test]
+    fn player_comes_to_rest_after_input_stops() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.press(Direction::Down);
+        game.step(10);
+        let resting = game.player_position();
+        game.step(60);
+        assert_eq!(game.player_position(), resting);
+    }
+
+    #[test]
+    fn walls_block_movement() {
+        let mut map = Headless::floor(10, 10);
+        map.insert((1, 0), MapTile { tile_id: 1, tile_type: TileType::Wall });
+        let mut game = Headless::new(map);
+        for _ in 0..20 {
+            game.press(Direction::Right);
+            game.step(5);
+        }
+        assert_eq!(game.player_tile(), (0, 0));
+    }
+
+    #[test]
+    fn player_cannot_leave_the_map() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        for _ in 0..20 {
+            game.press(Direction::Up);
+            game.step(5);
+        }
+        assert_eq!(game.player_tile(), (0, 0));
+    }
+
+    #[test]
+    fn npcs_block_the_player() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[object("Elder", "npc", (1, 0), &[])]);
+        for _ in 0..10 {
+            game.press(Direction::Right);
+            game.step(5);
+        }
+        assert_eq!(game.player_tile(), (0, 0));
+        assert_eq!(game.npc_tile("Elder"), (1, 0));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::{GameState, Point2, HEIGHT_PX, TL_PX, WIDTH_PX};
+use crate::component::{Player, Position};
+use crate::events::{GameEvent, Subscription};
+use crate::map;
+use crate::viewport_system;
+use crate::simulation::{MapProperties, TICKS_PER_SECOND};
+use ggez::graphics::{self, spritebatch::SpriteBatch};
+use ggez::{Context, GameResult};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::Deserialize;
+use specs::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Every kind of particle effect in the game is described here rather than in code.
+pub const PARTICLE_DEFS_PATH: &str = "assets/particles/particles.ron";
+
+/// Kicked up under the players feet on every tile they walk onto.
+const DUST: &str = "dust";
+/// Thrown off wherever something is hit.
+const SPARKS: &str = "sparks";
+/// How far the player stands above the bottom of their tile, for dust to come up from under their feet.
+const FEET_PX: f32 = TL_PX as f32 / 2.0 - 4.0;
+/// How far past the edges of the view weather keeps going, so that it is already falling
+/// by the time the player walks into it.
+const VIEW_MARGIN_PX: f32 = 2.0 * TL_PX as f32;
+/// Particles only exist to be seen, so they are seeded independently of the world and never affect it.
+const PARTICLE_SEED: u64 = 0x5a_4c_e5;
+
+/// Describes an effect made of particles, along with how every particle it lets out behaves.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmitterDef {
+    /// Path to the image for each particle, relative to the assets directory.
+    /// Images are best drawn in white, since they are tinted by `colors`.
+    pub texture: String,
+    /// Particles let out per second for as long as the emitter lasts.
+    #[serde(default)]
+    pub rate: f32,
+    /// Particles let out all at once as soon as the emitter starts.
+    #[serde(default)]
+    pub burst: u32,
+    /// How many seconds the emitter keeps going for. Emitters without one go on until they are replaced.
+    #[serde(default)]
+    pub duration: Option<f32>,
+    /// How many seconds each particle lasts, somewhere between the two.
+    pub lifetime: (f32, f32),
+    /// Pixels per second each particle starts moving at along each axis, somewhere between the two.
+    #[serde(default)]
+    pub velocity_x: (f32, f32),
+    #[serde(default)]
+    pub velocity_y: (f32, f32),
+    /// Pixels per second squared that particles are pulled down the screen by.
+    #[serde(default)]
+    pub gravity: f32,
+    /// Colours each particle passes through evenly over its life, fading with the alpha.
+    pub colors: Vec<[f32; 4]>,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// How far from the emitter particles can appear along each axis, in pixels.
+    #[serde(default)]
+    pub spread: (f32, f32),
+    /// Weather falls across the whole view and around it, rather than coming out of one point.
+    #[serde(default)]
+    pub fills_view: bool,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug)]
+pub enum ParticleDefsError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+impl fmt::Display for ParticleDefsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParticleDefsError::Io(e) => write!(f, "could not read particle definitions: {}", e),
+            ParticleDefsError::Parse(e) => write!(f, "could not parse particle definitions: {}", e),
+        }
+    }
+}
+
+/// Every known particle effect, by name.
+#[derive(Clone, Default, Debug)]
+pub struct ParticleDefs(pub HashMap<String, EmitterDef>);
+
+impl ParticleDefs {
+    pub fn load(path: &Path) -> Result<ParticleDefs, ParticleDefsError> {
+        let contents = fs::read_to_string(path).map_err(ParticleDefsError::Io)?;
+        contents.parse()
+    }
+}
+
+impl FromStr for ParticleDefs {
+    type Err = ParticleDefsError;
+
+    fn from_str(s: &str) -> Result<ParticleDefs, ParticleDefsError> {
+        ron::de::from_str(s).map(ParticleDefs).map_err(ParticleDefsError::Parse)
+    }
+}
+
+/// Something letting out particles of one kind, either at a point in the world or over the whole view.
+#[derive(Debug)]
+struct Emitter {
+    kind: String,
+    x: f32,
+    y: f32,
+    age: f32,
+    /// Particles due to be let out which did not add up to a whole one yet.
+    owed: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+/// Every particle in flight, and the emitters letting out more. Particles are only for show,
+/// so they live outside the world and move along with the ticks of the game without being part of them.
+pub struct Particles {
+    defs: ParticleDefs,
+    emitters: Vec<Emitter>,
+    particles: Vec<Particle>,
+    /// The weather of the current map, named by its `weather` property.
+    weather: Option<String>,
+    rng: Pcg32,
+    events: Subscription,
+}
+
+impl Particles {
+    pub fn new(defs: ParticleDefs) -> Particles {
+        Particles {
+            defs,
+            emitters: vec![],
+            particles: vec![],
+            weather: None,
+            rng: Pcg32::seed_from_u64(PARTICLE_SEED),
+            events: Subscription::default(),
+        }
+    }
+
+    /// Starts over on a different world, clearing away everything from the old one.
+    pub fn listen(&mut self, ecs: &World) {
+        self.emitters.clear();
+        self.particles.clear();
+        self.weather = None;
+        self.events.subscribe(ecs);
+    }
+
+    pub fn defs(&self) -> &ParticleDefs {
+        &self.defs
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// How many particles of a kind are in flight.
+    pub fn count(&self, kind: &str) -> usize {
+        self.particles.iter().filter(|particle| particle.kind == kind).count()
+    }
+
+    pub fn weather(&self) -> Option<&str> {
+        self.weather.as_deref()
+    }
+
+    /// Starts an emitter of the given kind at a point in the world, letting out its burst straight away.
+    pub fn emit(&mut self, kind: &str, x: f32, y: f32) {
+        let burst = match self.defs.0.get(kind) {
+            Some(def) => def.burst,
+            None => {
+                println!("Could not emit unknown particles '{}'", kind);
+                return;
+            }
+        };
+        let emitter = Emitter { kind: kind.to_string(), x, y, age: 0.0, owed: 0.0 };
+        for _ in 0..burst {
+            self.spawn(&emitter);
+        }
+        self.emitters.push(emitter);
+    }
+
+    /// Swaps the weather for another kind of particles, or none at all.
+    /// Whatever has already fallen is left to finish falling.
+    fn set_weather(&mut self, weather: Option<&str>) {
+        if self.weather.as_deref() == weather {
+            return;
+        }
+        if let Some(old) = self.weather.take() {
+            let defs = &self.defs;
+            self.emitters.retain(|emitter| emitter.kind != old || !emitter_fills_view(defs, emitter));
+        }
+        if let Some(weather) = weather {
+            if self.defs.0.contains_key(weather) {
+                self.emitters.push(Emitter { kind: weather.to_string(), x: 0.0, y: 0.0, age: 0.0, owed: 0.0 });
+            } else {
+                println!("Skipping unknown weather '{}'", weather);
+            }
+            self.weather = Some(weather.to_string());
+        }
+    }
+
+    /// Moves every particle along by a tick, lets out new ones and clears away those which are done.
+    /// Dust and sparks come from what happened in the world, and the weather from the current map.
+    pub fn tick(&mut self, ecs: &World) {
+        for event in self.events.read(ecs) {
+            match event {
+                GameEvent::TileEntered { entity, tile, .. } if ecs.read_storage::<Player>().contains(entity) => {
+                    self.emit(DUST, map::to_px(tile.0) as f32, map::to_px(tile.1) as f32 + FEET_PX);
+                }
+                GameEvent::Hit { x, y, .. } => self.emit(SPARKS, x, y),
+                _ => (),
+            }
+        }
+        let weather = ecs.fetch::<MapProperties>().get("weather").map(str::to_string);
+        self.set_weather(weather.as_deref());
+
+        let camera = match (&ecs.read_storage::<Position>(), &ecs.read_storage::<Player>()).join().next() {
+            Some((position, _player)) => (position.x, position.y),
+            None => return,
+        };
+        self.update(camera);
+    }
+
+    /// Advances by one tick with the view centered on the camera.
+    fn update(&mut self, camera: (f32, f32)) {
+        let dt = 1.0 / TICKS_PER_SECOND as f32;
+        let mut emitters = std::mem::take(&mut self.emitters);
+        for emitter in emitters.iter_mut() {
+            let def = match self.defs.0.get(&emitter.kind) {
+                Some(def) => def,
+                None => continue,
+            };
+            if def.fills_view {
+                emitter.x = camera.0;
+                emitter.y = camera.1;
+            }
+            emitter.age += dt;
+            emitter.owed += def.rate * dt;
+            while emitter.owed >= 1.0 {
+                emitter.owed -= 1.0;
+                self.spawn(emitter);
+            }
+        }
+        let defs = &self.defs;
+        emitters.retain(|emitter| {
+            defs.0.get(&emitter.kind)
+                .is_some_and(|def| def.duration.is_none_or(|duration| emitter.age < duration))
+        });
+        self.emitters = emitters;
+
+        let (left, top, right, bottom) = view(camera, VIEW_MARGIN_PX * 2.0);
+        for particle in self.particles.iter_mut() {
+            let gravity = defs.0.get(&particle.kind).map_or(0.0, |def| def.gravity);
+            particle.velocity_y += gravity * dt;
+            particle.x += particle.velocity_x * dt;
+            particle.y += particle.velocity_y * dt;
+            particle.age += dt;
+        }
+        // Anything which has drifted well away from the view would never be seen again anyway
+        self.particles.retain(|particle| {
+            particle.age < particle.lifetime
+                && particle.x >= left && particle.x <= right && particle.y >= top && particle.y <= bottom
+        });
+    }
+
+    fn spawn(&mut self, emitter: &Emitter) {
+        let def = match self.defs.0.get(&emitter.kind) {
+            Some(def) => def,
+            None => return,
+        };
+        let rng = &mut self.rng;
+        let mut between = |(low, high): (f32, f32)| if high > low { rng.gen_range(low, high) } else { low };
+        let (spread_x, spread_y) = if def.fills_view {
+            (WIDTH_PX as f32 / 2.0 + VIEW_MARGIN_PX, HEIGHT_PX as f32 / 2.0 + VIEW_MARGIN_PX)
+        } else {
+            def.spread
+        };
+        let particle = Particle {
+            kind: emitter.kind.clone(),
+            x: emitter.x + between((-spread_x, spread_x)),
+            y: emitter.y + between((-spread_y, spread_y)),
+            velocity_x: between(def.velocity_x),
+            velocity_y: between(def.velocity_y),
+            age: 0.0,
+            lifetime: between(def.lifetime),
+        };
+        self.particles.push(particle);
+    }
+}
+
+fn emitter_fills_view(defs: &ParticleDefs, emitter: &Emitter) -> bool {
+    defs.0.get(&emitter.kind).is_some_and(|def| def.fills_view)
+}
+
+/// The edges of what can be seen around the camera, pushed out by a margin.
+fn view(camera: (f32, f32), margin: f32) -> (f32, f32, f32, f32) {
+    let half_width = WIDTH_PX as f32 / 2.0 + margin;
+    let half_height = HEIGHT_PX as f32 / 2.0 + margin;
+    (camera.0 - half_width, camera.1 - half_height, camera.0 + half_width, camera.1 + half_height)
+}
+
+/// The colour of a particle some way through its life, from 0.0 to 1.0,
+/// blending between whichever two of the colours it is between.
+pub fn color_at(colors: &[[f32; 4]], progress: f32) -> [f32; 4] {
+    match colors.len() {
+        0 => [1.0, 1.0, 1.0, 1.0],
+        1 => colors[0],
+        len => {
+            let position = progress.clamp(0.0, 1.0) * (len - 1) as f32;
+            let index = (position as usize).min(len - 2);
+            let blend = position - index as f32;
+            let (from, to) = (colors[index], colors[index + 1]);
+            [0, 1, 2, 3].map(|channel| from[channel] + (to[channel] - from[channel]) * blend)
+        }
+    }
+}
+
+/// Where a particle appears on screen, or nothing when it is outside of the view.
+pub fn particle_on_screen(particle: &Particle, camera: (f32, f32)) -> Option<Point2> {
+    viewport_system::world_to_screen(particle.x, particle.y, camera, TL_PX as f32 / 2.0)
+}
+
+/// The image of every kind of particle, loaded once at startup.
+pub struct ParticleTextures(HashMap<String, graphics::Image>);
+
+impl ParticleTextures {
+    /// Particles whose image is missing are drawn as small squares instead.
+    pub fn load(ctx: &mut Context, defs: &ParticleDefs) -> ParticleTextures {
+        let textures = defs.0.iter()
+            .map(|(kind, def)| {
+                let image = graphics::Image::new(ctx, &def.texture)
+                    .or_else(|_| graphics::Image::solid(ctx, 4, graphics::WHITE))
+                    .expect("could not create particle image");
+                (kind.clone(), image)
+            })
+            .collect();
+        ParticleTextures(textures)
+    }
+}
+
+/// Draws every particle in view, all those of one kind in a single batch.
+pub fn render_particles(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let camera = {
+        let positions = state.ecs.read_storage::<Position>();
+        let players = state.ecs.read_storage::<Player>();
+        match (&positions, &players).join().next() {
+            Some((position, _player)) => (position.x, position.y),
+            None => return Ok(()),
+        }
+    };
+    let defs = state.particles.defs();
+    // Kinds are drawn in order of their names, so that overlapping particles always stack up the same way
+    let mut batches: BTreeMap<&str, SpriteBatch> = BTreeMap::new();
+    for particle in state.particles.particles() {
+        let (def, texture) = match (defs.0.get(&particle.kind), state.particle_textures.0.get(&particle.kind)) {
+            (Some(def), Some(texture)) => (def, texture),
+            _ => continue,
+        };
+        if let Some(dest) = particle_on_screen(particle, camera) {
+            let color = color_at(&def.colors, particle.age / particle.lifetime);
+            let drawparams = graphics::DrawParam::new()
+                .dest(dest)
+                .offset(Point2::new(0.5, 0.5))
+                .scale([def.scale, def.scale])
+                .color(color.into());
+            batches.entry(particle.kind.as_str())
+                .or_insert_with(|| SpriteBatch::new(texture.clone()))
+                .add(drawparams);
+        }
+    }
+    for batch in batches.values() {
+        graphics::draw(ctx, batch, graphics::DrawParam::default())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, enemy, shipped_particles};
+    use crate::map;
+    use crate::simulation;
+    use crate::input::{Action, Input};
+    use crate::Direction;
+    use std::path::Path;
+    use crate::{Point2, HEIGHT_PX, TL_PX, WIDTH_PX};
+
+    #[test]
+    fn weather_follows_the_map_it_is_set_on() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        let mut particles = shipped_particles();
+        particles.listen(&game.ecs);
+        for _ in 0..simulation::TICKS_PER_SECOND {
+            game.step(1);
+            particles.tick(&game.ecs);
+        }
+        assert_eq!(particles.weather(), Some("leaves"));
+        assert!(particles.count("leaves") > 0);
+
+        game.ecs.fetch_mut::<simulation::MapProperties>().0.insert("weather".to_string(), "snow".to_string());
+        game.step(1);
+        particles.tick(&game.ecs);
+        assert_eq!(particles.weather(), Some("snow"));
+        // Leaves already falling carry on, but no more of them come
+        let leaves = particles.count("leaves");
+        assert!(leaves > 0);
+        for _ in 0..simulation::TICKS_PER_SECOND * 10 {
+            particles.tick(&game.ecs);
+        }
+        assert_eq!(particles.count("leaves"), 0);
+        assert!(particles.count("snow") > 0);
+
+        game.ecs.fetch_mut::<simulation::MapProperties>().0.remove("weather");
+        particles.tick(&game.ecs);
+        assert_eq!(particles.weather(), None);
+    }
+
+    #[test]
+    fn particles_fall_fade_and_die_of_old_age() {
+        let defs: ParticleDefs = r#"{
+            "ember": (
+                texture: "/particles/spark.png",
+                burst: 1,
+                duration: Some(0.0),
+                lifetime: (1.0, 1.0),
+                velocity_y: (-30.0, -30.0),
+                gravity: 60.0,
+                colors: [(1.0, 1.0, 1.0, 1.0), (1.0, 0.5, 0.0, 0.0)],
+            ),
+        }"#.parse().unwrap();
+        let game = Headless::new(Headless::floor(10, 10));
+        let mut particles = Particles::new(defs);
+        particles.listen(&game.ecs);
+        particles.emit("ember", 0.0, 0.0);
+        assert_eq!(particles.count("ember"), 1);
+
+        let mut heights = vec![];
+        for _ in 0..=simulation::TICKS_PER_SECOND {
+            particles.tick(&game.ecs);
+            heights.extend(particles.particles().iter().map(|particle| particle.y));
+        }
+        // Thrown upwards and pulled back down again, until it burns out after a second
+        let top = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        assert!(top < heights[0] && top < *heights.last().unwrap());
+        assert_eq!(particles.count("ember"), 0);
+        // Emitting something unknown does nothing
+        particles.emit("confetti", 0.0, 0.0);
+        assert!(particles.particles().is_empty());
+
+        let colors = [[1.0, 1.0, 1.0, 1.0], [1.0, 0.5, 0.0, 0.0], [0.0, 0.0, 0.0, 0.0]];
+        assert_eq!(color_at(&colors, 0.0), colors[0]);
+        assert_eq!(color_at(&colors, 0.25), [1.0, 0.75, 0.5, 0.5]);
+        assert_eq!(color_at(&colors, 0.5), colors[1]);
+        assert_eq!(color_at(&colors, 1.0), colors[2]);
+        assert_eq!(color_at(&colors[..1], 0.7), colors[0]);
+    }
+
+    #[test]
+    fn walking_kicks_up_dust_and_hits_throw_sparks() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        let mut particles = shipped_particles();
+        particles.listen(&game.ecs);
+        for _ in 0..simulation::TICKS_PER_SECOND {
+            if game.player_tile() == (0, 1) {
+                break;
+            }
+            game.press(Direction::Down);
+            game.step(1);
+        }
+        assert_eq!(game.player_tile(), (0, 1));
+        particles.tick(&game.ecs);
+        assert!(particles.count("dust") > 0);
+        assert_eq!(particles.count("sparks"), 0);
+
+        game.spawn(&[enemy("Slime", (1, 1), "3")]);
+        game.press(Direction::Right);
+        game.input(Input::Action(Action::Attack));
+        game.step(simulation::ATTACK_TICKS);
+        particles.tick(&game.ecs);
+        assert_eq!(game.npc_health("Slime"), Some(2));
+        assert!(particles.count("sparks") > 0);
+        // Every spark flies out from between the player and the slime
+        let (player_x, _) = game.player_position();
+        let (slime_x, _) = game.npc_position("Slime");
+        assert!(particles.particles().iter()
+            .filter(|particle| particle.kind == "sparks")
+            .all(|particle| particle.x > player_x - TL_PX as f32 && particle.x < slime_x + TL_PX as f32));
+    }
+
+    #[test]
+    fn particles_off_screen_are_not_drawn() {
+        let at = |x: f32, y: f32| Particle { kind: "dust".to_string(), x, y, velocity_x: 0.0, velocity_y: 0.0, age: 0.0, lifetime: 1.0 };
+        let camera = (1000.0, 1000.0);
+        let left_edge = 1000.0 - (WIDTH_PX / 2) as f32;
+        let center = Point2::new((WIDTH_PX / 2) as f32, (HEIGHT_PX / 2) as f32);
+        assert_eq!(particle_on_screen(&at(1000.0, 1000.0), camera), Some(center));
+        assert!(particle_on_screen(&at(left_edge + 1.0, 1000.0), camera).is_some());
+        assert!(particle_on_screen(&at(left_edge - TL_PX as f32, 1000.0), camera).is_none());
+        assert!(particle_on_screen(&at(1000.0, 1000.0 + HEIGHT_PX as f32), camera).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use crate::map::{self, MapTile};
+
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+const NEIGHBORS: [(i32, i32); 8] = [
+    (0, -1), (0, 1), (-1, 0), (1, 0),
+    (-1, -1), (1, -1), (-1, 1), (1, 1),
+];
+
+/// Finds the cheapest walkable path between two tiles using A*.
+/// Moves can be made in all eight directions, but a diagonal move is only
+/// allowed when both of the tiles beside it are walkable, so paths never cut corners.
+/// The returned path excludes the start tile and ends on the goal tile.
+pub fn a_star(map: &BTreeMap<(i32, i32), MapTile>, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    if !map::tile_type_at(map, goal.0, goal.1).is_walkable() {
+        return None;
+    }
+    if start == goal {
+        return Some(vec![]);
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut costs: HashMap<(i32, i32), u32> = HashMap::new();
+    open.push(Reverse((heuristic(start, goal), start)));
+    costs.insert(start, 0);
+
+    while let Some(Reverse((_, current))) = open.pop() {
+        if current == goal {
+            let mut path = vec![current];
+            let mut step = current;
+            while let Some(previous) = came_from.get(&step) {
+                if *previous == start {
+                    break;
+                }
+                path.push(*previous);
+                step = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let current_cost = costs[&current];
+        for (dx, dy) in NEIGHBORS.iter() {
+            let next = (current.0 + dx, current.1 + dy);
+            if !map::tile_type_at(map, next.0, next.1).is_walkable() {
+                continue;
+            }
+            let diagonal = *dx != 0 && *dy != 0;
+            if diagonal && !(map::tile_type_at(map, current.0 + dx, current.1).is_walkable()
+                && map::tile_type_at(map, current.0, current.1 + dy).is_walkable()) {
+                continue;
+            }
+
+            let next_cost = current_cost + if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+            let cheaper = match costs.get(&next) {
+                Some(cost) => next_cost < *cost,
+                None => true,
+            };
+            if cheaper {
+                costs.insert(next, next_cost);
+                came_from.insert(next, current);
+                open.push(Reverse((next_cost + heuristic(next, goal), next)));
+            }
+        }
+    }
+    None
+}
+
+/// Octile distance, which never overestimates the cost of an eight directional path.
+fn heuristic(from: (i32, i32), to: (i32, i32)) -> u32 {
+    let dx = (from.0 - to.0).unsigned_abs();
+    let dy = (from.1 - to.1).unsigned_abs();
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::component::*;
+    use crate::map::{self, MapTile, TileType};
+    use crate::input::Input;
+    use crate::{viewport_system, Direction, HEIGHT_PX, TL_PX, WIDTH_PX};
+    use specs::prelude::*;
+
+    #[test]
+    fn clicking_a_tile_walks_the_player_around_walls_to_it() {
+        let mut map = Headless::floor(10, 10);
+        for y in 0..4 {
+            map.insert((2, y), MapTile { tile_id: 1, tile_type: TileType::Wall });
+        }
+        let mut game = Headless::new(map);
+        game.step(1);
+
+        // Clicking is resolved against the viewport, which is centered on the player
+        let target = {
+            let viewports = game.ecs.read_storage::<Viewport>();
+            let viewport = viewports.join().next().unwrap();
+            viewport_system::screen_to_tile(viewport, (WIDTH_PX / 2 + 4 * TL_PX) as f32, (HEIGHT_PX / 2) as f32)
+        };
+        assert_eq!(target, Some((4, 0)));
+
+        game.input(Input::MoveTo(4, 0));
+        game.step(300);
+        assert_eq!(game.player_position(), (map::to_px(4) as f32, 0.0));
+        assert_eq!(game.ecs.read_storage::<PathFollow>().count(), 0);
+    }
+
+    #[test]
+    fn paths_do_not_cut_corners() {
+        let mut map = Headless::floor(3, 3);
+        map.insert((1, 0), MapTile { tile_id: 1, tile_type: TileType::Wall });
+        let path = a_star(&map, (0, 0), (2, 1)).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1), (2, 1)]);
+
+        let open = Headless::floor(3, 3);
+        assert_eq!(a_star(&open, (0, 0), (2, 2)).unwrap(), vec![(1, 1), (2, 2)]);
+        assert_eq!(a_star(&open, (0, 0), (5, 5)), None);
+    }
+
+    #[test]
+    fn manual_input_cancels_click_to_move() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.input(Input::MoveTo(5, 5));
+        game.step(5);
+        game.press(Direction::Down);
+        game.step(30);
+        assert_eq!(game.ecs.read_storage::<PathFollow>().count(), 0);
+        assert!(game.player_tile() != (5, 5));
+    }
+}
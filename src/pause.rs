@@ -0,0 +1,167 @@
+use crate::{GameState, Point2, RootState};
+use crate::audio::Sound;
+use crate::input::{Action, Input};
+use crate::simulation;
+use crate::states::States;
+use crate::widget::{self, Menu};
+use ggez::{graphics, Context, GameResult};
+use specs::prelude::*;
+
+const PANEL_WIDTH_PX: f32 = 320.0;
+const PANEL_HEIGHT_PX: f32 = 200.0;
+const PANEL_PADDING_PX: f32 = 16.0;
+const TITLE_FONT_SIZE: f32 = 28.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PauseOption {
+    Resume,
+    Settings,
+    QuitToTitle,
+}
+
+impl PauseOption {
+    pub const ALL: [PauseOption; 3] = [PauseOption::Resume, PauseOption::Settings, PauseOption::QuitToTitle];
+
+    fn label(self) -> &'static str {
+        match self {
+            PauseOption::Resume => "Resume",
+            PauseOption::Settings => "Settings",
+            PauseOption::QuitToTitle => "Quit to Title",
+        }
+    }
+}
+
+pub fn pause_menu() -> Menu {
+    let panel = widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX);
+    let labels: Vec<&str> = PauseOption::ALL.iter().map(|option| option.label()).collect();
+    let top = panel.y + PANEL_PADDING_PX * 2.0 + TITLE_FONT_SIZE;
+    Menu::new(&labels, Point2::new(panel.x + PANEL_PADDING_PX, top), PANEL_WIDTH_PX - PANEL_PADDING_PX * 2.0)
+}
+
+/// What is left to do with an input once it has been routed past pausing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Routed {
+    /// The input belongs to the game and has been applied to the world.
+    Played,
+    /// Pausing took care of the input, or had no use for it.
+    Handled,
+    /// An option was picked from the pause menu. Resuming and quitting to the title have already
+    /// changed the states, anything outside of them like the settings screen is up to the caller.
+    Chose(PauseOption),
+}
+
+/// Routes an input meant for the game. Pause freezes the game underneath the pause menu instead of
+/// reaching the world, and while paused inputs only get around the menu. This needs nothing from
+/// the window, so the headless harness goes through it just like the real game does.
+pub fn route_input(states: &mut States, pause_menu: &mut Menu, ecs: &World, input: Input) -> Routed {
+    if states.current() == RootState::Paused {
+        return match input {
+            Input::Action(Action::Pause) => {
+                states.pop();
+                Routed::Handled
+            }
+            Input::Action(action) => match pause_menu.input(action) {
+                Some(index) => Routed::Chose(choose(states, PauseOption::ALL[index])),
+                None => Routed::Handled,
+            },
+            _ => Routed::Handled,
+        };
+    }
+    if input == Input::Action(Action::Pause) {
+        // The menu always starts out on resume
+        pause_menu.select(0);
+        states.push(RootState::Paused);
+        return Routed::Handled;
+    }
+    simulation::apply_input(input, ecs);
+    Routed::Played
+}
+
+pub fn pause_input(state: &mut GameState, action: Action) {
+    if let Routed::Chose(option) = route_input(&mut state.states, &mut state.pause_menu, &state.ecs, Input::Action(action)) {
+        chosen(state, option);
+    }
+}
+
+pub fn pause_click(state: &mut GameState, x: f32, y: f32) {
+    if let Some(index) = state.pause_menu.click(x, y) {
+        let option = choose(&mut state.states, PauseOption::ALL[index]);
+        chosen(state, option);
+    }
+}
+
+/// Goes wherever an option of the pause menu leads among the states.
+fn choose(states: &mut States, option: PauseOption) -> PauseOption {
+    match option {
+        PauseOption::Resume => states.pop(),
+        PauseOption::Settings => (),
+        PauseOption::QuitToTitle => states.reset(RootState::StartMenu),
+    }
+    option
+}
+
+/// Follows an option of the pause menu through everything outside of the states.
+fn chosen(state: &mut GameState, option: PauseOption) {
+    state.audio.play_sound(Sound::MenuClick);
+    match option {
+        PauseOption::Resume => (),
+        PauseOption::Settings => crate::settings::open_settings(state),
+        PauseOption::QuitToTitle => state.quit_to_title(),
+    }
+}
+
+pub fn pause_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    let panel = widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX);
+    widget::draw_panel(ctx, panel)?;
+    let title = graphics::Text::new(("Paused", state.font, TITLE_FONT_SIZE));
+    graphics::draw(ctx, &title, (Point2::new(panel.x + PANEL_PADDING_PX, panel.y + PANEL_PADDING_PX), graphics::WHITE))?;
+    state.pause_menu.draw(ctx, state.font)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::Headless;
+    use crate::input::{Action, Input};
+    use crate::simulation::Ticks;
+    use crate::states::RootState;
+
+    #[test]
+    fn pausing_freezes_the_world_until_resumed() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.input(Input::MoveTo(6, 0));
+        game.step(5);
+        game.input(Input::Action(Action::Pause));
+        assert_eq!(game.states.current(), RootState::Paused);
+        // Getting around the menu does not reach the world
+        game.input(Input::Action(Action::MoveDown));
+        let (position, ticks) = (game.player_position(), game.ecs.fetch::<Ticks>().0);
+        game.step(60);
+        assert_eq!(game.player_position(), position);
+        assert_eq!(game.ecs.fetch::<Ticks>().0, ticks);
+        assert_eq!(game.states.drawn(), &[RootState::InGame, RootState::Paused]);
+
+        // Pausing again goes straight back, and the menu starts out on resume when it opens again
+        game.input(Input::Action(Action::Pause));
+        assert_eq!(game.states.current(), RootState::InGame);
+        game.input(Input::Action(Action::Pause));
+        game.input(Input::Action(Action::Interact));
+        assert_eq!(game.states.current(), RootState::InGame);
+        game.step(300);
+        assert_eq!(game.player_tile(), (6, 0));
+    }
+
+    #[test]
+    fn quitting_to_title_throws_away_the_game_and_its_menus() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.input(Input::Action(Action::Pause));
+        game.input(Input::Action(Action::MoveUp));
+        game.input(Input::Action(Action::Interact));
+        assert_eq!(game.states.current(), RootState::StartMenu);
+        assert_eq!(game.states.drawn(), &[RootState::StartMenu]);
+        assert!(!game.states.contains(RootState::InGame));
+        // There is nothing underneath the start menu to go back to
+        game.states.pop();
+        assert_eq!(game.states.current(), RootState::StartMenu);
+        assert!(!game.states.simulating());
+    }
+}
@@ -0,0 +1,99 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::item::{self, ItemDefs};
+use crate::map;
+use crate::simulation::Notifications;
+use crate::events::{GameEvent, GameEvents};
+
+/// Picks up items that anything with an inventory is standing on.
+/// Items which do not fit stay on the ground with whatever is left over.
+pub struct PickupSystem {}
+
+impl<'a> System<'a> for PickupSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, ItemDefs>,
+        WriteExpect<'a, Notifications>,
+        Write<'a, GameEvents>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Inventory>,
+        WriteStorage<'a, Item>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, defs, mut notifications, mut events, positions, mut inventories, mut items) = data;
+
+        for (entity, position, inventory) in (&entities, &positions, &mut inventories).join() {
+            let tile = (map::to_tl(position.x), map::to_tl(position.y));
+            for (item_entity, item_position, item) in (&entities, &positions, &mut items).join() {
+                let item_tile = (map::to_tl(item_position.x), map::to_tl(item_position.y));
+                if !item.auto_pickup || item_tile != tile {
+                    continue;
+                }
+                let before = item.count;
+                if item::pick_up(inventory, item, &defs, &mut notifications) {
+                    entities.delete(item_entity).expect("could not delete picked up item");
+                }
+                if item.count != before {
+                    events.single_write(GameEvent::ItemPickedUp { entity, id: item.id.clone(), count: before - item.count });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::{Headless, object};
+    use crate::input::Input;
+    use crate::Direction;
+
+    #[test]
+    fn walking_over_items_picks_them_up() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[object("Apples", "item", (1, 0), &[("item", "apple"), ("count", "3")])]);
+        for _ in 0..10 {
+            game.press(Direction::Right);
+            game.step(5);
+        }
+        assert_eq!(game.carried("apple"), 3);
+        assert_eq!(game.lying_around("apple"), 0);
+    }
+
+    #[test]
+    fn items_which_do_not_fit_stay_on_the_ground() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[object("Coins", "item", (0, 0), &[("item", "coin"), ("count", "1200")])]);
+        game.step(1);
+        assert_eq!(game.carried("coin"), 12 * 99);
+        assert_eq!(game.lying_around("coin"), 12);
+    }
+
+    #[test]
+    fn some_items_have_to_be_interacted_with() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[object("Herb", "item", (0, 1), &[("item", "herb"), ("pickup", "interact")])]);
+        game.press(Direction::Down);
+        game.step(10);
+        assert_eq!(game.carried("herb"), 0);
+        assert_eq!(game.interact(), None);
+        assert_eq!(game.carried("herb"), 1);
+    }
+
+    #[test]
+    fn dropped_items_stay_put_until_picked_back_up() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[object("Key", "item", (0, 0), &[("item", "key")])]);
+        game.step(1);
+        assert_eq!(game.carried("key"), 1);
+
+        game.input(Input::DropItem(0));
+        game.step(10);
+        assert_eq!(game.carried("key"), 0);
+        assert_eq!(game.lying_around("key"), 1);
+
+        game.interact();
+        game.step(1);
+        assert_eq!(game.carried("key"), 1);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::component::{Inventory, Player};
+use crate::events::{GameEvent, GameEvents};
+use crate::item::ItemDefs;
+use crate::simulation::{self, Flags, Notifications};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Quests live here, one ron file per quest named after its id.
+pub const QUEST_DIR: &str = "assets/quests";
+
+/// Something the player has to do for a quest.
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub enum Objective {
+    /// Talk to the npc with the given name.
+    Talk(String),
+    /// Be carrying at least `count` of an item at once.
+    Collect { item: String, count: u32 },
+    /// Walk into the trigger zone with the given name.
+    Reach(String),
+}
+
+impl Objective {
+    /// Whether something that happened in the world does this objective.
+    fn done_by(&self, event: &GameEvent) -> bool {
+        match (self, event) {
+            (Objective::Talk(name), GameEvent::TalkedTo { npc, .. }) => name == npc,
+            (Objective::Reach(name), GameEvent::ZoneEntered { zone, .. }) => name == zone,
+            _ => false,
+        }
+    }
+
+    /// Whether the player already has what this objective asks for.
+    fn met_by(&self, inventory: Option<&Inventory>) -> bool {
+        match (self, inventory) {
+            (Objective::Collect { item, count }, Some(inventory)) => inventory.count(item) >= *count,
+            _ => false,
+        }
+    }
+
+    /// What the objective asks for, as shown in the quest log.
+    pub fn describe(&self, items: &ItemDefs, inventory: Option<&Inventory>) -> String {
+        match self {
+            Objective::Talk(name) => format!("Talk to {}", name),
+            Objective::Collect { item, count } => {
+                let name = items.get(item).map_or(item.as_str(), |def| def.name.as_str());
+                let carried = inventory.map_or(0, |inventory| inventory.count(item)).min(*count);
+                format!("Collect {} {}/{}", name, carried, count)
+            }
+            Objective::Reach(zone) => format!("Go to {}", zone),
+        }
+    }
+}
+
+/// A step of a quest, finished once every one of its objectives is done.
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuestStage {
+    pub description: String,
+    pub objectives: Vec<Objective>,
+}
+
+/// What the player gets for finishing a quest.
+#[derive(Clone, Debug, Deserialize)]
+pub enum Reward {
+    Item { id: String, count: u32 },
+    Flag(String),
+    Counter { name: String, amount: i64 },
+}
+
+/// Describes a quest, as it is written in a quest file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuestDef {
+    pub name: String,
+    pub description: String,
+    /// Starts the quest as soon as this is done. Quests without one are started by scripts or triggers.
+    #[serde(default)]
+    pub start: Option<Objective>,
+    pub stages: Vec<QuestStage>,
+    #[serde(default)]
+    pub rewards: Vec<Reward>,
+}
+
+#[derive(Debug)]
+pub enum QuestError {
+    Io(io::Error),
+    Parse { id: String, error: ron::Error },
+}
+
+impl fmt::Display for QuestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuestError::Io(e) => write!(f, "could not read quests: {}", e),
+            QuestError::Parse { id, error } => write!(f, "could not parse quest {}: {}", id, error),
+        }
+    }
+}
+
+/// Every quest in the game by id. Kept in order of id so that quests always update in the same order.
+#[derive(Clone, Default, Debug)]
+pub struct QuestDefs(pub BTreeMap<String, QuestDef>);
+
+impl QuestDefs {
+    /// Loads every quest file in a directory.
+    pub fn load(dir: &Path) -> Result<QuestDefs, QuestError> {
+        let mut defs = BTreeMap::new();
+        for entry in fs::read_dir(dir).map_err(QuestError::Io)? {
+            let path = entry.map_err(QuestError::Io)?.path();
+            if path.extension().is_none_or(|extension| extension != "ron") {
+                continue;
+            }
+            let id = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            let contents = fs::read_to_string(&path).map_err(QuestError::Io)?;
+            let def = ron::de::from_str(&contents).map_err(|error| QuestError::Parse { id: id.clone(), error })?;
+            defs.insert(id, def);
+        }
+        Ok(QuestDefs(defs))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&QuestDef> {
+        self.0.get(id)
+    }
+}
+
+/// How far the player has got with a quest they have started.
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct QuestProgress {
+    pub stage: usize,
+    /// Which objectives of the current stage are done, in the same order as the objectives.
+    pub done: Vec<bool>,
+    pub completed: bool,
+}
+
+/// Every quest the player has started, by id.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Quests(pub BTreeMap<String, QuestProgress>);
+
+impl Quests {
+    pub fn get(&self, id: &str) -> Option<&QuestProgress> {
+        self.0.get(id)
+    }
+
+    pub fn is_active(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|progress| !progress.completed)
+    }
+
+    pub fn is_completed(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|progress| progress.completed)
+    }
+}
+
+/// Where quests have read up to in the events of the world.
+pub struct QuestEvents(ReaderId<GameEvent>);
+
+/// Starts quests listening for anything the player does towards them.
+pub fn subscribe(ecs: &mut World) {
+    let reader = ecs.fetch_mut::<GameEvents>().register_reader();
+    ecs.insert(QuestEvents(reader));
+}
+
+/// Starts a quest, unless it has been started before.
+pub fn start_quest(ecs: &World, id: &str) {
+    let defs = ecs.fetch::<QuestDefs>();
+    let def = match defs.get(id) {
+        Some(def) => def,
+        None => {
+            println!("Could not start unknown quest '{}'", id);
+            return;
+        }
+    };
+    let mut quests = ecs.fetch_mut::<Quests>();
+    if quests.get(id).is_none() {
+        quests.0.insert(id.to_string(), QuestProgress::default());
+        ecs.fetch_mut::<Notifications>().push(format!("New quest: {}", def.name));
+    }
+}
+
+/// Moves every quest along with whatever happened during the tick, starting those whose start
+/// was just done and handing out the rewards of those which are finished.
+pub fn update_quests(ecs: &mut World) {
+    let events: Vec<GameEvent> = {
+        let mut reader = ecs.fetch_mut::<QuestEvents>();
+        ecs.fetch::<GameEvents>().read(&mut reader.0).cloned().collect()
+    };
+    let starting: Vec<String> = ecs.fetch::<QuestDefs>().0.iter()
+        .filter(|(id, _def)| ecs.fetch::<Quests>().get(id).is_none())
+        .filter(|(_id, def)| def.start.as_ref().is_some_and(|start| events.iter().any(|event| start.done_by(event))))
+        .map(|(id, _def)| id.clone())
+        .collect();
+    for id in &starting {
+        start_quest(ecs, id);
+    }
+
+    let mut rewards = vec![];
+    {
+        let defs = ecs.fetch::<QuestDefs>();
+        let mut quests = ecs.fetch_mut::<Quests>();
+        let mut notifications = ecs.fetch_mut::<Notifications>();
+        let players = ecs.read_storage::<Player>();
+        let inventories = ecs.read_storage::<Inventory>();
+        let inventory = (&players, &inventories).join().next().map(|(_player, inventory)| inventory);
+        for (id, progress) in quests.0.iter_mut().filter(|(_id, progress)| !progress.completed) {
+            let def = match defs.get(id) {
+                Some(def) => def,
+                None => continue,
+            };
+            // Whatever started a quest does not also count towards it
+            let events = if starting.contains(id) { &[][..] } else { &events[..] };
+            if advance(def, progress, events, inventory, &mut notifications) {
+                rewards.extend(def.rewards.iter().cloned());
+            }
+        }
+    }
+    for reward in rewards {
+        match reward {
+            Reward::Item { id, count } => simulation::give_item(ecs, &id, count),
+            Reward::Flag(flag) => ecs.fetch_mut::<Flags>().set(&flag),
+            Reward::Counter { name, amount } => {
+                ecs.fetch_mut::<Flags>().add(&name, amount);
+            }
+        }
+    }
+}
+
+/// Marks off the objectives of the current stage done by the events, moving on through as many
+/// stages as are finished. Returns whether the quest was just completed.
+fn advance(def: &QuestDef, progress: &mut QuestProgress, events: &[GameEvent], inventory: Option<&Inventory>, notifications: &mut Notifications) -> bool {
+    let mut events = events;
+    while let Some(stage) = def.stages.get(progress.stage) {
+        progress.done.resize(stage.objectives.len(), false);
+        for (objective, done) in stage.objectives.iter().zip(progress.done.iter_mut()) {
+            *done |= objective.met_by(inventory) || events.iter().any(|event| objective.done_by(event));
+        }
+        if !progress.done.iter().all(|done| *done) {
+            return false;
+        }
+        progress.stage += 1;
+        progress.done.clear();
+        // Anything that happened only counts towards the stage it happened during
+        events = &[];
+        if let Some(next) = def.stages.get(progress.stage) {
+            notifications.push(format!("{}: {}", def.name, next.description));
+        }
+    }
+    progress.completed = true;
+    notifications.push(format!("Quest complete: {}", def.name));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, trigger, with_scripts, with_quests};
+    use crate::map;
+    use crate::simulation;
+    use crate::item::{self, ItemDefs};
+    use crate::input::Input;
+    use crate::Direction;
+    use std::path::Path;
+    use crate::script::Scripts;
+
+    fn quest_progress(game: &Headless, id: &str) -> Option<QuestProgress> {
+        game.ecs.fetch::<Quests>().get(id).cloned()
+    }
+
+    #[test]
+    fn quests_follow_what_the_player_does() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        with_quests(&mut game, "follow", &[
+            ("apples", r#"(
+                name: "Apple Picking",
+                description: "The gardener wants apples.",
+                start: Some(Talk("Gardener")),
+                stages: [
+                    (description: "Pick two apples.", objectives: [Collect(item: "apple", count: 2)]),
+                    (description: "Bring them over and look at the porch.", objectives: [Talk("Gardener"), Reach("Porch")]),
+                ],
+                rewards: [Item(id: "coin", count: 3), Flag("picked_apples"), Counter(name: "reputation", amount: 2)],
+            )"#),
+            ("errand", r#"(
+                name: "Errand",
+                description: "Started by walking onto the porch.",
+                stages: [(description: "Talk to the gardener.", objectives: [Talk("Gardener")])],
+            )"#),
+        ]);
+        game.spawn(&[
+            object("Gardener", "npc", (1, 0), &[("dialogue", "gardener")]),
+            object("Apples", "item", (0, 3), &[("item", "apple"), ("count", "2")]),
+            trigger("Porch", (5, 5), (1, 1), &[("on_enter", "quest errand")]),
+        ]);
+
+        game.press(Direction::Right);
+        game.step(10);
+        assert_eq!(game.interact(), Some("gardener".to_string()));
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["New quest: Apple Picking".to_string()]);
+        // Talking to start the quest does not also count towards it
+        assert_eq!(quest_progress(&game, "apples"), Some(QuestProgress { stage: 0, done: vec![false], completed: false }));
+        assert!(quest_progress(&game, "errand").is_none());
+
+        game.input(Input::MoveTo(0, 3));
+        game.step(80);
+        assert!(game.notifications().contains(&"Apple Picking: Bring them over and look at the porch.".to_string()));
+        assert_eq!(quest_progress(&game, "apples").unwrap().stage, 1);
+
+        game.input(Input::MoveTo(0, 0));
+        game.step(80);
+        game.press(Direction::Right);
+        game.step(10);
+        game.interact();
+        game.step(1);
+        assert_eq!(quest_progress(&game, "apples").unwrap().done, vec![true, false]);
+
+        // Heading straight down first, since cutting past the gardener is blocked by him
+        game.input(Input::MoveTo(0, 5));
+        game.step(100);
+        game.input(Input::MoveTo(5, 5));
+        game.step(100);
+        assert_eq!(game.player_tile(), (5, 5));
+        let notifications = game.notifications();
+        assert!(notifications.contains(&"Quest complete: Apple Picking".to_string()), "{:?}", notifications);
+        assert!(notifications.contains(&"New quest: Errand".to_string()), "{:?}", notifications);
+        assert!(game.ecs.fetch::<Quests>().is_completed("apples"));
+        assert!(game.ecs.fetch::<Quests>().is_active("errand"));
+        assert_eq!(game.carried("coin"), 3);
+        let flags = game.ecs.fetch::<simulation::Flags>();
+        assert!(flags.is_set("picked_apples"));
+        assert_eq!(flags.count("reputation"), 2);
+    }
+
+    #[test]
+    fn quests_and_counters_are_saved() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.ecs.insert(QuestDefs::load(Path::new(QUEST_DIR)).expect("could not load quests"));
+        with_scripts(&mut game, "counters", &[
+            ("tally", r#"
+                fn run() {
+                    add_to_counter("visits", 2);
+                    if counter("visits") >= 2 {
+                        start_quest("herbs");
+                    }
+                }
+            "#),
+        ]);
+        game.ecs.fetch_mut::<Scripts>().call("tally", "run", "");
+        game.step(1);
+        assert!(game.ecs.fetch::<Quests>().is_active("herbs"));
+
+        game.reload();
+        assert_eq!(game.ecs.fetch::<simulation::Flags>().count("visits"), 2);
+        assert_eq!(quest_progress(&game, "herbs").unwrap().stage, 0);
+
+        simulation::give_item(&mut game.ecs, "herb", 1);
+        game.step(1);
+        game.reload();
+        assert_eq!(quest_progress(&game, "herbs"), Some(QuestProgress { stage: 1, done: vec![false], completed: false }));
+    }
+
+    #[test]
+    fn shipped_quests_only_use_items_that_exist() {
+        let quests = QuestDefs::load(Path::new(QUEST_DIR)).expect("could not load quests");
+        let items = ItemDefs::load(Path::new(item::ITEM_DEFS_PATH)).expect("could not load item definitions");
+        assert!(!quests.0.is_empty());
+        for (id, quest) in &quests.0 {
+            let collected = quest.stages.iter().flat_map(|stage| &stage.objectives).filter_map(|objective| match objective {
+                Objective::Collect { item, .. } => Some(item),
+                _ => None,
+            });
+            let rewarded = quest.rewards.iter().filter_map(|reward| match reward {
+                Reward::Item { id, .. } => Some(id),
+                _ => None,
+            });
+            for item in collected.chain(rewarded) {
+                assert!(items.get(item).is_some(), "quest {} uses unknown item {}", id, item);
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{GameState, Point2, RootState};
+use crate::component::{Inventory, Player};
+use crate::input::Action;
+use crate::item::ItemDefs;
+use crate::quest::{QuestDefs, Quests};
+use crate::simulation::TICKS_PER_SECOND;
+use crate::widget;
+use ggez::{graphics, Context, GameResult, timer};
+use specs::prelude::*;
+
+const PANEL_WIDTH_PX: f32 = 600.0;
+const PANEL_HEIGHT_PX: f32 = 470.0;
+const PANEL_PADDING_PX: f32 = 16.0;
+const ROW_HEIGHT_PX: f32 = 28.0;
+const TITLE_FONT_SIZE: f32 = 24.0;
+const TEXT_FONT_SIZE: f32 = 20.0;
+/// The list of quests takes up the left of the panel, the details of the selected one the right.
+const LIST_WIDTH_PX: f32 = 220.0;
+const HIGHLIGHT: [f32; 4] = [1.0, 0.85, 0.4, 1.0];
+const FADED: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const DONE: [f32; 4] = [0.55, 0.55, 0.55, 1.0];
+
+/// The quest log, listing the quests the player is on followed by the ones they have finished.
+#[derive(Default, Debug)]
+pub struct QuestLog {
+    selected: usize,
+}
+
+/// A started quest as shown in the log.
+struct LogEntry {
+    name: String,
+    completed: bool,
+    /// The description of the quest followed by where the player is up to with it.
+    details: Vec<(String, [f32; 4])>,
+}
+
+pub fn open_quest_log(state: &mut GameState) {
+    state.quest_log = Some(QuestLog::default());
+    state.states.push(RootState::QuestLog);
+}
+
+fn close_quest_log(state: &mut GameState) {
+    state.quest_log = None;
+    state.states.pop();
+}
+
+pub fn quest_log_input(state: &mut GameState, action: Action) {
+    let entries = log_entries(state).len();
+    let log = match &mut state.quest_log {
+        Some(log) => log,
+        None => return close_quest_log(state),
+    };
+    match action {
+        Action::QuestLog | Action::Pause => close_quest_log(state),
+        Action::MoveUp if entries > 0 => log.selected = (log.selected + entries - 1) % entries,
+        Action::MoveDown if entries > 0 => log.selected = (log.selected + 1) % entries,
+        _ => (),
+    }
+}
+
+/// The world stays paused while the quest log is open.
+pub fn quest_log_update(_state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    while timer::check_update_time(ctx, TICKS_PER_SECOND) {}
+    Ok(())
+}
+
+/// Every quest the player has started, the active ones first.
+fn log_entries(state: &GameState) -> Vec<LogEntry> {
+    let defs = state.ecs.fetch::<QuestDefs>();
+    let quests = state.ecs.fetch::<Quests>();
+    let items = state.ecs.fetch::<ItemDefs>();
+    let players = state.ecs.read_storage::<Player>();
+    let inventories = state.ecs.read_storage::<Inventory>();
+    let inventory = (&players, &inventories).join().next().map(|(_player, inventory)| inventory);
+
+    let mut entries: Vec<LogEntry> = quests.0.iter()
+        .filter_map(|(id, progress)| {
+            let def = defs.get(id)?;
+            let mut details = vec![(def.description.clone(), graphics::WHITE.into())];
+            match def.stages.get(progress.stage) {
+                Some(stage) if !progress.completed => {
+                    details.push((stage.description.clone(), HIGHLIGHT));
+                    for (index, objective) in stage.objectives.iter().enumerate() {
+                        let done = progress.done.get(index).copied().unwrap_or(false);
+                        let (marker, color) = if done { ("[x] ", DONE) } else { ("[ ] ", FADED) };
+                        details.push((format!("{}{}", marker, objective.describe(&items, inventory)), color));
+                    }
+                }
+                _ => details.push(("Completed".to_string(), DONE)),
+            }
+            Some(LogEntry { name: def.name.clone(), completed: progress.completed, details })
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.completed);
+    entries
+}
+
+pub fn quest_log_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    if let Some(log) = &state.quest_log {
+        render_quest_log(ctx, state, log)?;
+    }
+    Ok(())
+}
+
+fn render_quest_log(ctx: &mut Context, state: &GameState, log: &QuestLog) -> GameResult<()> {
+    let entries = log_entries(state);
+    let panel = widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX);
+    widget::draw_panel(ctx, panel)?;
+
+    let left = panel.x + PANEL_PADDING_PX;
+    let mut top = panel.y + PANEL_PADDING_PX;
+    let title = graphics::Text::new(("Quests", state.font, TITLE_FONT_SIZE));
+    graphics::draw(ctx, &title, (Point2::new(left, top), graphics::WHITE))?;
+    top += TITLE_FONT_SIZE + 12.0;
+
+    if entries.is_empty() {
+        let empty = graphics::Text::new(("No quests yet.", state.font, TEXT_FONT_SIZE));
+        graphics::draw(ctx, &empty, (Point2::new(left, top), FADED.into()))?;
+    }
+
+    for (index, entry) in entries.iter().enumerate() {
+        let color = match (index == log.selected, entry.completed) {
+            (true, _) => HIGHLIGHT,
+            (false, true) => DONE,
+            (false, false) => FADED,
+        };
+        let mut text = graphics::Text::new((entry.name.as_str(), state.font, TEXT_FONT_SIZE));
+        text.set_bounds(Point2::new(LIST_WIDTH_PX, f32::INFINITY), graphics::Align::Left);
+        graphics::draw(ctx, &text, (Point2::new(left, top + index as f32 * ROW_HEIGHT_PX), color.into()))?;
+    }
+
+    // The details of the selected quest wrap within the right of the panel
+    if let Some(entry) = entries.get(log.selected) {
+        let details_left = left + LIST_WIDTH_PX + PANEL_PADDING_PX;
+        let width = panel.x + PANEL_WIDTH_PX - PANEL_PADDING_PX - details_left;
+        let mut details_top = top;
+        for (line, color) in &entry.details {
+            let mut text = graphics::Text::new((line.as_str(), state.font, TEXT_FONT_SIZE));
+            text.set_bounds(Point2::new(width, f32::INFINITY), graphics::Align::Left);
+            graphics::draw(ctx, &text, (Point2::new(details_left, details_top), (*color).into()))?;
+            let (_width, height) = text.dimensions(ctx);
+            details_top += height as f32 + 8.0;
+        }
+    }
+    Ok(())
+}
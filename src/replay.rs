@@ -0,0 +1,164 @@
+use crate::input::Input;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where the inputs of the current session are recorded unless we are replaying.
+pub const RECORDING_PATH: &str = "last_session.replay";
+
+const REPLAY_HEADER: &str = "# tiny-topdown-game replay v1";
+
+/// An input along with the number of ticks the simulation had completed when it arrived.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReplayEvent {
+    pub tick: u64,
+    pub input: Input,
+}
+
+/// A recorded sequence of inputs that can be fed back into the simulation.
+/// Events are consumed as they become due, in the order they were recorded.
+#[derive(Default, Debug)]
+pub struct Replay {
+    pub events: VecDeque<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let contents = fs::read_to_string(path)?;
+        contents.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Removes and returns every input which should be applied before the given tick runs.
+    pub fn pop_due(&mut self, tick: u64) -> Vec<Input> {
+        let mut due = Vec::new();
+        while let Some(event) = self.events.front() {
+            if event.tick > tick {
+                break;
+            }
+            due.push(event.input);
+            self.events.pop_front();
+        }
+        due
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", REPLAY_HEADER)?;
+        for event in self.events.iter() {
+            writeln!(f, "{} {}", event.tick, event.input)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the line based replay format, where each line is a tick followed by an input.
+/// Blank lines and lines starting with `#` are ignored.
+impl FromStr for Replay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Replay, String> {
+        let mut replay = Replay::default();
+        for (line_number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (tick, input) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let tick = tick.parse::<u64>()
+                .map_err(|e| format!("line {}: invalid tick '{}': {}", line_number + 1, tick, e))?;
+            let input = input.parse::<Input>()
+                .map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+            if let Some(last) = replay.events.back() {
+                if last.tick > tick {
+                    return Err(format!("line {}: ticks must not go backwards", line_number + 1));
+                }
+            }
+            replay.events.push_back(ReplayEvent { tick, input });
+        }
+        Ok(replay)
+    }
+}
+
+/// Appends inputs to a replay file as they happen.
+/// Each line is written straight away so that a crash still leaves a usable replay behind.
+pub struct ReplayRecorder {
+    file: File,
+}
+
+impl ReplayRecorder {
+    pub fn create(path: &Path) -> io::Result<ReplayRecorder> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", REPLAY_HEADER)?;
+        Ok(ReplayRecorder { file })
+    }
+
+    pub fn record(&mut self, tick: u64, input: Input) {
+        if let Err(e) = writeln!(self.file, "{} {}", tick, input) {
+            println!("Could not record input to replay: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::simulation::Ticks;
+    use crate::input::Input;
+    use crate::Direction;
+
+    #[test]
+    fn replay_reproduces_live_inputs() {
+        let mut live = Headless::new(Headless::floor(10, 10));
+        live.press(Direction::Right);
+        live.step(4);
+        live.press(Direction::Right);
+        live.press(Direction::Down);
+        live.step(26);
+
+        let mut replay: Replay = "0 MoveRight\n4 MoveRight\n4 MoveDown\n30 Pause\n".parse().unwrap();
+        let mut replayed = Headless::new(Headless::floor(10, 10));
+        replayed.play(&mut replay);
+
+        assert_eq!(replayed.ecs.fetch::<Ticks>().0, 30);
+        assert_eq!(replayed.player_position(), live.player_position());
+    }
+
+    #[test]
+    fn replay_round_trips_through_text() {
+        let text = "# tiny-topdown-game replay v1\n3 MoveUp\n3 MoveLeft\n10 ToggleFps\n12 Pause\n";
+        let replay: Replay = text.parse().unwrap();
+        assert_eq!(replay.events.len(), 4);
+        assert_eq!(replay.to_string(), text);
+    }
+
+    #[test]
+    fn replay_rejects_ticks_going_backwards() {
+        assert!("5 MoveUp\n4 MoveDown\n".parse::<Replay>().is_err());
+        assert!("5 Jump\n".parse::<Replay>().is_err());
+    }
+
+    #[test]
+    fn stick_input_round_trips_through_replays() {
+        let mut replay: Replay = "0 Stick 0.75 -0.5\n20 Stick 0 0\n".parse().unwrap();
+        assert_eq!(replay.events[0].input, Input::Stick(0.75, -0.5));
+        let mut game = Headless::new(Headless::floor(20, 20));
+        game.play(&mut replay);
+        assert_eq!(game.ecs.fetch::<Ticks>().0, 20);
+    }
+
+    #[test]
+    fn item_inputs_round_trip_through_replays() {
+        for input in [Input::UseItem(3), Input::DropItem(0)].iter() {
+            assert_eq!(input.to_string().parse::<Input>(), Ok(*input));
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use crate::component::*;
+use crate::death_system::SpawnPoint;
+use crate::clock::Clock;
+use crate::quest::Quests;
+use crate::visibility_system::Explored;
+use crate::trigger_system::SpentTriggers;
+use crate::simulation::{self, Flags, MapPath, Ticks};
+use crate::{Direction, Point2, AUTHOR, GAME_ID};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use specs::error::NoError;
+use specs::prelude::*;
+use specs::saveload::{ConvertSaveload, DeserializeComponents, MarkedBuilder, Marker, SerializeComponents, SimpleMarker, SimpleMarkerAllocator};
+use specs_derive::Component;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The directory save slots are kept in, inside the user data directory of the game.
+pub const SAVE_DIR: &str = "saves";
+pub const SAVE_SLOTS: usize = 3;
+
+/// Every save starts with this followed by the version of the format it was written in.
+const SAVE_HEADER: &str = "# tiny-topdown-game save v";
+/// Bumped whenever the save format changes in a way older saves need migrating for.
+pub const SAVE_VERSION: u32 = 7;
+
+/// Rewrites a save from one version of the format into the next.
+type Migration = fn(String) -> Result<String, SaveError>;
+
+/// Migrations in order, where the first one upgrades version 1 saves into version 2 and so on.
+/// There has to be one for every version before `SAVE_VERSION`.
+const MIGRATIONS: [Migration; (SAVE_VERSION - 1) as usize] = [
+    // Version 2 saves audio emitters
+    add_component,
+    // Version 3 saves trigger zones
+    add_component,
+    // Version 4 saves which entities have scripts
+    add_component,
+    // Version 5 saves counters alongside the flags
+    count_flags,
+    // Version 6 saves light sources, in a second list of components
+    add_component_list,
+    // Version 7 saves knockback, paths being followed and attacks in progress
+    add_movement_components,
+];
+
+/// Only marked entities are saved. Everything the map spawns is marked, along with the player
+/// and anything else which should outlive a trip to the menu, but not short lived things like swings.
+pub struct SerializeMe;
+
+pub type SaveMarker = SimpleMarker<SerializeMe>;
+
+/// Everything in a save which is not a component, carried by an entity of its own
+/// while saving and loading since only entities can be saved.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct SavedWorld {
+    pub map: String,
+    pub ticks: u64,
+    pub spawn_point: (i32, i32),
+    pub flags: Flags,
+    /// Saves from before quests simply have none started.
+    #[serde(default)]
+    pub quests: Quests,
+    /// Saves from before the clock start out on the first morning.
+    #[serde(default)]
+    pub clock: Clock,
+    /// Saves from before fog have nothing explored.
+    #[serde(default)]
+    pub explored: Explored,
+    /// Saves from before remembering triggers of other maps start them all over.
+    #[serde(default)]
+    pub spent_triggers: SpentTriggers,
+}
+
+/// The player is saved along with its velocity, so that a game saved mid knockback carries on sliding.
+#[derive(Serialize, Deserialize)]
+pub struct PlayerData {
+    pub direction: Direction,
+    /// Saves from before velocity was saved start out standing still.
+    #[serde(default)]
+    pub velocity: (f32, f32),
+}
+
+impl<M: Marker> ConvertSaveload<M> for Player {
+    type Data = PlayerData;
+    type Error = NoError;
+
+    fn convert_into<F>(&self, _ids: F) -> Result<PlayerData, NoError>
+    where
+        F: FnMut(Entity) -> Option<M>,
+    {
+        Ok(PlayerData { direction: self.direction, velocity: (self.velocity.x, self.velocity.y) })
+    }
+
+    fn convert_from<F>(data: PlayerData, _ids: F) -> Result<Player, NoError>
+    where
+        F: FnMut(M) -> Option<Entity>,
+    {
+        Ok(Player {
+            direction: data.direction,
+            velocity: Point2::new(data.velocity.0, data.velocity.1),
+            acceleration: Point2::origin(),
+            animation_index: 0,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KnockbackData {
+    pub velocity: (f32, f32),
+}
+
+impl<M: Marker> ConvertSaveload<M> for Knockback {
+    type Data = KnockbackData;
+    type Error = NoError;
+
+    fn convert_into<F>(&self, _ids: F) -> Result<KnockbackData, NoError>
+    where
+        F: FnMut(Entity) -> Option<M>,
+    {
+        Ok(KnockbackData { velocity: (self.velocity.x, self.velocity.y) })
+    }
+
+    fn convert_from<F>(data: KnockbackData, _ids: F) -> Result<Knockback, NoError>
+    where
+        F: FnMut(M) -> Option<Entity>,
+    {
+        Ok(Knockback { velocity: Point2::new(data.velocity.0, data.velocity.1) })
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Serialize(ron::Error),
+    Parse(ron::Error),
+    MissingHeader,
+    /// The save was written by a newer version of the game than this one, or is corrupt.
+    UnsupportedVersion(u32),
+    /// The save does not say which map it was made on.
+    MissingWorld,
+    MissingMap(String),
+    /// The save is too broken to be migrated.
+    Corrupt,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "could not access save: {}", e),
+            SaveError::Serialize(e) => write!(f, "could not write save: {}", e),
+            SaveError::Parse(e) => write!(f, "could not parse save: {}", e),
+            SaveError::MissingHeader => write!(f, "save is missing its header"),
+            SaveError::UnsupportedVersion(version) => write!(f, "save version {} is not supported", version),
+            SaveError::MissingWorld => write!(f, "save does not say which map it was made on"),
+            SaveError::MissingMap(map) => write!(f, "saved map {} does not exist", map),
+            SaveError::Corrupt => write!(f, "save is corrupt"),
+        }
+    }
+}
+
+/// Where save slots are kept, in the same user data directory ggez uses for the game.
+/// Without one to put them in they end up in the working directory instead.
+pub fn save_dir() -> PathBuf {
+    ProjectDirs::from("", AUTHOR, GAME_ID)
+        .map(|dirs| dirs.data_dir().join(SAVE_DIR))
+        .unwrap_or_else(|| PathBuf::from(SAVE_DIR))
+}
+
+pub fn slot_path(slot: usize) -> PathBuf {
+    save_dir().join(format!("slot{}.ron", slot + 1))
+}
+
+/// When each slot was last saved to, or nothing for empty slots.
+/// The slots on disk are only looked at by `scan`, so this is as fresh as the last scan.
+#[derive(Clone, Debug, Default)]
+pub struct SlotTimes {
+    pub times: Vec<Option<SystemTime>>,
+}
+
+impl SlotTimes {
+    pub fn scan() -> SlotTimes {
+        SlotTimes {
+            times: (0..SAVE_SLOTS)
+                .map(|slot| fs::metadata(slot_path(slot)).and_then(|metadata| metadata.modified()).ok())
+                .collect(),
+        }
+    }
+
+    /// The slot which was saved to most recently.
+    pub fn latest_slot(&self) -> Option<usize> {
+        self.times.iter().enumerate()
+            .filter_map(|(slot, time)| time.map(|time| (slot, time)))
+            .max_by_key(|(_slot, time)| *time)
+            .map(|(slot, _time)| slot)
+    }
+
+    /// The slot a new game saves into, the first empty one or otherwise the one saved to longest ago.
+    pub fn new_game_slot(&self) -> usize {
+        self.times.iter().position(Option::is_none)
+            .or_else(|| self.times.iter().enumerate().min_by_key(|(_slot, time)| *time).map(|(slot, _time)| slot))
+            .unwrap_or(0)
+    }
+}
+
+/// Writes every marked entity to disk along with the current map.
+pub fn save(ecs: &mut World, path: &Path) -> Result<(), SaveError> {
+    let contents = save_to_string(ecs)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(SaveError::Io)?;
+    }
+    fs::write(path, contents).map_err(SaveError::Io)
+}
+
+pub fn save_to_string(ecs: &mut World) -> Result<String, SaveError> {
+    let saved_world = SavedWorld {
+        map: ecs.fetch::<MapPath>().0.clone(),
+        ticks: ecs.fetch::<Ticks>().0,
+        spawn_point: ecs.fetch::<SpawnPoint>().0,
+        flags: (*ecs.fetch::<Flags>()).clone(),
+        quests: (*ecs.fetch::<Quests>()).clone(),
+        clock: *ecs.fetch::<Clock>(),
+        explored: (*ecs.fetch::<Explored>()).clone(),
+        spent_triggers: (*ecs.fetch::<SpentTriggers>()).clone(),
+    };
+    let saved_world = ecs.create_entity().with(saved_world).marked::<SaveMarker>().build();
+
+    let mut body = Vec::new();
+    let mut more = Vec::new();
+    let result = {
+        let storages = (
+            ecs.read_storage::<Position>(),
+            ecs.read_storage::<Renderable>(),
+            ecs.read_storage::<Player>(),
+            ecs.read_storage::<Npc>(),
+            ecs.read_storage::<BlocksTile>(),
+            ecs.read_storage::<Ai>(),
+            ecs.read_storage::<Interactable>(),
+            ecs.read_storage::<Item>(),
+            ecs.read_storage::<Inventory>(),
+            ecs.read_storage::<Health>(),
+            ecs.read_storage::<Hurtbox>(),
+            ecs.read_storage::<Hitbox>(),
+            ecs.read_storage::<SavedWorld>(),
+            ecs.read_storage::<AudioEmitter>(),
+            ecs.read_storage::<TriggerZone>(),
+            ecs.read_storage::<Scripted>(),
+        );
+        // Only sixteen storages fit in one list, so the rest go in a second list of the same entities
+        let more_storages = (
+            ecs.read_storage::<LightSource>(),
+            ecs.read_storage::<Knockback>(),
+            ecs.read_storage::<PathFollow>(),
+            ecs.read_storage::<Attacking>(),
+        );
+        ron::ser::Serializer::new(&mut body, Some(ron::ser::PrettyConfig::default()), false)
+            .and_then(|mut serializer| {
+                SerializeComponents::<NoError, SaveMarker>::serialize(&storages, &ecs.entities(), &ecs.read_storage::<SaveMarker>(), &mut serializer)
+            })
+            .and_then(|()| ron::ser::Serializer::new(&mut more, Some(ron::ser::PrettyConfig::default()), false))
+            .and_then(|mut serializer| {
+                SerializeComponents::<NoError, SaveMarker>::serialize(&more_storages, &ecs.entities(), &ecs.read_storage::<SaveMarker>(), &mut serializer)
+            })
+    };
+    ecs.delete_entity(saved_world).expect("could not delete saved world");
+    result.map_err(SaveError::Serialize)?;
+
+    Ok(format!("{}{}\n{}\n{}\n", SAVE_HEADER, SAVE_VERSION, String::from_utf8_lossy(&body), String::from_utf8_lossy(&more)))
+}
+
+/// Loads a save into a fresh world, upgrading it first if it was written by an older version.
+/// Item definitions are not part of a save and still have to be inserted into the world.
+pub fn load(path: &Path) -> Result<World, SaveError> {
+    let contents = fs::read_to_string(path).map_err(SaveError::Io)?;
+    load_from_str(&contents)
+}
+
+pub fn load_from_str(contents: &str) -> Result<World, SaveError> {
+    let (header, body) = contents.split_at(contents.find('\n').unwrap_or(contents.len()));
+    let version = header.trim().strip_prefix(SAVE_HEADER)
+        .and_then(|version| version.parse::<u32>().ok())
+        .ok_or(SaveError::MissingHeader)?;
+    let body = migrate(version, body.to_string())?;
+
+    let mut ecs = simulation::create_empty_world();
+    {
+        let mut storages = (
+            ecs.write_storage::<Position>(),
+            ecs.write_storage::<Renderable>(),
+            ecs.write_storage::<Player>(),
+            ecs.write_storage::<Npc>(),
+            ecs.write_storage::<BlocksTile>(),
+            ecs.write_storage::<Ai>(),
+            ecs.write_storage::<Interactable>(),
+            ecs.write_storage::<Item>(),
+            ecs.write_storage::<Inventory>(),
+            ecs.write_storage::<Health>(),
+            ecs.write_storage::<Hurtbox>(),
+            ecs.write_storage::<Hitbox>(),
+            ecs.write_storage::<SavedWorld>(),
+            ecs.write_storage::<AudioEmitter>(),
+            ecs.write_storage::<TriggerZone>(),
+            ecs.write_storage::<Scripted>(),
+        );
+        let mut more_storages = (
+            ecs.write_storage::<LightSource>(),
+            ecs.write_storage::<Knockback>(),
+            ecs.write_storage::<PathFollow>(),
+            ecs.write_storage::<Attacking>(),
+        );
+        let mut markers = ecs.write_storage::<SaveMarker>();
+        let mut allocator = ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>();
+        let mut deserializer = ron::de::Deserializer::from_str(&body).map_err(SaveError::Parse)?;
+        DeserializeComponents::<NoError, SaveMarker>::deserialize(&mut storages, &ecs.entities(), &mut markers, &mut allocator, &mut deserializer)
+            .map_err(SaveError::Parse)?;
+        // The second list follows the first, referring to the same entities by their markers
+        let more = deserializer.remainder().into_owned();
+        let mut deserializer = ron::de::Deserializer::from_str(&more).map_err(SaveError::Parse)?;
+        DeserializeComponents::<NoError, SaveMarker>::deserialize(&mut more_storages, &ecs.entities(), &mut markers, &mut allocator, &mut deserializer)
+            .map_err(SaveError::Parse)?;
+    }
+
+    let (entity, saved_world) = {
+        let saved_worlds = ecs.read_storage::<SavedWorld>();
+        let (entity, saved_world) = (&ecs.entities(), &saved_worlds).join().next().ok_or(SaveError::MissingWorld)?;
+        (entity, saved_world.clone())
+    };
+    ecs.delete_entity(entity).expect("could not delete saved world");
+    if !simulation::map_exists(&saved_world.map) {
+        return Err(SaveError::MissingMap(saved_world.map));
+    }
+    simulation::load_map_tiles(&mut ecs, Path::new(&saved_world.map));
+    ecs.insert(Ticks(saved_world.ticks));
+    ecs.insert(SpawnPoint(saved_world.spawn_point));
+    ecs.insert(saved_world.flags);
+    ecs.insert(saved_world.quests);
+    ecs.insert(saved_world.clock);
+    ecs.insert(saved_world.explored);
+    ecs.insert(saved_world.spent_triggers);
+
+    // What the player can see is worked out again rather than saved
+    let players: Vec<Entity> = (&ecs.entities(), &ecs.read_storage::<Player>()).join().map(|(entity, _player)| entity).collect();
+    for player in players {
+        ecs.write_storage::<Viewport>().insert(player, Viewport { tiles: vec![], dirty: true })
+            .expect("could not insert viewport");
+    }
+    ecs.maintain();
+    Ok(ecs)
+}
+
+/// Upgrades the body of a save written in an older version of the format to the current one.
+fn migrate(version: u32, mut body: String) -> Result<String, SaveError> {
+    if version == 0 || version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+    for migration in MIGRATIONS.iter().skip(version as usize - 1) {
+        body = migration(body)?;
+    }
+    Ok(body)
+}
+
+/// Makes room for a component added to the end of the saved storages,
+/// which none of the entities in an older save have.
+fn add_component(body: String) -> Result<String, SaveError> {
+    const COMPONENTS: &str = "components: (";
+    let mut migrated = String::with_capacity(body.len());
+    let mut rest = body.as_str();
+    while let Some(start) = find_outside_strings(rest, COMPONENTS) {
+        let (before, components) = rest.split_at(start + COMPONENTS.len());
+        migrated.push_str(before);
+        let end = closing_paren(components).ok_or(SaveError::Corrupt)?;
+        migrated.push_str(&components[..end]);
+        migrated.push_str(", None");
+        rest = &components[end..];
+    }
+    migrated.push_str(rest);
+    Ok(migrated)
+}
+
+/// Makes room for components added to the end of the second list of saved storages, which none
+/// of the entities in an older save have. The first list is left alone.
+fn add_movement_components(body: String) -> Result<String, SaveError> {
+    let start = body.find('[').ok_or(SaveError::Corrupt)? + 1;
+    let end = start + closing(&body[start..], '[', ']').ok_or(SaveError::Corrupt)?;
+    let (first, more) = body.split_at(end);
+    let more = add_component(add_component(add_component(more.to_string())?)?)?;
+    Ok(format!("{}{}", first, more))
+}
+
+/// Starts a second list of components after the first, for components which no longer fit in it.
+/// None of the entities in an older save have any of them.
+fn add_component_list(body: String) -> Result<String, SaveError> {
+    Ok(format!("{}\n[]\n", body.trim_end()))
+}
+
+/// Turns the flags of a save from a plain list of names into the named fields they are kept in
+/// now that there are counters as well. Saves without a saved world are left for loading to reject.
+fn count_flags(body: String) -> Result<String, SaveError> {
+    const FLAGS: &str = "flags: (";
+    let start = match find_outside_strings(&body, FLAGS) {
+        Some(start) => start,
+        None => return Ok(body),
+    };
+    let (before, flags) = body.split_at(start + FLAGS.len());
+    let end = closing_paren(flags).ok_or(SaveError::Corrupt)?;
+    Ok(format!("{}set: {}, counters: {{}}{}", before, &flags[..end], &flags[end..]))
+}
+
+/// Finds some text in a save which is not part of a string.
+fn find_outside_strings(text: &str, pattern: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if !in_string && text[index..].starts_with(pattern) => return Some(index),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Finds the paren closing one which was just opened, skipping over any inside of strings.
+fn closing_paren(text: &str) -> Option<usize> {
+    closing(text, '(', ')')
+}
+
+/// Finds the bracket closing one which was just opened, skipping over any inside of strings.
+fn closing(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => (),
+            _ if c == open => depth += 1,
+            _ if c == close && depth == 0 => return Some(index),
+            _ if c == close => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, enemy};
+    use crate::map;
+    use crate::simulation::{self, Ticks};
+    use crate::input::Input;
+    use std::path::Path;
+
+    #[test]
+    fn saved_games_load_back_into_a_fresh_world() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.input(Input::MoveTo(6, 1));
+        game.step(180);
+        assert_eq!(game.player_tile(), (6, 1));
+        assert_eq!(game.carried("apple"), 3);
+        game.ecs.fetch_mut::<simulation::Flags>().set("met_elder");
+        for (_player, health) in (&game.ecs.read_storage::<Player>(), &mut game.ecs.write_storage::<Health>()).join() {
+            health.current = 3;
+        }
+        game.input(Input::DropItem(0));
+        game.step(1);
+        let ticks = game.ecs.fetch::<Ticks>().0;
+        let npcs = game.ecs.read_storage::<Npc>().join().count();
+
+        game.reload();
+        assert_eq!(game.player_tile(), (6, 1));
+        assert_eq!(game.player_health(), 3);
+        assert_eq!(game.lying_around("apple"), 3);
+        assert_eq!(game.lying_around("coin"), 5);
+        assert!(game.ecs.fetch::<simulation::Flags>().is_set("met_elder"));
+        assert_eq!(game.ecs.fetch::<Ticks>().0, ticks);
+        assert_eq!(game.ecs.fetch::<simulation::MapPath>().0, map::BASIC_MAP_PATH);
+        assert_eq!(game.ecs.read_storage::<Npc>().join().count(), npcs);
+        assert_eq!(game.ecs.read_storage::<SavedWorld>().join().count(), 0);
+
+        // The loaded world carries on just like the one that was saved
+        game.interact();
+        assert_eq!(game.carried("apple"), 3);
+        game.input(Input::MoveTo(2, 5));
+        game.step(60);
+        assert_ne!(game.player_tile(), (6, 1));
+        assert!(game.ecs.read_storage::<Viewport>().join().all(|viewport| !viewport.tiles.is_empty()));
+    }
+
+    #[test]
+    fn knockback_paths_and_attacks_in_progress_survive_saving() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.spawn(&[enemy("Slime", (5, 5), "3")]);
+        game.step(1);
+        {
+            let entities = game.ecs.entities();
+            let npcs = game.ecs.read_storage::<Npc>();
+            let players = game.ecs.read_storage::<Player>();
+            let (slime, _npc) = (&entities, &npcs).join().find(|(_slime, npc)| npc.name == "Slime").unwrap();
+            let (player, _player) = (&entities, &players).join().next().unwrap();
+            game.ecs.write_storage::<Knockback>().insert(slime, Knockback { velocity: Point2::new(3.0, -2.0) }).unwrap();
+            game.ecs.write_storage::<PathFollow>().insert(player, PathFollow { path: vec![(1, 0), (2, 0)].into() }).unwrap();
+            game.ecs.write_storage::<Attacking>().insert(player, Attacking { ticks: 4 }).unwrap();
+        }
+
+        game.reload();
+        let knockbacks = game.ecs.read_storage::<Knockback>();
+        let knockback = (&game.ecs.read_storage::<Npc>(), &knockbacks).join().next().unwrap().1;
+        assert_eq!((knockback.velocity.x, knockback.velocity.y), (3.0, -2.0));
+        let paths = game.ecs.read_storage::<PathFollow>();
+        let path = (&game.ecs.read_storage::<Player>(), &paths).join().next().unwrap().1;
+        assert_eq!(path.path, vec![(1, 0), (2, 0)]);
+        let attacks = game.ecs.read_storage::<Attacking>();
+        assert_eq!((&game.ecs.read_storage::<Player>(), &attacks).join().next().unwrap().1.ticks, 4);
+    }
+
+    #[test]
+    fn saves_need_a_known_version() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        let saved = save_to_string(&mut game.ecs).unwrap();
+        assert!(saved.starts_with(&format!("# tiny-topdown-game save v{}\n", SAVE_VERSION)));
+        let body = saved.split_once('\n').unwrap().1;
+        assert!(matches!(load_from_str(body), Err(SaveError::MissingHeader)));
+        let newer = format!("# tiny-topdown-game save v{}\n{}", SAVE_VERSION + 1, body);
+        assert!(matches!(load_from_str(&newer), Err(SaveError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn older_saves_are_migrated() {
+        let v1 = r#"# tiny-topdown-game save v1
+[
+    (marker: (0), components: (Some((x: 64, y: 32)), Some(()), Some((direction: Left)), None, Some(()), None, None, None, None, None, None, None, None)),
+    (marker: (1), components: (None, None, None, None, None, None, None, None, None, None, None, None, Some((map: "assets/map/basic.tmx", ticks: 5, spawn_point: (2, 1), flags: (["(odd) \"name", "components: (sneaky)"]))))),
+]
+"#;
+        let ecs = load_from_str(v1).expect("could not load version 1 save");
+        let game = Headless::from_world(ecs);
+        assert_eq!(game.player_position(), (64.0, 32.0));
+        assert_eq!(game.ecs.fetch::<simulation::Ticks>().0, 5);
+        assert!(game.ecs.fetch::<simulation::Flags>().is_set("(odd) \"name"));
+        assert!(game.ecs.fetch::<simulation::Flags>().is_set("components: (sneaky)"));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{GameState, Point2, HEIGHT_PX, WIDTH_PX};
+use crate::component::Player;
+use crate::events::{GameEvent, Subscription};
+use crate::simulation::TICKS_PER_SECOND;
+use ggez::graphics::{self, Rect};
+use ggez::{Context, GameResult};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use specs::prelude::*;
+use std::collections::VecDeque;
+
+/// How long the screen takes to fade in from black on arriving on a different map.
+const MAP_FADE_SECONDS: f32 = 0.5;
+/// How the screen reacts to the player being hurt.
+const HURT_FLASH_SECONDS: f32 = 0.25;
+const HURT_SHAKE_PX: f32 = 6.0;
+const HURT_SHAKE_SECONDS: f32 = 0.3;
+/// Shakes are only for show, so they are seeded independently of the world and never affect it.
+const SHAKE_SEED: u64 = 0x5a_4a_ce;
+
+/// Something drawn over the whole screen, asked for by gameplay or by scripts.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ScreenEffect {
+    /// Fades the screen over to a colour, which stays covering it until faded back from.
+    FadeTo { color: [f32; 3], seconds: f32 },
+    /// Fades the screen back from being covered by a colour.
+    FadeFrom { color: [f32; 3], seconds: f32 },
+    /// Covers the screen with a colour which straight away starts fading.
+    Flash { color: [f32; 3], seconds: f32 },
+    /// Shakes the world by up to `amplitude` pixels, dying away over `decay` seconds.
+    Shake { amplitude: f32, decay: f32 },
+    /// Tints the world with a colour, as strongly as given from 0.0 for not at all to 1.0
+    /// for nothing but the colour. The tint stays until the world is graded again.
+    Grade { color: [f32; 3], strength: f32 },
+}
+
+/// A fade running from one amount of a colour covering the screen to another.
+#[derive(Debug)]
+struct Fade {
+    color: [f32; 3],
+    from: f32,
+    to: f32,
+    ticks: u32,
+    total: u32,
+}
+
+/// Every effect currently over the screen. Fades play one after another in the order they
+/// were asked for, so that fading to black and back again can be asked for all at once.
+/// Like particles, effects live outside the world and move along with its ticks.
+pub struct ScreenEffects {
+    fades: VecDeque<Fade>,
+    /// The colour covering the screen once the last fade finished, and how much of it.
+    cover: ([f32; 3], f32),
+    flash: Option<Fade>,
+    shake: Option<Fade>,
+    shake_offset: (f32, f32),
+    grade: ([f32; 3], f32),
+    rng: Pcg32,
+    events: Subscription,
+}
+
+impl Default for ScreenEffects {
+    fn default() -> ScreenEffects {
+        ScreenEffects {
+            fades: VecDeque::new(),
+            cover: ([0.0, 0.0, 0.0], 0.0),
+            flash: None,
+            shake: None,
+            shake_offset: (0.0, 0.0),
+            grade: ([1.0, 1.0, 1.0], 0.0),
+            rng: Pcg32::seed_from_u64(SHAKE_SEED),
+            events: Subscription::default(),
+        }
+    }
+}
+
+fn seconds_to_ticks(seconds: f32) -> u32 {
+    (seconds.max(0.0) * TICKS_PER_SECOND as f32).round().max(1.0) as u32
+}
+
+impl Fade {
+    fn new(color: [f32; 3], from: f32, to: f32, seconds: f32) -> Fade {
+        let total = seconds_to_ticks(seconds);
+        Fade { color, from, to, ticks: 0, total }
+    }
+
+    /// How much of the colour covers the screen this far through the fade.
+    fn amount(&self) -> f32 {
+        self.from + (self.to - self.from) * self.ticks as f32 / self.total as f32
+    }
+
+    fn is_done(&self) -> bool {
+        self.ticks >= self.total
+    }
+}
+
+impl ScreenEffects {
+    /// Starts over on a different world, clearing away everything over the old one.
+    pub fn listen(&mut self, ecs: &World) {
+        let events = std::mem::take(&mut self.events);
+        *self = ScreenEffects { events, ..ScreenEffects::default() };
+        self.events.subscribe(ecs);
+    }
+
+    pub fn start(&mut self, effect: ScreenEffect) {
+        match effect {
+            ScreenEffect::FadeTo { color, seconds } => self.fades.push_back(Fade::new(color, 0.0, 1.0, seconds)),
+            ScreenEffect::FadeFrom { color, seconds } => self.fades.push_back(Fade::new(color, 1.0, 0.0, seconds)),
+            ScreenEffect::Flash { color, seconds } => self.flash = Some(Fade::new(color, 1.0, 0.0, seconds)),
+            ScreenEffect::Shake { amplitude, decay } => {
+                // A smaller shake happening during a bigger one is lost in it
+                if self.shake.as_ref().is_none_or(|shake| shake.amount() <= amplitude) {
+                    self.shake = Some(Fade::new([0.0; 3], amplitude, 0.0, decay));
+                }
+            }
+            ScreenEffect::Grade { color, strength } => self.grade = (color, strength.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// Moves every effect along by a tick, starting any that were asked for since the last one.
+    pub fn tick(&mut self, ecs: &World) {
+        for event in self.events.read(ecs) {
+            match event {
+                GameEvent::ScreenEffect(effect) => self.start(effect),
+                GameEvent::MapChanged { .. } => {
+                    self.fades.clear();
+                    self.start(ScreenEffect::FadeFrom { color: [0.0, 0.0, 0.0], seconds: MAP_FADE_SECONDS });
+                }
+                GameEvent::Hit { entity, .. } if ecs.read_storage::<Player>().contains(entity) => {
+                    self.start(ScreenEffect::Flash { color: [1.0, 1.0, 1.0], seconds: HURT_FLASH_SECONDS });
+                    self.start(ScreenEffect::Shake { amplitude: HURT_SHAKE_PX, decay: HURT_SHAKE_SECONDS });
+                }
+                _ => (),
+            }
+        }
+        self.update();
+    }
+
+    fn update(&mut self) {
+        if let Some(fade) = self.fades.front_mut() {
+            fade.ticks += 1;
+            self.cover = (fade.color, fade.amount());
+            if fade.is_done() {
+                self.fades.pop_front();
+            }
+        }
+        if let Some(flash) = &mut self.flash {
+            flash.ticks += 1;
+            if flash.is_done() {
+                self.flash = None;
+            }
+        }
+        self.shake_offset = match &mut self.shake {
+            Some(shake) => {
+                shake.ticks += 1;
+                let amplitude = shake.amount();
+                (self.rng.gen_range(-1.0, 1.0) * amplitude, self.rng.gen_range(-1.0, 1.0) * amplitude)
+            }
+            None => (0.0, 0.0),
+        };
+        if self.shake.as_ref().is_some_and(Fade::is_done) {
+            self.shake = None;
+            self.shake_offset = (0.0, 0.0);
+        }
+    }
+
+    /// How much of which colour covers the whole screen, from fading or flashing.
+    /// A flash only shows while it covers more of the screen than the fade does.
+    pub fn cover(&self) -> ([f32; 3], f32) {
+        match &self.flash {
+            Some(flash) if flash.amount() > self.cover.1 => (flash.color, flash.amount()),
+            _ => self.cover,
+        }
+    }
+
+    /// How far the world is shaken from where it should be, in pixels.
+    pub fn shake_offset(&self) -> (f32, f32) {
+        self.shake_offset
+    }
+
+    pub fn grade(&self) -> ([f32; 3], f32) {
+        self.grade
+    }
+}
+
+/// Moves everything drawn until `end_shake` by however far the screen is shaken.
+pub fn begin_shake(ctx: &mut Context, effects: &ScreenEffects) -> GameResult<()> {
+    let (x, y) = effects.shake_offset();
+    graphics::push_transform(ctx, Some(graphics::DrawParam::new().dest(Point2::new(x, y)).to_matrix()));
+    graphics::apply_transformations(ctx)
+}
+
+pub fn end_shake(ctx: &mut Context) -> GameResult<()> {
+    graphics::pop_transform(ctx);
+    graphics::apply_transformations(ctx)
+}
+
+fn cover_screen(ctx: &mut Context, color: [f32; 3], alpha: f32) -> GameResult<()> {
+    if alpha <= 0.0 {
+        return Ok(());
+    }
+    let screen = Rect::new(0.0, 0.0, WIDTH_PX as f32, HEIGHT_PX as f32);
+    let cover = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), screen, [color[0], color[1], color[2], alpha].into())?;
+    graphics::draw(ctx, &cover, graphics::DrawParam::default())
+}
+
+/// Tints the world by its grading, underneath the hud.
+pub fn render_grade(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let (color, strength) = state.screen_effects.grade();
+    cover_screen(ctx, color, strength)
+}
+
+/// Covers everything, the hud included, with whatever the screen is faded or flashed to.
+pub fn render_cover(ctx: &mut Context, state: &GameState) -> GameResult<()> {
+    let (color, alpha) = state.screen_effects.cover();
+    cover_screen(ctx, color, alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, with_scripts};
+    use crate::map;
+    use crate::simulation;
+    use std::path::Path;
+    use crate::script::Scripts;
+
+    #[test]
+    fn fades_play_in_turn_while_flashes_and_shakes_die_away() {
+        let game = Headless::new(Headless::floor(10, 10));
+        let mut effects = ScreenEffects::default();
+        effects.listen(&game.ecs);
+        let black = [0.0, 0.0, 0.0];
+        effects.start(ScreenEffect::FadeTo { color: black, seconds: 1.0 });
+        effects.start(ScreenEffect::FadeFrom { color: black, seconds: 0.5 });
+        let ticks = simulation::TICKS_PER_SECOND;
+        for _ in 0..ticks / 2 {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover(), (black, 0.5));
+        for _ in 0..ticks / 2 {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover(), (black, 1.0));
+        for _ in 0..ticks / 4 {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover(), (black, 0.5));
+        for _ in 0..ticks {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover().1, 0.0);
+
+        let white = [1.0, 1.0, 1.0];
+        effects.start(ScreenEffect::Flash { color: white, seconds: 1.0 });
+        effects.start(ScreenEffect::Shake { amplitude: 8.0, decay: 1.0 });
+        let mut offsets = vec![];
+        for _ in 0..ticks / 2 {
+            effects.tick(&game.ecs);
+            offsets.push(effects.shake_offset());
+        }
+        assert_eq!(effects.cover(), (white, 0.5));
+        assert!(offsets.iter().all(|(x, y)| x.abs() <= 8.0 && y.abs() <= 8.0));
+        assert!(offsets.iter().any(|offset| *offset != (0.0, 0.0)));
+        // A weaker shake does not cut a stronger one short
+        effects.start(ScreenEffect::Shake { amplitude: 1.0, decay: 5.0 });
+        for _ in 0..ticks / 2 {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover().1, 0.0);
+        assert_eq!(effects.shake_offset(), (0.0, 0.0));
+
+        effects.start(ScreenEffect::Grade { color: [0.2, 0.3, 0.8], strength: 1.5 });
+        assert_eq!(effects.grade(), ([0.2, 0.3, 0.8], 1.0));
+    }
+
+    #[test]
+    fn hurts_map_changes_and_scripts_set_off_screen_effects() {
+        let mut game = Headless::new(Headless::floor(20, 20));
+        with_scripts(&mut game, "screen_effects", &[
+            ("dream", r##"
+                fn run() {
+                    grade("#336699", 0.4);
+                    fade_to("#ffffff", 1.0);
+                    fade_to("not a colour", 1.0);
+                }
+            "##),
+        ]);
+        let mut effects = ScreenEffects::default();
+        effects.listen(&game.ecs);
+        game.ecs.fetch_mut::<Scripts>().call("dream", "run", "");
+        game.step(1);
+        for _ in 0..simulation::TICKS_PER_SECOND {
+            effects.tick(&game.ecs);
+        }
+        assert_eq!(effects.cover(), ([1.0, 1.0, 1.0], 1.0));
+        assert_eq!(effects.grade(), ([51.0 / 255.0, 102.0 / 255.0, 153.0 / 255.0], 0.4));
+
+        // Arriving on a map fades in from black, whatever the screen was covered by
+        simulation::load_map(&mut game.ecs, Path::new(map::BASIC_MAP_PATH));
+        effects.tick(&game.ecs);
+        let (color, amount) = effects.cover();
+        assert_eq!(color, [0.0, 0.0, 0.0]);
+        assert!(amount > 0.9 && amount < 1.0);
+
+        let mut game = Headless::new(Headless::floor(20, 20));
+        let mut effects = ScreenEffects::default();
+        effects.listen(&game.ecs);
+        game.spawn(&[object("Slime", "enemy", (3, 0), &[("sight", "8")])]);
+        for _ in 0..simulation::TICKS_PER_SECOND * 2 {
+            if game.player_health() < 6 {
+                break;
+            }
+            game.step(1);
+            effects.tick(&game.ecs);
+        }
+        assert!(game.player_health() < 6);
+        assert_eq!(effects.cover().0, [1.0, 1.0, 1.0]);
+        assert!(effects.cover().1 > 0.5);
+        assert_ne!(effects.shake_offset(), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::clock::Clock;
+use crate::quest;
+use crate::events::{self, GameEvent};
+use crate::screen_effects::ScreenEffect;
+use crate::spawner;
+use crate::simulation::{self, Flags, Notifications, PendingDialogue};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, ParseError, Scope, AST};
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Scripts live here, one rhai file per script named after its id.
+pub const SCRIPT_DIR: &str = "assets/scripts";
+
+/// How much work a script may do in one go, so that a script stuck in a loop cannot hang the game.
+const MAX_OPERATIONS: u64 = 100_000;
+/// How big strings, arrays and maps made by a script may grow, so that a script
+/// piling up data cannot run the game out of memory before it runs out of operations.
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 10_000;
+const MAX_MAP_SIZE: usize = 10_000;
+/// How deep functions may call each other, so that runaway recursion fails instead of overflowing the stack.
+const MAX_CALL_LEVELS: usize = 32;
+/// How deeply expressions may nest at the top level of a script and inside of its functions.
+const MAX_EXPR_DEPTHS: (usize, usize) = (64, 32);
+
+/// Something a script asked to happen to the world, carried out once the script has finished.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ScriptCommand {
+    Message(String),
+    SetFlag(String),
+    AddToCounter { name: String, amount: i64 },
+    StartQuest(String),
+    SetTime { hour: u64, minute: u64 },
+    /// Sets the npc with the given name walking over to a tile, or the player if the name is `player`.
+    MoveTo { name: String, tile: (i32, i32) },
+    StartDialogue(String),
+    GiveItem { id: String, count: u32 },
+    ChangeMap { path: String, tile: (i32, i32) },
+    ScreenEffect(ScreenEffect),
+}
+
+/// A function of a script waiting to be called at the end of the tick. Functions are called
+/// with the name of whatever the script is attached to, if they take anything at all.
+#[derive(Clone, Debug)]
+pub struct ScriptCall {
+    pub script: String,
+    pub function: String,
+    pub owner: String,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse(ParseError),
+    Run(Box<EvalAltResult>),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Io(e) => write!(f, "could not read script: {}", e),
+            ScriptError::Parse(e) => write!(f, "could not parse script: {}", e),
+            ScriptError::Run(e) => write!(f, "script failed: {}", e),
+        }
+    }
+}
+
+/// What a running script can see of the world, and what it has asked for so far.
+#[derive(Default)]
+struct ScriptState {
+    flags: Flags,
+    clock: Clock,
+    commands: Vec<ScriptCommand>,
+}
+
+struct LoadedScript {
+    ast: AST,
+    /// When the file was last changed as of loading it, to tell when it needs loading again.
+    modified: Option<SystemTime>,
+}
+
+/// Compiles and runs the scripts attached to things in the world. Scripts are loaded the first time
+/// they are needed, and can only reach the world through the functions registered here.
+pub struct Scripts {
+    engine: Engine,
+    dir: PathBuf,
+    state: Arc<Mutex<ScriptState>>,
+    loaded: HashMap<String, LoadedScript>,
+    pending: Vec<ScriptCall>,
+}
+
+impl Default for Scripts {
+    fn default() -> Scripts {
+        Scripts::new(Path::new(SCRIPT_DIR))
+    }
+}
+
+impl Scripts {
+    /// Creates a script engine which loads scripts out of the given directory.
+    pub fn new(dir: &Path) -> Scripts {
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_ARRAY_SIZE);
+        engine.set_max_map_size(MAX_MAP_SIZE);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_expr_depths(MAX_EXPR_DEPTHS.0, MAX_EXPR_DEPTHS.1);
+        // Scripts may not go loading other files behind our back
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+
+        let command = |state: &Arc<Mutex<ScriptState>>| {
+            let state = state.clone();
+            move |command: ScriptCommand| state.lock().expect("script state poisoned").commands.push(command)
+        };
+        let push = command(&state);
+        engine.register_fn("message", move |text: &str| push(ScriptCommand::Message(text.to_string())));
+        let flags = state.clone();
+        engine.register_fn("flag", move |name: &str| flags.lock().expect("script state poisoned").flags.is_set(name));
+        let flags = state.clone();
+        engine.register_fn("set_flag", move |name: &str| {
+            let mut state = flags.lock().expect("script state poisoned");
+            state.flags.set(name);
+            state.commands.push(ScriptCommand::SetFlag(name.to_string()));
+        });
+        let flags = state.clone();
+        engine.register_fn("counter", move |name: &str| flags.lock().expect("script state poisoned").flags.count(name));
+        let flags = state.clone();
+        engine.register_fn("add_to_counter", move |name: &str, amount: i64| {
+            let mut state = flags.lock().expect("script state poisoned");
+            state.flags.add(name, amount);
+            state.commands.push(ScriptCommand::AddToCounter { name: name.to_string(), amount });
+        });
+        let clock = state.clone();
+        engine.register_fn("hour", move || clock.lock().expect("script state poisoned").clock.hour() as i64);
+        let clock = state.clone();
+        engine.register_fn("minute", move || clock.lock().expect("script state poisoned").clock.minute() as i64);
+        let clock = state.clone();
+        engine.register_fn("day", move || clock.lock().expect("script state poisoned").clock.day() as i64);
+        let clock = state.clone();
+        engine.register_fn("set_time", move |hour: i64, minute: i64| {
+            let mut state = clock.lock().expect("script state poisoned");
+            // Wrapped into a day before anything is worked out from them, so no time a script asks for can overflow
+            let (hour, minute) = (hour.rem_euclid(24) as u64, minute.rem_euclid(60) as u64);
+            state.clock.set_time(hour, minute);
+            state.commands.push(ScriptCommand::SetTime { hour, minute });
+        });
+        let push = command(&state);
+        engine.register_fn("start_quest", move |id: &str| push(ScriptCommand::StartQuest(id.to_string())));
+        let push = command(&state);
+        engine.register_fn("move_to", move |name: &str, x: i64, y: i64| {
+            push(ScriptCommand::MoveTo { name: name.to_string(), tile: (x as i32, y as i32) })
+        });
+        let push = command(&state);
+        engine.register_fn("start_dialogue", move |id: &str| push(ScriptCommand::StartDialogue(id.to_string())));
+        let push = command(&state);
+        engine.register_fn("give_item", move |id: &str, count: i64| {
+            push(ScriptCommand::GiveItem { id: id.to_string(), count: count.max(0) as u32 })
+        });
+        let push = command(&state);
+        engine.register_fn("change_map", move |path: &str, x: i64, y: i64| {
+            push(ScriptCommand::ChangeMap { path: path.to_string(), tile: (x as i32, y as i32) })
+        });
+        // Colours are written the way tiled writes them, as `#rrggbb`, and times are in seconds
+        let effect = |state: &Arc<Mutex<ScriptState>>| {
+            let push = command(state);
+            move |color: &str, effect: &dyn Fn([f32; 3]) -> ScreenEffect| match spawner::parse_color(color) {
+                Some(color) => push(ScriptCommand::ScreenEffect(effect(color))),
+                None => println!("Skipping screen effect with unknown colour '{}'", color),
+            }
+        };
+        let push = effect(&state);
+        engine.register_fn("fade_to", move |color: &str, seconds: f64| {
+            push(color, &|color| ScreenEffect::FadeTo { color, seconds: seconds as f32 })
+        });
+        let push = effect(&state);
+        engine.register_fn("fade_from", move |color: &str, seconds: f64| {
+            push(color, &|color| ScreenEffect::FadeFrom { color, seconds: seconds as f32 })
+        });
+        let push = effect(&state);
+        engine.register_fn("flash", move |color: &str, seconds: f64| {
+            push(color, &|color| ScreenEffect::Flash { color, seconds: seconds as f32 })
+        });
+        let push = effect(&state);
+        engine.register_fn("grade", move |color: &str, strength: f64| {
+            push(color, &|color| ScreenEffect::Grade { color, strength: strength as f32 })
+        });
+        let push = command(&state);
+        engine.register_fn("shake", move |amplitude: f64, decay: f64| {
+            push(ScriptCommand::ScreenEffect(ScreenEffect::Shake { amplitude: amplitude as f32, decay: decay as f32 }))
+        });
+
+        Scripts { engine, dir: dir.to_path_buf(), state, loaded: HashMap::new(), pending: vec![] }
+    }
+
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.rhai", id))
+    }
+
+    /// When the file of a script was last changed.
+    fn modified(&self, id: &str) -> Option<SystemTime> {
+        fs::metadata(self.path(id)).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /// Asks for a function of a script to be called at the end of the tick.
+    pub fn call(&mut self, script: &str, function: &str, owner: &str) {
+        self.pending.push(ScriptCall { script: script.to_string(), function: function.to_string(), owner: owner.to_string() });
+    }
+
+    /// Compiles a script from its file, replacing any version of it loaded before.
+    pub fn load(&mut self, id: &str) -> Result<(), ScriptError> {
+        // Checked before reading, so that a change made while loading is picked up next time
+        let modified = self.modified(id);
+        let source = fs::read_to_string(self.path(id)).map_err(ScriptError::Io)?;
+        let ast = self.engine.compile(source).map_err(ScriptError::Parse)?;
+        self.loaded.insert(id.to_string(), LoadedScript { ast, modified });
+        Ok(())
+    }
+
+    /// Loads every script again whose file changed since it was loaded, returning those which failed.
+    pub fn reload_changed(&mut self) -> Vec<(String, ScriptError)> {
+        let changed: Vec<String> = self.loaded.iter()
+            .filter(|(id, script)| self.modified(id) != script.modified)
+            .map(|(id, _script)| id.clone())
+            .collect();
+        let mut failed = vec![];
+        for id in changed {
+            match self.load(&id) {
+                Ok(()) => println!("Reloaded script {}", id),
+                Err(e) => {
+                    // Keep the old version around rather than trying again every frame
+                    let modified = self.modified(&id);
+                    if let Some(script) = self.loaded.get_mut(&id) {
+                        script.modified = modified;
+                    }
+                    failed.push((id, e));
+                }
+            }
+        }
+        failed
+    }
+
+    /// Calls a function of a script seeing the given flags and time, returning what it asked to happen.
+    /// Scripts without the function have nothing to do, which is not a mistake.
+    fn run(&mut self, call: &ScriptCall, flags: Flags, clock: Clock) -> Result<Vec<ScriptCommand>, ScriptError> {
+        if !self.loaded.contains_key(&call.script) {
+            self.load(&call.script)?;
+        }
+        let ast = &self.loaded[&call.script].ast;
+        let takes_owner = match ast.iter_functions().find(|function| function.name == call.function) {
+            Some(function) => !function.params.is_empty(),
+            None => return Ok(vec![]),
+        };
+        *self.state.lock().expect("script state poisoned") = ScriptState { flags, clock, commands: vec![] };
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = if takes_owner {
+            self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, &call.function, (call.owner.clone(),))
+        } else {
+            self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, &call.function, ())
+        };
+        let commands = std::mem::take(&mut self.state.lock().expect("script state poisoned").commands);
+        result.map(|_result| commands).map_err(ScriptError::Run)
+    }
+}
+
+/// Runs every script function asked for during the tick, then does whatever they asked for.
+/// A script which fails changes nothing, and the player is told about it rather than the game crashing.
+pub fn run_scripts(ecs: &mut World) {
+    let calls = std::mem::take(&mut ecs.fetch_mut::<Scripts>().pending);
+    for call in calls {
+        let flags = (*ecs.fetch::<Flags>()).clone();
+        let clock = *ecs.fetch::<Clock>();
+        let result = ecs.fetch_mut::<Scripts>().run(&call, flags, clock);
+        match result {
+            Ok(commands) => {
+                for command in commands {
+                    apply(ecs, command);
+                }
+            }
+            Err(e) => report(ecs, &call.script, &e),
+        }
+    }
+}
+
+/// Picks up changes to scripts while the game is running, reporting any which no longer work.
+pub fn hot_reload(ecs: &World) {
+    let failed = ecs.fetch_mut::<Scripts>().reload_changed();
+    for (id, e) in failed {
+        report(ecs, &id, &e);
+    }
+}
+
+fn report(ecs: &World, id: &str, e: &ScriptError) {
+    println!("Script {} failed, {}", id, e);
+    ecs.fetch_mut::<Notifications>().push(format!("Script {}: {}", id, e));
+}
+
+fn apply(ecs: &mut World, command: ScriptCommand) {
+    match command {
+        ScriptCommand::Message(message) => ecs.fetch_mut::<Notifications>().push(message),
+        ScriptCommand::SetFlag(flag) => ecs.fetch_mut::<Flags>().set(&flag),
+        ScriptCommand::AddToCounter { name, amount } => {
+            ecs.fetch_mut::<Flags>().add(&name, amount);
+        }
+        ScriptCommand::StartQuest(id) => quest::start_quest(ecs, &id),
+        ScriptCommand::SetTime { hour, minute } => ecs.fetch_mut::<Clock>().set_time(hour, minute),
+        ScriptCommand::MoveTo { name, tile } => simulation::walk_to(ecs, &name, tile),
+        ScriptCommand::StartDialogue(id) => ecs.insert(PendingDialogue(Some(id))),
+        ScriptCommand::GiveItem { id, count } => simulation::give_item(ecs, &id, count),
+        ScriptCommand::ChangeMap { path, tile } => simulation::warp_player(ecs, Some(&path), tile),
+        ScriptCommand::ScreenEffect(effect) => events::publish(ecs, GameEvent::ScreenEffect(effect)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, trigger, with_scripts};
+    use crate::map;
+    use crate::simulation;
+    use crate::input::Input;
+    use crate::Direction;
+    use std::fs;
+
+    #[test]
+    fn scripts_attached_to_triggers_and_npcs_change_the_world() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        with_scripts(&mut game, "scripts", &[
+            ("gate", r#"
+                fn on_enter(zone) {
+                    message("Entered " + zone);
+                    if !flag("gate_opened") {
+                        set_flag("gate_opened");
+                        give_item("coin", 2);
+                    }
+                    if flag("gate_opened") {
+                        move_to("Guard", 5, 5);
+                    }
+                }
+            "#),
+            ("guard", r#"
+                fn on_interact() {
+                    start_dialogue("elder");
+                    change_map("assets/map/basic.tmx", 2, 5);
+                }
+            "#),
+        ]);
+        game.spawn(&[
+            trigger("Gate", (2, 0), (1, 1), &[("on_enter", "script gate")]),
+            object("Guard", "npc", (8, 8), &[("script", "guard"), ("dialogue", "gardener")]),
+        ]);
+
+        game.input(Input::MoveTo(2, 0));
+        game.step(30);
+        assert_eq!(game.notifications(), vec!["Entered Gate".to_string(), "Picked up Coin x2".to_string()]);
+        assert!(game.ecs.fetch::<simulation::Flags>().is_set("gate_opened"));
+        assert_eq!(game.carried("coin"), 2);
+        game.step(120);
+        assert_eq!(game.npc_tile("Guard"), (5, 5));
+
+        // Npcs with scripts leave talking up to them
+        game.input(Input::MoveTo(5, 4));
+        game.step(120);
+        assert_eq!((game.player_tile(), game.npc_tile("Guard")), ((5, 4), (5, 5)));
+        game.press(Direction::Down);
+        assert_eq!(game.interact(), None);
+        game.step(1);
+        assert_eq!(game.ecs.fetch::<simulation::PendingDialogue>().0.as_deref(), Some("elder"));
+        assert_eq!(game.player_tile(), (2, 5));
+        assert_eq!(game.ecs.fetch::<simulation::MapPath>().0, map::BASIC_MAP_PATH);
+    }
+
+    #[test]
+    fn broken_scripts_are_reported_instead_of_crashing() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        let dir = with_scripts(&mut game, "broken_scripts", &[
+            ("typo", "fn on_interact() { message(\"unclosed); }"),
+            ("crash", "fn on_interact() { set_flag(\"half_done\"); no_such_function(); }"),
+            ("forever", "fn on_interact() { loop { } }"),
+            ("sneaky", "fn on_interact() { import \"typo\" as typo; }"),
+            ("changing", "fn on_interact() { message(\"Before\"); }"),
+        ]);
+        for script in ["typo", "crash", "forever", "sneaky", "missing"] {
+            game.ecs.fetch_mut::<Scripts>().call(script, "on_interact", "");
+            game.step(1);
+            let notifications = game.notifications();
+            assert_eq!(notifications.len(), 1, "{:?}", notifications);
+            assert!(notifications[0].starts_with(&format!("Script {}: ", script)), "{:?}", notifications);
+        }
+        assert!(!game.ecs.fetch::<simulation::Flags>().is_set("half_done"));
+
+        // Scripts are picked up again once they change, and a broken change keeps the working version
+        game.ecs.fetch_mut::<Scripts>().call("changing", "on_interact", "");
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["Before".to_string()]);
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        let rewrite = |source: &str, modified: std::time::SystemTime| {
+            fs::write(dir.join("changing.rhai"), source).unwrap();
+            fs::File::options().write(true).open(dir.join("changing.rhai")).unwrap().set_modified(modified).unwrap();
+        };
+        rewrite("fn on_interact() { message(\"After\"); }", later);
+        hot_reload(&game.ecs);
+        game.ecs.fetch_mut::<Scripts>().call("changing", "on_interact", "");
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["After".to_string()]);
+        rewrite("fn on_interact() {", later + std::time::Duration::from_secs(10));
+        hot_reload(&game.ecs);
+        assert_eq!(game.notifications().len(), 1);
+        hot_reload(&game.ecs);
+        assert!(game.notifications().is_empty());
+        game.ecs.fetch_mut::<Scripts>().call("changing", "on_interact", "");
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["After".to_string()]);
+    }
+
+    #[test]
+    fn scripts_piling_up_data_fail_before_running_out_of_memory() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        with_scripts(&mut game, "runaway_scripts", &[
+            ("doubling", "fn on_interact() { let s = \"ab\"; loop { s += s; } }"),
+            ("hoarding", "fn on_interact() { let a = [0]; loop { a += a; } }"),
+            ("recursing", "fn on_interact() { on_interact(); }"),
+        ]);
+        for script in ["doubling", "hoarding", "recursing"] {
+            game.ecs.fetch_mut::<Scripts>().call(script, "on_interact", "");
+            game.step(1);
+            let notifications = game.notifications();
+            assert_eq!(notifications.len(), 1, "{:?}", notifications);
+            assert!(notifications[0].starts_with(&format!("Script {}: ", script)), "{:?}", notifications);
+        }
+    }
+
+    #[test]
+    fn scripts_setting_absurd_times_leave_the_game_running() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        with_scripts(&mut game, "absurd_times", &[
+            ("huge", "fn on_interact() { set_time(9223372036854775807, 9223372036854775807); }"),
+            ("negative", "fn on_interact() { set_time(-1, -1); message(`${hour()}:${minute()}`); }"),
+        ]);
+        game.ecs.fetch_mut::<Scripts>().call("huge", "on_interact", "");
+        game.step(1);
+        assert!(game.notifications().is_empty());
+        let clock = *game.ecs.fetch::<Clock>();
+        assert_eq!((clock.hour(), clock.minute()), (7, 7));
+        game.ecs.fetch_mut::<Scripts>().call("negative", "on_interact", "");
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["23:59".to_string()]);
+    }
+
+    #[test]
+    fn shipped_scripts_compile() {
+        let mut scripts = Scripts::default();
+        for entry in fs::read_dir(SCRIPT_DIR).unwrap() {
+            let path = entry.unwrap().path();
+            let id = path.file_stem().unwrap().to_string_lossy().into_owned();
+            if let Err(e) = scripts.load(&id) {
+                panic!("script {} does not compile: {}", id, e);
+            }
+        }
+    }
+}
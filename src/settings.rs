@@ -0,0 +1,537 @@
+use crate::{GameState, Point2, RootState, AUTHOR, GAME_ID, HEIGHT_PX, WIDTH_PX};
+use crate::audio::{Sound, Volumes};
+use crate::hud::HudWidget;
+use crate::input::{self, Action, InputMap};
+use crate::widget::{self, Menu};
+use directories::ProjectDirs;
+use ggez::conf::{FullscreenType, WindowMode};
+use ggez::event::KeyCode;
+use ggez::{graphics, Context, GameResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings live in the user config directory, under this name.
+pub const SETTINGS_FILE: &str = "settings.ron";
+/// The window sizes on offer, as multiples of the size the game is drawn at.
+const SCALES: [f32; 3] = [1.0, 1.5, 2.0];
+const VOLUME_STEP: f32 = 0.1;
+
+const PANEL_WIDTH_PX: f32 = 460.0;
+const PANEL_HEIGHT_PX: f32 = 480.0;
+const BINDINGS_PANEL_WIDTH_PX: f32 = 560.0;
+const BINDINGS_PANEL_HEIGHT_PX: f32 = 500.0;
+const PANEL_PADDING_PX: f32 = 16.0;
+const TITLE_FONT_SIZE: f32 = 28.0;
+const BINDING_ROW_HEIGHT_PX: f32 = 28.0;
+const TEXT_FONT_SIZE: f32 = 20.0;
+const FADED: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// Everything the player can change about how the game runs, kept between launches.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window_mode: FullscreenType,
+    /// How many times larger than the size the game is drawn at the window is.
+    pub scale: f32,
+    /// Every volume goes from 0.0 for silence to 1.0 for full volume.
+    /// The music and sound effect volumes are scaled by the master volume.
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub show_fps: bool,
+    pub show_minimap: bool,
+    /// Only takes effect on the next launch, ggez cannot change it on a running window.
+    pub vsync: bool,
+    /// Keys the player has rebound, replacing the shipped bindings for those actions.
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            window_mode: FullscreenType::Windowed,
+            scale: 1.0,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            show_fps: true,
+            show_minimap: true,
+            vsync: true,
+            keys: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Parse(ron::Error),
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "could not access settings: {}", e),
+            SettingsError::Parse(e) => write!(f, "could not parse settings: {}", e),
+            SettingsError::Serialize(e) => write!(f, "could not write settings: {}", e),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Settings, SettingsError> {
+        let contents = fs::read_to_string(path).map_err(SettingsError::Io)?;
+        contents.parse()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(SettingsError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(SettingsError::Io)?;
+        }
+        fs::write(path, contents).map_err(SettingsError::Io)
+    }
+
+    /// The window these settings ask for, for `ContextBuilder` or changing the window later.
+    pub fn window(&self) -> WindowMode {
+        WindowMode::default()
+            .dimensions(WIDTH_PX as f32 * self.scale, HEIGHT_PX as f32 * self.scale)
+            .fullscreen_type(self.window_mode)
+    }
+
+    pub fn volumes(&self) -> Volumes {
+        Volumes { master: self.master_volume, music: self.music_volume, sfx: self.sfx_volume }
+    }
+
+    /// Applies the rebound keys over the top of the shipped key bindings.
+    pub fn apply_keys(&self, input_map: &mut InputMap) {
+        for action in Action::ALL.iter() {
+            if let Some(keys) = self.keys.get(action) {
+                if let Err(e) = input_map.set_keys(*action, keys) {
+                    println!("Leaving out a rebound key, {}", e);
+                }
+            }
+        }
+    }
+
+    /// Brings anything edited by hand back within what the settings screen could have chosen.
+    fn clamped(mut self) -> Settings {
+        let scale = self.scale;
+        self.scale = SCALES.iter().copied()
+            .min_by(|a, b| (a - scale).abs().total_cmp(&(b - scale).abs()))
+            .unwrap_or(1.0);
+        for volume in [&mut self.master_volume, &mut self.music_volume, &mut self.sfx_volume] {
+            *volume = if volume.is_nan() { 1.0 } else { volume.clamp(0.0, 1.0) };
+        }
+        self
+    }
+}
+
+impl FromStr for Settings {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Settings, SettingsError> {
+        ron::de::from_str(s).map(Settings::clamped).map_err(SettingsError::Parse)
+    }
+}
+
+/// Where settings are kept, in the same user config directory ggez uses for the game.
+pub fn settings_path() -> Option<PathBuf> {
+    ProjectDirs::from("", AUTHOR, GAME_ID).map(|dirs| dirs.config_dir().join(SETTINGS_FILE))
+}
+
+/// Loads the settings saved last time, falling back to the defaults on the first launch.
+pub fn load_settings() -> Settings {
+    let path = match settings_path() {
+        Some(path) => path,
+        None => return Settings::default(),
+    };
+    match Settings::load(&path) {
+        Ok(settings) => settings,
+        Err(SettingsError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Settings::default(),
+        Err(e) => {
+            println!("Falling back to default settings, {}", e);
+            Settings::default()
+        }
+    }
+}
+
+fn save_settings(settings: &Settings) {
+    if let Some(path) = settings_path() {
+        if let Err(e) = settings.save(&path) {
+            println!("Could not save settings: {}", e);
+        }
+    }
+}
+
+/// Lays the game out at its own size no matter how big the window is.
+pub fn fit_to_window(ctx: &mut Context) -> GameResult<()> {
+    graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, WIDTH_PX as f32, HEIGHT_PX as f32))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Setting {
+    WindowMode,
+    Scale,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    ShowFps,
+    ShowMinimap,
+    Vsync,
+    KeyBindings,
+    Back,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 10] = [
+        Setting::WindowMode,
+        Setting::Scale,
+        Setting::MasterVolume,
+        Setting::MusicVolume,
+        Setting::SfxVolume,
+        Setting::ShowFps,
+        Setting::ShowMinimap,
+        Setting::Vsync,
+        Setting::KeyBindings,
+        Setting::Back,
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SettingsPage {
+    Main,
+    Bindings,
+}
+
+/// The settings screen, with a page for rebinding keys.
+#[derive(Debug)]
+pub struct SettingsScreen {
+    page: SettingsPage,
+    main: Menu,
+    /// One item per action, followed by resetting them all and going back.
+    bindings: Menu,
+    /// The action waiting for the player to press its new key.
+    rebinding: Option<Action>,
+    message: Option<String>,
+}
+
+impl SettingsScreen {
+    pub fn new() -> SettingsScreen {
+        let panel = widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX);
+        let top = panel.y + PANEL_PADDING_PX * 2.0 + TITLE_FONT_SIZE;
+        let main = Menu::new(&[""; Setting::ALL.len()], Point2::new(panel.x + PANEL_PADDING_PX, top), PANEL_WIDTH_PX - PANEL_PADDING_PX * 2.0);
+        let panel = widget::centered_panel(BINDINGS_PANEL_WIDTH_PX, BINDINGS_PANEL_HEIGHT_PX);
+        let top = panel.y + PANEL_PADDING_PX * 2.0 + TITLE_FONT_SIZE;
+        let mut labels = vec![""; Action::ALL.len()];
+        labels.push("Reset to Defaults");
+        labels.push("Back");
+        let bindings = Menu::new(&labels, Point2::new(panel.x + PANEL_PADDING_PX, top), BINDINGS_PANEL_WIDTH_PX - PANEL_PADDING_PX * 2.0)
+            .with_rows(BINDING_ROW_HEIGHT_PX, TEXT_FONT_SIZE);
+        SettingsScreen { page: SettingsPage::Main, main, bindings, rebinding: None, message: None }
+    }
+
+    fn page_menu(&mut self) -> &mut Menu {
+        match self.page {
+            SettingsPage::Main => &mut self.main,
+            SettingsPage::Bindings => &mut self.bindings,
+        }
+    }
+}
+
+impl Default for SettingsScreen {
+    fn default() -> SettingsScreen {
+        SettingsScreen::new()
+    }
+}
+
+/// Opens the settings over whatever is showing, from the start menu or the pause menu.
+pub fn open_settings(state: &mut GameState) {
+    let screen = &mut state.settings_screen;
+    screen.page = SettingsPage::Main;
+    screen.rebinding = None;
+    screen.message = None;
+    screen.main.select(0);
+    refresh_labels(state);
+    state.states.push(RootState::Settings);
+}
+
+/// Whether the next key pressed should be bound rather than treated as an action.
+pub fn is_rebinding(state: &GameState) -> bool {
+    state.settings_screen.rebinding.is_some()
+}
+
+/// Binds the key that was pressed to the action waiting for one. Escape cancels instead.
+pub fn rebind_key(state: &mut GameState, key: KeyCode) {
+    let action = match state.settings_screen.rebinding.take() {
+        Some(action) => action,
+        None => return,
+    };
+    if key != KeyCode::Escape {
+        match state.input_map.set_keys(action, &[key]) {
+            Ok(()) => {
+                // The key may have been swapped with another action, so every binding is remembered
+                state.settings.keys = Action::ALL.iter().map(|action| (*action, state.input_map.keys(*action).to_vec())).collect();
+                save_settings(&state.settings);
+            }
+            Err(e) => state.settings_screen.message = Some(format!("Could not rebind, {}", e)),
+        }
+    }
+    refresh_labels(state);
+}
+
+pub fn settings_input(state: &mut GameState, ctx: &mut Context, action: Action) {
+    let screen = &mut state.settings_screen;
+    screen.message = None;
+    match (screen.page, action) {
+        (SettingsPage::Main, Action::Pause) => state.states.pop(),
+        (SettingsPage::Bindings, Action::Pause) => screen.page = SettingsPage::Main,
+        (SettingsPage::Main, Action::MoveLeft | Action::MoveRight) => {
+            let setting = Setting::ALL[screen.main.selected()];
+            state.audio.play_sound(Sound::MenuClick);
+            change(state, ctx, setting, action == Action::MoveRight);
+        }
+        _ => {
+            if let Some(index) = screen.page_menu().input(action) {
+                choose(state, ctx, index);
+            }
+        }
+    }
+}
+
+pub fn settings_hover(state: &mut GameState, x: f32, y: f32) {
+    state.settings_screen.page_menu().hover(x, y);
+}
+
+pub fn settings_click(state: &mut GameState, ctx: &mut Context, x: f32, y: f32) {
+    state.settings_screen.message = None;
+    if let Some(index) = state.settings_screen.page_menu().click(x, y) {
+        choose(state, ctx, index);
+    }
+}
+
+fn choose(state: &mut GameState, ctx: &mut Context, index: usize) {
+    state.audio.play_sound(Sound::MenuClick);
+    let screen = &mut state.settings_screen;
+    match screen.page {
+        SettingsPage::Main => match Setting::ALL[index] {
+            Setting::KeyBindings => {
+                screen.bindings.select(0);
+                screen.page = SettingsPage::Bindings;
+            }
+            Setting::Back => state.states.pop(),
+            setting => change(state, ctx, setting, true),
+        },
+        SettingsPage::Bindings if index < Action::ALL.len() => screen.rebinding = Some(Action::ALL[index]),
+        SettingsPage::Bindings if index == Action::ALL.len() => {
+            state.settings.keys.clear();
+            state.input_map = InputMap::load(Path::new(input::KEY_BINDINGS_PATH))
+                .map(|(input_map, _conflicts)| input_map)
+                .unwrap_or_default();
+            save_settings(&state.settings);
+            refresh_labels(state);
+        }
+        SettingsPage::Bindings => screen.page = SettingsPage::Main,
+    }
+}
+
+/// Steps a setting forwards or backwards, applying it straight away wherever ggez allows.
+fn change(state: &mut GameState, ctx: &mut Context, setting: Setting, forward: bool) {
+    let settings = &mut state.settings;
+    let result = match setting {
+        Setting::WindowMode => {
+            let modes = [FullscreenType::Windowed, FullscreenType::Desktop, FullscreenType::True];
+            settings.window_mode = cycle(&modes, settings.window_mode, forward);
+            graphics::set_fullscreen(ctx, settings.window_mode).and_then(|_| fit_to_window(ctx))
+        }
+        Setting::Scale => {
+            settings.scale = cycle(&SCALES, settings.scale, forward);
+            graphics::set_drawable_size(ctx, WIDTH_PX as f32 * settings.scale, HEIGHT_PX as f32 * settings.scale)
+                .and_then(|_| fit_to_window(ctx))
+        }
+        Setting::MasterVolume | Setting::MusicVolume | Setting::SfxVolume => {
+            let volume = match setting {
+                Setting::MasterVolume => &mut settings.master_volume,
+                Setting::MusicVolume => &mut settings.music_volume,
+                _ => &mut settings.sfx_volume,
+            };
+            *volume = step_volume(*volume, forward);
+            state.audio.set_volumes(settings.volumes());
+            Ok(())
+        }
+        Setting::ShowFps => {
+            settings.show_fps = !settings.show_fps;
+            state.hud.set_shown(HudWidget::Fps, settings.show_fps);
+            Ok(())
+        }
+        Setting::ShowMinimap => {
+            settings.show_minimap = !settings.show_minimap;
+            state.hud.set_shown(HudWidget::Minimap, settings.show_minimap);
+            Ok(())
+        }
+        Setting::Vsync => {
+            settings.vsync = !settings.vsync;
+            state.settings_screen.message = Some("Vsync changes take effect after a restart".to_string());
+            Ok(())
+        }
+        Setting::KeyBindings | Setting::Back => Ok(()),
+    };
+    if let Err(e) = result {
+        println!("Could not change the window: {}", e);
+    }
+    save_settings(&state.settings);
+    refresh_labels(state);
+}
+
+/// Flips a hud widget from the game, remembering the choice like the settings screen would.
+/// Replays flip it without remembering, since the choice was made by whoever recorded them.
+pub fn toggle_hud_widget(state: &mut GameState, widget: HudWidget) {
+    state.hud.toggle(widget);
+    if state.replay.is_some() {
+        return;
+    }
+    let shown = state.hud.is_shown(widget);
+    match widget {
+        HudWidget::Fps => state.settings.show_fps = shown,
+        HudWidget::Minimap => state.settings.show_minimap = shown,
+        _ => return,
+    }
+    save_settings(&state.settings);
+}
+
+/// Turns a volume up or down a step, staying on whole steps between silent and full volume.
+fn step_volume(volume: f32, up: bool) -> f32 {
+    let step = if up { VOLUME_STEP } else { -VOLUME_STEP };
+    ((volume + step) / VOLUME_STEP).round().clamp(0.0, 1.0 / VOLUME_STEP) * VOLUME_STEP
+}
+
+/// The option after or before the current one, wrapping around.
+fn cycle<T: Copy + PartialEq>(options: &[T], current: T, forward: bool) -> T {
+    let index = options.iter().position(|option| *option == current).unwrap_or(0);
+    let next = if forward { index + 1 } else { index + options.len() - 1 };
+    options[next % options.len()]
+}
+
+/// Shows the current value of every setting next to its name.
+fn refresh_labels(state: &mut GameState) {
+    let settings = &state.settings;
+    let on_off = |on: bool| if on { "On" } else { "Off" };
+    for (index, setting) in Setting::ALL.iter().enumerate() {
+        let label = match setting {
+            Setting::WindowMode => format!("Window: {}", match settings.window_mode {
+                FullscreenType::Windowed => "Windowed",
+                FullscreenType::Desktop => "Borderless",
+                FullscreenType::True => "Fullscreen",
+            }),
+            Setting::Scale => format!("Scale: {}x", settings.scale),
+            Setting::MasterVolume => format!("Master Volume: {}%", (settings.master_volume * 100.0).round()),
+            Setting::MusicVolume => format!("Music Volume: {}%", (settings.music_volume * 100.0).round()),
+            Setting::SfxVolume => format!("Sound Volume: {}%", (settings.sfx_volume * 100.0).round()),
+            Setting::ShowFps => format!("Show FPS: {}", on_off(settings.show_fps)),
+            Setting::ShowMinimap => format!("Show Minimap: {}", on_off(settings.show_minimap)),
+            Setting::Vsync => format!("Vsync: {}", on_off(settings.vsync)),
+            Setting::KeyBindings => "Key Bindings".to_string(),
+            Setting::Back => "Back".to_string(),
+        };
+        state.settings_screen.main.set_label(index, label);
+    }
+    for (index, action) in Action::ALL.iter().enumerate() {
+        let keys: Vec<String> = state.input_map.keys(*action).iter().map(|key| format!("{:?}", key)).collect();
+        let keys = if keys.is_empty() { "Unbound".to_string() } else { keys.join(", ") };
+        state.settings_screen.bindings.set_label(index, format!("{}: {}", action, keys));
+    }
+}
+
+pub fn settings_draw(state: &mut GameState, ctx: &mut Context) -> GameResult<()> {
+    let screen = &mut state.settings_screen;
+    let (panel, title) = match screen.page {
+        SettingsPage::Main => (widget::centered_panel(PANEL_WIDTH_PX, PANEL_HEIGHT_PX), "Settings"),
+        SettingsPage::Bindings => (widget::centered_panel(BINDINGS_PANEL_WIDTH_PX, BINDINGS_PANEL_HEIGHT_PX), "Key Bindings"),
+    };
+    widget::draw_panel(ctx, panel)?;
+    let title = graphics::Text::new((title, state.font, TITLE_FONT_SIZE));
+    graphics::draw(ctx, &title, (Point2::new(panel.x + PANEL_PADDING_PX, panel.y + PANEL_PADDING_PX), graphics::WHITE))?;
+    let page_menu = screen.page_menu();
+    page_menu.draw(ctx, state.font)?;
+
+    let message = match screen.rebinding {
+        Some(action) => Some(format!("Press a key for {}, or Escape to cancel", action)),
+        None => screen.message.clone(),
+    };
+    if let Some(message) = message {
+        let text = graphics::Text::new((message, state.font, TEXT_FONT_SIZE));
+        let top = panel.y + panel.h - PANEL_PADDING_PX - TEXT_FONT_SIZE;
+        graphics::draw(ctx, &text, (Point2::new(panel.x + PANEL_PADDING_PX, top), FADED.into()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Action, InputMap, InputMapError};
+    use ggez::conf::FullscreenType;
+    use ggez::event::KeyCode;
+
+    #[test]
+    fn settings_fill_in_missing_fields_and_round_trip() {
+        let settings: Settings = "(scale: 2.0, show_fps: false)".parse().expect("could not parse settings");
+        assert_eq!(settings.scale, 2.0);
+        assert!(!settings.show_fps);
+        assert_eq!(settings.window_mode, FullscreenType::Windowed);
+        assert!(settings.vsync);
+
+        let mut settings = Settings { window_mode: FullscreenType::Desktop, music_volume: 0.4, ..Settings::default() };
+        settings.keys.insert(Action::Attack, vec![KeyCode::K]);
+        let written = ron::ser::to_string(&settings).expect("could not write settings");
+        assert_eq!(written.parse::<Settings>().expect("could not parse settings"), settings);
+    }
+
+    #[test]
+    fn rebinding_a_key_swaps_it_with_its_old_action() {
+        let mut input_map = InputMap::default();
+        let mut settings = Settings::default();
+        settings.keys.insert(Action::Attack, vec![KeyCode::M]);
+        settings.apply_keys(&mut input_map);
+        assert_eq!(input_map.action(KeyCode::M), Some(Action::Attack));
+        assert_eq!(input_map.keys(Action::Attack), &[KeyCode::M]);
+        assert_eq!(input_map.keys(Action::ToggleMinimap), &[KeyCode::X, KeyCode::J]);
+        assert_eq!(input_map.action(KeyCode::X), Some(Action::ToggleMinimap));
+    }
+
+    #[test]
+    fn required_actions_are_never_left_without_a_key() {
+        let mut input_map = InputMap::default();
+        assert!(matches!(input_map.set_keys(Action::Pause, &[]), Err(InputMapError::Unbound(Action::Pause))));
+        // Pause has nothing to swap for the key it would lose
+        input_map.set_keys(Action::ToggleFps, &[]).unwrap();
+        assert!(matches!(input_map.set_keys(Action::ToggleFps, &[KeyCode::Escape]), Err(InputMapError::Unbound(Action::Pause))));
+        assert_eq!(input_map.action(KeyCode::Escape), Some(Action::Pause));
+        assert_eq!(input_map.keys(Action::ToggleFps), &[] as &[KeyCode]);
+        // Interact has other keys left, so taking one of them is fine
+        input_map.set_keys(Action::ToggleFps, &[KeyCode::E]).unwrap();
+        assert_eq!(input_map.keys(Action::Interact), &[KeyCode::Return, KeyCode::Space]);
+        // Keys taken from several actions at once are made up for with one given up key each
+        let mut input_map = InputMap::default();
+        input_map.set_keys(Action::MoveUp, &[KeyCode::Left, KeyCode::A, KeyCode::Right, KeyCode::D]).unwrap();
+        assert_eq!(input_map.keys(Action::MoveLeft), &[KeyCode::Up]);
+        assert_eq!(input_map.keys(Action::MoveRight), &[KeyCode::W]);
+        assert_eq!(input_map.action(KeyCode::W), Some(Action::MoveRight));
+    }
+
+    #[test]
+    fn hand_edited_settings_are_brought_back_in_range() {
+        let settings: Settings = "(scale: 7.0, master_volume: -2.0, music_volume: 3.5)".parse().expect("could not parse settings");
+        assert_eq!(settings.scale, 2.0);
+        assert_eq!(settings.master_volume, 0.0);
+        assert_eq!(settings.music_volume, 1.0);
+        let settings: Settings = "(scale: 1.4)".parse().expect("could not parse settings");
+        assert_eq!(settings.scale, 1.5);
+    }
+}
@@ -0,0 +1,683 @@
+use crate::component::*;
+use crate::map::{self, MapObject, MapTile};
+use crate::pathfinding;
+use crate::mapgen;
+use crate::{Direction, Point2, TL_PX};
+use crate::input::Input;
+use crate::viewport_system::{self, ViewportSystem};
+use crate::visibility_system::{self, Explored, Visibility, VisibilitySystem};
+use crate::movement_system::MovementSystem;
+use crate::animation_system::AnimationSystem;
+use crate::ai_system::AiSystem;
+use crate::spawner;
+use crate::item::{self, ItemDefs};
+use crate::pickup_system::PickupSystem;
+use crate::combat_system::CombatSystem;
+use crate::death_system::{DeathSystem, SpawnPoint};
+use crate::trigger_system::{self, FiredTriggers, SpentTriggers, TriggerSystem};
+use crate::save::{SaveMarker, SavedWorld, SerializeMe};
+use crate::input::Action;
+use crate::events::{self, GameEvent, GameEvents};
+use crate::script::{self, Scripts};
+use crate::quest::{self, QuestDefs, Quests};
+use crate::clock::Clock;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use specs::saveload::{MarkedBuilder, SimpleMarkerAllocator};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use specs::prelude::*;
+
+pub const TICKS_PER_SECOND: u32 = 60;
+const ANIMATION_FPS: u64 = 8;
+const PLAYER_MOVE_SPEED_TPS: f32 = 1.0;
+/// How hard the player accelerates each tick with the stick pushed all the way.
+/// Friction in the movement system settles this at a little under two pixels per tick.
+const PLAYER_STICK_ACCELERATION: f32 = PLAYER_MOVE_SPEED_TPS * TL_PX as f32 * 0.5;
+
+const PLAYER_INVENTORY_CAPACITY: usize = 12;
+/// Health is counted in half hearts.
+const PLAYER_HEALTH: i32 = 6;
+const PLAYER_IFRAMES: u32 = 60;
+/// Everything that can be hurt is a little smaller than the tile it stands on.
+pub const HURTBOX_PX: f32 = 24.0;
+
+/// How long a swing of the players weapon takes, and how long until the next one can start.
+pub const ATTACK_TICKS: u32 = 18;
+/// How many ticks at the start of a swing can actually hit anything.
+const ATTACK_ACTIVE_TICKS: u32 = 6;
+/// How far in front of the player the middle of a swing is.
+const ATTACK_REACH_PX: f32 = 24.0;
+const ATTACK_SIZE_PX: f32 = 28.0;
+const ATTACK_DAMAGE: i32 = 1;
+const ATTACK_KNOCKBACK_PX: f32 = 24.0;
+
+/// The lantern the player carries around.
+const LANTERN_RADIUS_PX: f32 = 3.5 * TL_PX as f32;
+const LANTERN_COLOR: [f32; 3] = [1.0, 0.85, 0.6];
+const LANTERN_INTENSITY: f32 = 0.8;
+
+/// Every world starts from the same seed, so that replays play out exactly as they were recorded.
+const WORLD_SEED: u64 = 0x7e57_ab1e;
+
+/// Number of fixed-step ticks the simulation has advanced since the world was created.
+#[derive(Default, Debug)]
+pub struct Ticks(pub u64);
+
+/// Messages for the player about things that happened in the world, waiting to be shown.
+#[derive(Default, Debug)]
+pub struct Notifications(pub Vec<String>);
+
+impl Notifications {
+    pub fn push(&mut self, message: String) {
+        self.0.push(message);
+    }
+
+    /// Takes every message that has not been shown yet.
+    pub fn drain(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// The only source of randomness the simulation is allowed to use.
+pub struct WorldRng(pub Pcg32);
+
+/// Where the current map was loaded from, so that a saved game knows which map to come back to.
+#[derive(Default, Debug)]
+pub struct MapPath(pub String);
+
+/// Everything placed on the current map in tiled, so that objects spawned later by triggers
+/// can still find the objects they refer to.
+#[derive(Clone, Default, Debug)]
+pub struct MapObjects(pub Vec<MapObject>);
+
+/// Custom properties of the current map, which pick things like its music.
+#[derive(Clone, Default, Debug)]
+pub struct MapProperties(pub HashMap<String, String>);
+
+impl MapProperties {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| value.as_str())
+    }
+}
+
+/// A conversation which something in the world started with the player,
+/// waiting to be opened once the tick is over.
+#[derive(Default, Debug)]
+pub struct PendingDialogue(pub Option<String>);
+
+/// Named switches and counters remembering what the player has done in the world.
+/// Counters which were never added to count zero.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Flags {
+    set: BTreeSet<String>,
+    counters: BTreeMap<String, i64>,
+}
+
+impl Flags {
+    pub fn set(&mut self, name: &str) {
+        self.set.insert(name.to_string());
+    }
+
+    pub fn is_set(&self, name: &str) -> bool {
+        self.set.contains(name)
+    }
+
+    /// Adds to a counter, which may be negative to count back down, returning where it ends up.
+    pub fn add(&mut self, name: &str, amount: i64) -> i64 {
+        let count = self.counters.entry(name.to_string()).or_insert(0);
+        *count += amount;
+        *count
+    }
+
+    pub fn count(&self, name: &str) -> i64 {
+        self.counters.get(name).copied().unwrap_or(0)
+    }
+}
+
+/// Creates the ecs world with every component registered and the player spawned.
+/// The world starts out with an empty map, use `load_map` to fill it in.
+pub fn create_world() -> World {
+    let mut world = create_empty_world();
+    spawn_player(&mut world);
+    world
+}
+
+/// Creates the ecs world with every component registered but nothing in it,
+/// ready for a saved game to be loaded into.
+pub fn create_empty_world() -> World {
+    let mut world = World::new();
+    world.register::<Position>();
+    world.register::<Renderable>();
+    world.register::<Player>();
+    world.register::<Viewport>();
+    world.register::<PathFollow>();
+    world.register::<Npc>();
+    world.register::<BlocksTile>();
+    world.register::<Ai>();
+    world.register::<Interactable>();
+    world.register::<Item>();
+    world.register::<Inventory>();
+    world.register::<Health>();
+    world.register::<Hurtbox>();
+    world.register::<Hitbox>();
+    world.register::<Knockback>();
+    world.register::<Attacking>();
+    world.register::<AudioEmitter>();
+    world.register::<TriggerZone>();
+    world.register::<Scripted>();
+    world.register::<LightSource>();
+    world.register::<SaveMarker>();
+    world.register::<SavedWorld>();
+
+    world.insert(Ticks(0));
+    world.insert(Clock::default());
+    world.insert(WorldRng(Pcg32::seed_from_u64(WORLD_SEED)));
+    world.insert(BTreeMap::<(i32, i32), MapTile>::new());
+    world.insert(ItemDefs::default());
+    world.insert(SpawnPoint((0, 0)));
+    world.insert(Notifications::default());
+    world.insert(MapPath::default());
+    world.insert(MapProperties::default());
+    world.insert(MapObjects::default());
+    world.insert(GameEvents::new());
+    viewport_system::subscribe(&mut world);
+    world.insert(Visibility::default());
+    world.insert(Explored::default());
+    visibility_system::subscribe(&mut world);
+    world.insert(Flags::default());
+    world.insert(QuestDefs::default());
+    world.insert(Quests::default());
+    quest::subscribe(&mut world);
+    world.insert(FiredTriggers::default());
+    world.insert(SpentTriggers::default());
+    world.insert(Scripts::default());
+    world.insert(PendingDialogue::default());
+    world.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+    world
+}
+
+fn spawn_player(world: &mut World) {
+    world.create_entity()
+        .with(Position { x: 0.0, y: 0.0 })
+        .with(Renderable {})
+        .with(Player {
+            direction: Direction::Down,
+            velocity: Point2::new(0.0, 0.0),
+            acceleration: Point2::new(0.0, 0.0),
+            animation_index: 0,
+        })
+        .with(Viewport {
+            tiles: vec![],
+            dirty: true,
+        })
+        .with(BlocksTile {})
+        .with(Inventory::new(PLAYER_INVENTORY_CAPACITY))
+        .with(Health::new(PLAYER_HEALTH, PLAYER_IFRAMES))
+        .with(Hurtbox { faction: Faction::Friendly, width: HURTBOX_PX, height: HURTBOX_PX })
+        .with(LightSource { radius: LANTERN_RADIUS_PX, color: LANTERN_COLOR, intensity: LANTERN_INTENSITY })
+        .marked::<SaveMarker>()
+        .build();
+}
+
+/// Loads a tiled map from disk and makes it the current map of the world,
+/// spawning everything placed on it.
+pub fn load_map(ecs: &mut World, path: &Path) {
+    let objects = load_map_tiles(ecs, path);
+    spawner::spawn_map_objects(ecs, &objects);
+}
+
+/// Leaves the current map for another one, throwing away everything on it except the player.
+/// Only the triggers which went off for good are remembered, coming back to it later starts everything else over.
+pub fn change_map(ecs: &mut World, path: &Path) {
+    trigger_system::remember_spent(ecs);
+    let left_behind: Vec<Entity> = (&ecs.entities(), !&ecs.read_storage::<Player>()).join()
+        .map(|(entity, _player)| entity)
+        .collect();
+    ecs.delete_entities(&left_behind).expect("could not delete entities of the old map");
+    load_map(ecs, path);
+}
+
+/// Moves the player straight onto a tile, first changing to a different map if one is given.
+/// Arriving on a different map makes the tile the player arrived on their spawn point.
+pub fn warp_player(ecs: &mut World, map: Option<&str>, tile: (i32, i32)) {
+    let mut tile = tile;
+    if let Some(map) = map {
+        if !map_exists(map) {
+            println!("Could not warp to missing map {}", map);
+            return;
+        }
+        change_map(ecs, Path::new(map));
+        // Nobody knows where the walls of a generated map are before it is generated,
+        // so arriving in one lands on the closest tile that can be stood on instead
+        if !map::tile_type_at(&ecs.fetch(), tile.0, tile.1).is_walkable() {
+            tile = map::nearest_walkable(&ecs.fetch(), tile).unwrap_or(tile);
+        }
+        ecs.insert(SpawnPoint(tile));
+    }
+    let entities = ecs.entities();
+    let mut positions = ecs.write_storage::<Position>();
+    let mut players = ecs.write_storage::<Player>();
+    let mut paths = ecs.write_storage::<PathFollow>();
+    let mut events = ecs.fetch_mut::<GameEvents>();
+    for (entity, position, player) in (&entities, &mut positions, &mut players).join() {
+        position.x = map::to_px(tile.0) as f32;
+        position.y = map::to_px(tile.1) as f32;
+        player.velocity = Point2::origin();
+        paths.remove(entity);
+        events.single_write(GameEvent::PlayerMoved { entity, x: position.x, y: position.y });
+    }
+}
+
+/// Whether there is a map to be found at a path, either on disk or generated from a seed.
+pub fn map_exists(path: &str) -> bool {
+    mapgen::parse_source(path).is_some() || Path::new(path).exists()
+}
+
+/// Makes a tiled map the current map of the world without spawning anything placed on it,
+/// returning the objects instead.
+pub fn load_map_tiles(ecs: &mut World, path: &Path) -> Vec<MapObject> {
+    let loaded = match mapgen::parse_source(&path.to_string_lossy()) {
+        Some((generator, seed)) => mapgen::generate_map(generator, seed),
+        None => map::load_map_tmx(path),
+    };
+    ecs.insert(loaded.tiles);
+    ecs.insert(loaded.tilesheet);
+    ecs.insert(MapPath(path.to_string_lossy().into_owned()));
+    ecs.insert(MapProperties(loaded.properties));
+    ecs.insert(MapObjects(loaded.objects.clone()));
+    events::publish(ecs, GameEvent::MapChanged { path: path.to_string_lossy().into_owned() });
+    loaded.objects
+}
+
+/// Advances the world by exactly one fixed-step tick.
+/// Nothing in here may depend on wall-clock time, so that the same
+/// inputs always produce the same world.
+pub fn tick(ecs: &mut World) {
+    let mut ai_system = AiSystem{};
+    let mut movement_system = MovementSystem{};
+    let mut viewport_system = ViewportSystem{};
+    let mut visibility_system = VisibilitySystem{};
+    let mut pickup_system = PickupSystem{};
+    let mut combat_system = CombatSystem{};
+    let mut death_system = DeathSystem{};
+    let mut trigger_system = TriggerSystem{};
+    ai_system.run_now(ecs);
+    movement_system.run_now(ecs);
+    pickup_system.run_now(ecs);
+    combat_system.run_now(ecs);
+    death_system.run_now(ecs);
+    trigger_system.run_now(ecs);
+    trigger_system::run_triggers(ecs);
+    script::run_scripts(ecs);
+    quest::update_quests(ecs);
+    viewport_system.run_now(ecs);
+    visibility_system.run_now(ecs);
+
+    ecs.write_resource::<Clock>().advance();
+    let ticks = {
+        let mut ticks = ecs.write_resource::<Ticks>();
+        ticks.0 += 1;
+        ticks.0
+    };
+    // Animations step eight times a second, on whichever ticks start the next eighth
+    let animation_frame = |ticks: u64| ticks * ANIMATION_FPS / TICKS_PER_SECOND as u64;
+    if animation_frame(ticks) != animation_frame(ticks - 1) {
+        let mut animation_system = AnimationSystem{};
+        animation_system.run_now(ecs);
+    }
+
+    // Something about rebalancing the new / old entities, not exactly sure
+    ecs.maintain();
+}
+
+/// Pushes the player one tile worth of velocity in the given direction.
+#[allow(clippy::neg_multiply)]
+pub fn try_move_player(direction: Direction, ecs: &World) {
+    let delta = match direction {
+        Direction::Up => (0.0, -1.0 * (PLAYER_MOVE_SPEED_TPS * TL_PX as f32)),
+        Direction::Left => (-1.0 * (PLAYER_MOVE_SPEED_TPS * TL_PX as f32), 0.0),
+        Direction::Down => (0.0, PLAYER_MOVE_SPEED_TPS * TL_PX as f32),
+        Direction::Right => (PLAYER_MOVE_SPEED_TPS * TL_PX as f32, 0.0),
+    };
+    let entities = ecs.entities();
+    let mut players = ecs.write_storage::<Player>();
+    let mut paths = ecs.write_storage::<PathFollow>();
+    for (entity, player) in (&entities, &mut players).join() {
+        player.direction = direction;
+        player.velocity.x += delta.0;
+        player.velocity.y += delta.1;
+        // Taking control manually abandons any path the player was walking
+        paths.remove(entity);
+    }
+}
+
+/// Applies the parts of an input which affect the simulation.
+pub fn apply_input(input: Input, ecs: &World) {
+    match input {
+        Input::Action(Action::Attack) => attack(ecs),
+        Input::Action(action) => {
+            if let Some(direction) = action.direction() {
+                try_move_player(direction, ecs);
+            }
+        }
+        Input::Stick(x, y) => steer_player(x, y, ecs),
+        Input::MoveTo(x, y) => walk_player_to((x, y), ecs),
+        Input::UseItem(slot) => use_item(slot, ecs),
+        Input::DropItem(slot) => drop_item(slot, ecs),
+    }
+}
+
+/// Picks up any items on the tile the player is standing on or facing,
+/// otherwise looks for something to talk to on the tile the player is facing.
+/// Any npc found turns around to face the player, and the id of its dialogue is returned.
+/// With nobody to talk to, any trigger zones the player is in or facing are interacted with instead.
+pub fn interact(ecs: &World) -> Option<String> {
+    if pick_up_nearby_items(ecs) {
+        return None;
+    }
+    match talk(ecs) {
+        Talk::Dialogue(dialogue) => Some(dialogue),
+        Talk::Scripted => None,
+        Talk::Nobody => {
+            trigger_system::interact_with_zones(ecs);
+            None
+        }
+    }
+}
+
+/// What came of the player trying to talk to whatever they are facing.
+enum Talk {
+    /// There was nothing there to talk to.
+    Nobody,
+    /// It has a dialogue with this id for the player.
+    Dialogue(String),
+    /// It has a script, which was asked to handle being talked to instead.
+    Scripted,
+}
+
+/// Finds something to talk to on the tile the player is facing, and works out what comes of talking to it.
+/// Anything with a script calls `on_interact` on its script instead of opening a dialogue.
+fn talk(ecs: &World) -> Talk {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let interactables = ecs.read_storage::<Interactable>();
+    let scripted = ecs.read_storage::<Scripted>();
+    let mut npcs = ecs.write_storage::<Npc>();
+
+    let (talker, position, player) = match (&entities, &positions, &players).join().next() {
+        Some(player) => player,
+        None => return Talk::Nobody,
+    };
+    let (dx, dy) = player.direction.offset();
+    let faced = (map::to_tl(position.x) + dx, map::to_tl(position.y) + dy);
+    let found = (&entities, &positions).join()
+        .filter(|(entity, _position)| interactables.contains(*entity) || scripted.contains(*entity))
+        .find(|(_entity, position)| (map::to_tl(position.x), map::to_tl(position.y)) == faced);
+    let (entity, _position) = match found {
+        Some(found) => found,
+        None => return Talk::Nobody,
+    };
+
+    if let Some(npc) = npcs.get_mut(entity) {
+        npc.direction = player.direction.opposite();
+        events::publish(ecs, GameEvent::TalkedTo { entity: talker, npc: npc.name.clone() });
+    }
+    if let Some(scripted) = scripted.get(entity) {
+        let owner = npcs.get(entity).map_or("", |npc| npc.name.as_str());
+        ecs.fetch_mut::<Scripts>().call(&scripted.script, "on_interact", owner);
+        return Talk::Scripted;
+    }
+    match interactables.get(entity) {
+        Some(interactable) => Talk::Dialogue(interactable.dialogue.clone()),
+        None => Talk::Nobody,
+    }
+}
+
+/// Swings the players weapon in the direction they are facing, unless they are already swinging it.
+pub fn attack(ecs: &World) {
+    let entities = ecs.entities();
+    let mut positions = ecs.write_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let mut attacks = ecs.write_storage::<Attacking>();
+    let mut hitboxes = ecs.write_storage::<Hitbox>();
+
+    let mut swings = vec![];
+    for (entity, position, player) in (&entities, &positions, &players).join() {
+        if attacks.contains(entity) {
+            continue;
+        }
+        attacks.insert(entity, Attacking { ticks: ATTACK_TICKS }).expect("could not insert attack");
+        let (dx, dy) = player.direction.offset();
+        swings.push(Position {
+            x: position.x + dx as f32 * ATTACK_REACH_PX,
+            y: position.y + dy as f32 * ATTACK_REACH_PX,
+        });
+    }
+    for swing in swings {
+        entities.build_entity()
+            .with(swing, &mut positions)
+            .with(Hitbox {
+                faction: Faction::Friendly,
+                width: ATTACK_SIZE_PX,
+                height: ATTACK_SIZE_PX,
+                damage: ATTACK_DAMAGE,
+                knockback: ATTACK_KNOCKBACK_PX,
+                lifetime: Some(ATTACK_ACTIVE_TICKS),
+                hit: vec![],
+            }, &mut hitboxes)
+            .build();
+    }
+}
+
+/// Picks up every item on the players tile and the tile in front of them,
+/// including those which are not picked up just by walking over them.
+/// Returns whether anything was picked up.
+fn pick_up_nearby_items(ecs: &World) -> bool {
+    let defs = ecs.fetch::<ItemDefs>();
+    let mut notifications = ecs.fetch_mut::<Notifications>();
+    let mut events = ecs.fetch_mut::<GameEvents>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let mut inventories = ecs.write_storage::<Inventory>();
+    let mut items = ecs.write_storage::<Item>();
+
+    let mut picked_up = false;
+    for (entity, position, player, inventory) in (&entities, &positions, &players, &mut inventories).join() {
+        let here = (map::to_tl(position.x), map::to_tl(position.y));
+        let (dx, dy) = player.direction.offset();
+        let faced = (here.0 + dx, here.1 + dy);
+        for (item_entity, item_position, item) in (&entities, &positions, &mut items).join() {
+            let item_tile = (map::to_tl(item_position.x), map::to_tl(item_position.y));
+            if item_tile != here && item_tile != faced {
+                continue;
+            }
+            let before = item.count;
+            if item::pick_up(inventory, item, &defs, &mut notifications) {
+                entities.delete(item_entity).expect("could not delete picked up item");
+            }
+            if item.count != before {
+                events.single_write(GameEvent::ItemPickedUp { entity, id: item.id.clone(), count: before - item.count });
+                picked_up = true;
+            }
+        }
+    }
+    picked_up
+}
+
+/// Uses an item out of the players inventory, using it up if it is consumable.
+/// Items which have no use are left alone.
+pub fn use_item(slot: usize, ecs: &World) {
+    let defs = ecs.fetch::<ItemDefs>();
+    let players = ecs.read_storage::<Player>();
+    let mut inventories = ecs.write_storage::<Inventory>();
+    for (_player, inventory) in (&players, &mut inventories).join() {
+        let def = match inventory.slots.get(slot).and_then(|stack| defs.get(&stack.id)) {
+            Some(def) => def,
+            None => continue,
+        };
+        if def.use_message.is_some() && def.consumable {
+            inventory.take(slot, 1);
+        }
+    }
+}
+
+/// Drops a whole stack out of the players inventory onto the tile they are standing on.
+/// Dropped items have to be interacted with to pick them back up, otherwise they
+/// would be picked straight back up again.
+pub fn drop_item(slot: usize, ecs: &World) {
+    let entities = ecs.entities();
+    let mut positions = ecs.write_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let mut inventories = ecs.write_storage::<Inventory>();
+    let mut renderables = ecs.write_storage::<Renderable>();
+    let mut items = ecs.write_storage::<Item>();
+    let mut markers = ecs.write_storage::<SaveMarker>();
+    let mut allocator = ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>();
+
+    let mut dropped = vec![];
+    for (position, _player, inventory) in (&positions, &players, &mut inventories).join() {
+        if let Some(stack) = inventory.take(slot, u32::MAX) {
+            let tile = (map::to_tl(position.x), map::to_tl(position.y));
+            dropped.push((tile, stack));
+        }
+    }
+    for (tile, stack) in dropped {
+        entities.build_entity()
+            .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 }, &mut positions)
+            .with(Renderable {}, &mut renderables)
+            .with(Item { id: stack.id, count: stack.count, auto_pickup: false }, &mut items)
+            .marked(&mut markers, &mut allocator)
+            .build();
+    }
+}
+
+/// Plans a path from the players current tile to the destination and sets them walking along it.
+/// Nothing happens if the destination cannot be reached.
+pub fn walk_player_to(destination: (i32, i32), ecs: &World) {
+    let map = ecs.fetch::<BTreeMap<(i32, i32), MapTile>>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let mut paths = ecs.write_storage::<PathFollow>();
+    for (entity, position, _player) in (&entities, &positions, &players).join() {
+        let start = (map::to_tl(position.x), map::to_tl(position.y));
+        if let Some(path) = pathfinding::a_star(&map, start, destination) {
+            paths.insert(entity, PathFollow { path: path.into_iter().collect() })
+                .expect("could not insert path for player");
+        }
+    }
+}
+
+/// Sets the npc with the given name walking over to a tile, or the player if the name is `player`.
+/// Nothing happens if the tile cannot be reached.
+pub fn walk_to(ecs: &World, name: &str, destination: (i32, i32)) {
+    if name == "player" {
+        walk_player_to(destination, ecs);
+        return;
+    }
+    let map = ecs.fetch::<BTreeMap<(i32, i32), MapTile>>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let npcs = ecs.read_storage::<Npc>();
+    let mut paths = ecs.write_storage::<PathFollow>();
+    match (&entities, &positions, &npcs).join().find(|(_entity, _position, npc)| npc.name == name) {
+        Some((entity, position, _npc)) => {
+            let start = (map::to_tl(position.x), map::to_tl(position.y));
+            if let Some(path) = pathfinding::a_star(&map, start, destination) {
+                paths.insert(entity, PathFollow { path: path.into_iter().collect() })
+                    .expect("could not insert path for npc");
+            }
+        }
+        None => println!("Could not find npc {} to move", name),
+    }
+}
+
+/// Hands items straight to the player, leaving whatever does not fit on the tile they are standing on.
+pub fn give_item(ecs: &mut World, id: &str, count: u32) {
+    if ecs.fetch::<ItemDefs>().get(id).is_none() {
+        println!("Could not give unknown item '{}'", id);
+        return;
+    }
+    let mut left_over = vec![];
+    {
+        let defs = ecs.fetch::<ItemDefs>();
+        let mut notifications = ecs.fetch_mut::<Notifications>();
+        let mut events = ecs.fetch_mut::<GameEvents>();
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let players = ecs.read_storage::<Player>();
+        let mut inventories = ecs.write_storage::<Inventory>();
+        for (entity, position, _player, inventory) in (&entities, &positions, &players, &mut inventories).join() {
+            let mut item = Item { id: id.to_string(), count, auto_pickup: false };
+            item::pick_up(inventory, &mut item, &defs, &mut notifications);
+            if item.count != count {
+                events.single_write(GameEvent::ItemPickedUp { entity, id: id.to_string(), count: count - item.count });
+            }
+            if item.count > 0 {
+                left_over.push((Position { x: position.x, y: position.y }, item));
+            }
+        }
+    }
+    for (position, item) in left_over {
+        ecs.create_entity()
+            .with(position)
+            .with(Renderable {})
+            .with(item)
+            .marked::<SaveMarker>()
+            .build();
+    }
+}
+
+/// Keeps the player accelerating every tick in proportion to how far the stick is pushed,
+/// until the stick is let go again. The player turns to face whichever axis is pushed furthest.
+pub fn steer_player(x: f32, y: f32, ecs: &World) {
+    let entities = ecs.entities();
+    let mut players = ecs.write_storage::<Player>();
+    let mut paths = ecs.write_storage::<PathFollow>();
+    for (entity, player) in (&entities, &mut players).join() {
+        player.acceleration = Point2::new(x * PLAYER_STICK_ACCELERATION, y * PLAYER_STICK_ACCELERATION);
+        if let Some(direction) = Direction::from_vector(x, y) {
+            player.direction = direction;
+            paths.remove(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object};
+    use crate::Direction;
+
+    #[test]
+    fn interacting_talks_to_the_npc_the_player_faces() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[object("Elder", "npc", (1, 0), &[("dialogue", "elder")])]);
+        game.press(Direction::Right);
+        game.step(10);
+        assert_eq!(game.interact(), Some("elder".to_string()));
+
+        let npcs = game.ecs.read_storage::<Npc>();
+        let elder = (&npcs).join().next().unwrap();
+        assert_eq!(elder.direction, Direction::Left);
+    }
+
+    #[test]
+    fn interacting_with_nothing_faced_does_nothing() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[
+            object("Elder", "npc", (1, 0), &[("dialogue", "elder")]),
+            object("Mute", "npc", (0, 1), &[]),
+        ]);
+        game.press(Direction::Left);
+        game.step(10);
+        assert_eq!(game.interact(), None);
+        game.press(Direction::Down);
+        game.step(10);
+        assert_eq!(game.interact(), None);
+    }
+}
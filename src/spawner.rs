@@ -0,0 +1,327 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::map::{self, MapObject};
+use std::collections::HashSet;
+use crate::item::ItemDefs;
+use crate::simulation::{MapPath, HURTBOX_PX};
+use crate::trigger_system::SpentTriggers;
+use crate::save::SaveMarker;
+use specs::saveload::MarkedBuilder;
+use crate::{Direction, TL_PX};
+
+const ENEMY_IFRAMES: u32 = 15;
+/// Enemies reach a little past their own tile, so that standing next to one hurts.
+const ENEMY_CONTACT_PX: f32 = 48.0;
+const ENEMY_KNOCKBACK_PX: f32 = 32.0;
+const DEFAULT_SOUND_RANGE_TL: i32 = 6;
+const DEFAULT_WANDER_RADIUS_TL: i32 = 3;
+
+/// Creates entities for every object on the map that we know how to spawn.
+/// Objects which only exist to be referenced by others, like patrol routes
+/// or objects spawned later by triggers, are skipped.
+pub fn spawn_map_objects(ecs: &mut World, objects: &[MapObject]) {
+    let spawned_later = spawned_by_triggers(objects);
+    for object in objects.iter() {
+        if spawned_later.contains(object.name.as_str()) {
+            continue;
+        }
+        spawn_map_object(ecs, object, objects);
+    }
+}
+
+/// Creates entities for one object on the map, which can refer to any of the other objects on it.
+pub fn spawn_map_object(ecs: &mut World, object: &MapObject, objects: &[MapObject]) {
+    match object.kind.as_str() {
+        "npc" => {
+            spawn_npc(ecs, object, objects);
+        }
+        "item" => spawn_item(ecs, object),
+        "enemy" => {
+            spawn_enemy(ecs, object);
+        }
+        "sound" => {
+            spawn_sound(ecs, object);
+        }
+        "trigger" => {
+            spawn_trigger(ecs, object, objects);
+        }
+        "light" => {
+            spawn_light(ecs, object);
+        }
+        "route" => (),
+        kind => println!("Skipping map object {} with unknown type '{}'", object.name, kind),
+    }
+}
+
+/// How many tiles a wandering npc strays from where it started.
+/// A radius which is negative or not a number falls back to the default.
+fn wander_radius(object: &MapObject) -> i32 {
+    let radius = match object.property("radius") {
+        Some(radius) => radius,
+        None => return DEFAULT_WANDER_RADIUS_TL,
+    };
+    match radius.parse() {
+        Ok(radius) if radius >= 0 => radius,
+        _ => {
+            println!("Npc {} wanders within invalid radius '{}', using {} instead", object.name, radius, DEFAULT_WANDER_RADIUS_TL);
+            DEFAULT_WANDER_RADIUS_TL
+        }
+    }
+}
+
+/// Spawns an npc, reading its behaviour from the `behaviour` property of the object.
+/// Wandering npcs read their `radius`, and patrolling npcs follow the polyline or polygon
+/// named by their `route`. Open routes are walked back and forth, closed ones loop around.
+/// Npcs with a `dialogue` property can be talked to, and those with a `script` run it instead.
+/// Npcs with a `light` property carry a light around with them.
+pub fn spawn_npc(ecs: &mut World, object: &MapObject, objects: &[MapObject]) -> Entity {
+    let tile = object.tile();
+    let behaviour = match object.property("behaviour").unwrap_or("idle") {
+        "wander" => Behaviour::Wander { origin: tile, radius: wander_radius(object) },
+        "patrol" => {
+            let route_name = object.property("route").unwrap_or_default();
+            match objects.iter().find(|route| route.name == route_name) {
+                Some(route) => Behaviour::Patrol { route: patrol_route(route), next: 0 },
+                None => {
+                    println!("Npc {} patrols along missing route '{}'", object.name, route_name);
+                    Behaviour::Idle
+                }
+            }
+        }
+        _ => Behaviour::Idle,
+    };
+
+    let mut builder = ecs.create_entity()
+        .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 })
+        .with(Renderable {})
+        .with(Npc {
+            name: object.name.clone(),
+            direction: Direction::Down,
+            animation_index: 0,
+        })
+        .with(BlocksTile {})
+        .with(Ai { behaviour, wait: 0 })
+        .marked::<SaveMarker>();
+    if let Some(dialogue) = object.property("dialogue") {
+        builder = builder.with(Interactable { dialogue: dialogue.to_string() });
+    }
+    if let Some(script) = object.property("script") {
+        builder = builder.with(Scripted { script: script.to_string() });
+    }
+    if let Some(emitter) = audio_emitter(object) {
+        builder = builder.with(emitter);
+    }
+    if let Some(light) = light_source(object) {
+        builder = builder.with(light);
+    }
+    builder.build()
+}
+
+/// Spawns a hostile npc which chases the player once they come within `sight` tiles,
+/// hurting them by `damage` on contact. It takes `health` hits to put down.
+pub fn spawn_enemy(ecs: &mut World, object: &MapObject) -> Entity {
+    let tile = object.tile();
+    let number = |name: &str, default: i32| object.property(name).and_then(|value| value.parse().ok()).unwrap_or(default);
+    ecs.create_entity()
+        .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 })
+        .with(Renderable {})
+        .with(Npc {
+            name: object.name.clone(),
+            direction: Direction::Down,
+            animation_index: 0,
+        })
+        .with(BlocksTile {})
+        .with(Ai { behaviour: Behaviour::Chase { sight: number("sight", 5) }, wait: 0 })
+        .with(Health::new(number("health", 3), ENEMY_IFRAMES))
+        .with(Hurtbox { faction: Faction::Hostile, width: HURTBOX_PX, height: HURTBOX_PX })
+        .with(Hitbox {
+            faction: Faction::Hostile,
+            width: ENEMY_CONTACT_PX,
+            height: ENEMY_CONTACT_PX,
+            damage: number("damage", 1),
+            knockback: ENEMY_KNOCKBACK_PX,
+            lifetime: None,
+            hit: vec![],
+        })
+        .marked::<SaveMarker>()
+        .build()
+}
+
+/// Spawns an item named by the `item` property of the object, `count` of them at once.
+/// Setting `pickup` to `interact` means the player has to interact with the item to pick it up.
+/// Items which are not defined in the item definitions are skipped.
+pub fn spawn_item(ecs: &mut World, object: &MapObject) {
+    let id = object.property("item").unwrap_or_default().to_string();
+    if ecs.fetch::<ItemDefs>().get(&id).is_none() {
+        println!("Skipping map object {} with unknown item '{}'", object.name, id);
+        return;
+    }
+    let tile = object.tile();
+    ecs.create_entity()
+        .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 })
+        .with(Renderable {})
+        .with(Item {
+            id,
+            count: object.property("count").and_then(|count| count.parse().ok()).unwrap_or(1),
+            auto_pickup: object.property("pickup") != Some("interact"),
+        })
+        .marked::<SaveMarker>()
+        .build();
+}
+
+/// Spawns something which can only be heard, like a river or birds in the trees.
+pub fn spawn_sound(ecs: &mut World, object: &MapObject) -> Option<Entity> {
+    let emitter = match audio_emitter(object) {
+        Some(emitter) => emitter,
+        None => {
+            println!("Skipping map object {} without a sound", object.name);
+            return None;
+        }
+    };
+    let tile = object.tile();
+    let entity = ecs.create_entity()
+        .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 })
+        .with(emitter)
+        .marked::<SaveMarker>()
+        .build();
+    Some(entity)
+}
+
+/// Reads the sound an object makes from its `sound` property. Sounds carry for `range` tiles,
+/// at `volume` from 0.0 to 1.0, and keep playing while in range if `loop` is set.
+fn audio_emitter(object: &MapObject) -> Option<AudioEmitter> {
+    let sound = object.property("sound")?;
+    let range: i32 = object.property("range").and_then(|range| range.parse().ok()).unwrap_or(DEFAULT_SOUND_RANGE_TL);
+    Some(AudioEmitter {
+        sound: sound.to_string(),
+        looping: object.property("loop") == Some("true"),
+        range: map::to_px(range) as f32,
+        volume: object.property("volume").and_then(|volume| volume.parse().ok()).unwrap_or(1.0),
+    })
+}
+
+/// Spawns a light which is nothing else, like a torch on a wall or a lit window.
+pub fn spawn_light(ecs: &mut World, object: &MapObject) -> Option<Entity> {
+    let light = match light_source(object) {
+        Some(light) => light,
+        None => {
+            println!("Skipping map object {} without a light", object.name);
+            return None;
+        }
+    };
+    let tile = object.tile();
+    let entity = ecs.create_entity()
+        .with(Position { x: map::to_px(tile.0) as f32, y: map::to_px(tile.1) as f32 })
+        .with(light)
+        .marked::<SaveMarker>()
+        .build();
+    Some(entity)
+}
+
+/// Reads the light an object gives off from its `light` property, how many tiles it reaches.
+/// Lights are white unless given a `light_color` such as `#ffcc88`,
+/// and shine at `light_intensity` from 0.0 to 1.0.
+fn light_source(object: &MapObject) -> Option<LightSource> {
+    let radius: f32 = object.property("light")?.parse().ok()?;
+    Some(LightSource {
+        radius: radius * TL_PX as f32,
+        color: object.property("light_color").and_then(parse_color).unwrap_or([1.0, 1.0, 1.0]),
+        intensity: object.property("light_intensity").and_then(|intensity| intensity.parse().ok()).unwrap_or(1.0),
+    })
+}
+
+/// Parses a colour the way tiled writes them, as `#rrggbb` or with alpha in front as `#aarrggbb`.
+pub fn parse_color(text: &str) -> Option<[f32; 3]> {
+    let hex = text.strip_prefix('#')?;
+    let rgb = match hex.len() {
+        6 => hex,
+        8 => &hex[2..],
+        _ => return None,
+    };
+    let channel = |start: usize| u8::from_str_radix(rgb.get(start..start + 2)?, 16).ok().map(|value| value as f32 / 255.0);
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Spawns a zone covering the rectangle of the object, which does things as the player moves around it.
+/// Its `on_enter`, `on_exit` and `on_interact` properties each list actions separated by `;`, such as
+/// `message Welcome home`, `flag visited_home`, `warp 4 2`, `warp assets/map/house.tmx 4 2`, `sound birdsong`
+/// `spawn Slime`, where the object named Slime is spawned, `quest herbs`, which starts the herbs quest,
+/// or `script gate`, which calls the function of the gate script named after when the trigger went off.
+/// Actions starting with `once` only happen once, even when the player leaves the map and comes back.
+pub fn spawn_trigger(ecs: &mut World, object: &MapObject, objects: &[MapObject]) -> Entity {
+    let mut triggers = vec![];
+    for (when, property) in [(TriggerWhen::Enter, "on_enter"), (TriggerWhen::Exit, "on_exit"), (TriggerWhen::Interact, "on_interact")] {
+        for (once, verb, argument) in trigger_actions(object.property(property).unwrap_or_default()) {
+            match trigger_action(verb, argument, objects) {
+                Some(action) => triggers.push(Trigger { when, action, once, spent: false }),
+                None => println!("Skipping action '{} {}' of trigger {}", verb, argument, object.name),
+            }
+        }
+    }
+    if let Some(spent) = ecs.fetch::<SpentTriggers>().0.get(&ecs.fetch::<MapPath>().0) {
+        for (index, trigger) in triggers.iter_mut().enumerate() {
+            trigger.spent = spent.contains(&(object.name.clone(), index));
+        }
+    }
+    ecs.create_entity()
+        .with(TriggerZone { name: object.name.clone(), area: object.area(), triggers, occupied: false })
+        .marked::<SaveMarker>()
+        .build()
+}
+
+/// Splits a list of trigger actions into whether each only happens once, what it does, and what to.
+fn trigger_actions(actions: &str) -> impl Iterator<Item = (bool, &str, &str)> {
+    actions.split(';')
+        .map(str::trim)
+        .filter(|action| !action.is_empty())
+        .map(|action| {
+            let (once, action) = match action.strip_prefix("once ") {
+                Some(action) => (true, action.trim_start()),
+                None => (false, action),
+            };
+            let (verb, argument) = action.split_once(' ').unwrap_or((action, ""));
+            (once, verb, argument.trim())
+        })
+}
+
+fn trigger_action(verb: &str, argument: &str, objects: &[MapObject]) -> Option<TriggerAction> {
+    match verb {
+        "message" => Some(TriggerAction::Message(argument.to_string())),
+        "flag" if !argument.is_empty() => Some(TriggerAction::SetFlag(argument.to_string())),
+        "sound" if !argument.is_empty() => Some(TriggerAction::PlaySound(argument.to_string())),
+        "warp" => {
+            let words: Vec<&str> = argument.split_whitespace().collect();
+            let (map, x, y) = match words.as_slice() {
+                [x, y] => (None, x, y),
+                [map, x, y] => (Some(map.to_string()), x, y),
+                _ => return None,
+            };
+            Some(TriggerAction::Warp { map, tile: (x.parse().ok()?, y.parse().ok()?) })
+        }
+        "script" if !argument.is_empty() => Some(TriggerAction::Script(argument.to_string())),
+        "quest" if !argument.is_empty() => Some(TriggerAction::StartQuest(argument.to_string())),
+        "spawn" => objects.iter().find(|object| object.name == argument).cloned().map(TriggerAction::Spawn),
+        _ => None,
+    }
+}
+
+/// Names of every object which a trigger spawns, rather than being spawned along with the map.
+fn spawned_by_triggers(objects: &[MapObject]) -> HashSet<&str> {
+    objects.iter()
+        .filter(|object| object.kind == "trigger")
+        .flat_map(|object| ["on_enter", "on_exit", "on_interact"].iter().filter_map(move |property| object.property(property)))
+        .flat_map(trigger_actions)
+        .filter(|(_once, verb, _argument)| *verb == "spawn")
+        .map(|(_once, _verb, argument)| argument)
+        .collect()
+}
+
+/// Turns the points of a route into the cycle of tiles that a patrol walks through.
+fn patrol_route(route: &MapObject) -> Vec<(i32, i32)> {
+    let mut tiles = route.point_tiles();
+    if !route.closed && tiles.len() > 2 {
+        let returning: Vec<(i32, i32)> = tiles[1..tiles.len() - 1].iter().rev().copied().collect();
+        tiles.extend(returning);
+    }
+    tiles
+}
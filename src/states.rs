@@ -0,0 +1,101 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RootState {
+    StartMenu,
+    InGame,
+    Paused,
+    Dialogue,
+    Inventory,
+    QuestLog,
+    Settings,
+}
+
+impl RootState {
+    /// Overlays are drawn over the top of the states underneath them, which stay frozen
+    /// until the overlay is popped off again.
+    fn is_overlay(self) -> bool {
+        match self {
+            RootState::StartMenu | RootState::InGame => false,
+            RootState::Paused | RootState::Dialogue | RootState::Inventory | RootState::QuestLog | RootState::Settings => true,
+        }
+    }
+}
+
+/// The stack of root states. Only the state on top gets input and updates,
+/// the ones underneath wait for it to be popped.
+#[derive(Debug)]
+pub struct States(Vec<RootState>);
+
+impl States {
+    pub fn new(first: RootState) -> States {
+        States(vec![first])
+    }
+
+    /// The state which is currently getting input.
+    pub fn current(&self) -> RootState {
+        *self.0.last().expect("there is always a state")
+    }
+
+    pub fn push(&mut self, state: RootState) {
+        self.0.push(state);
+    }
+
+    /// Goes back to the state underneath the current one. The bottom state is never popped.
+    pub fn pop(&mut self) {
+        if self.0.len() > 1 {
+            self.0.pop();
+        }
+    }
+
+    /// Throws away every state, leaving only the given one.
+    pub fn reset(&mut self, state: RootState) {
+        self.0 = vec![state];
+    }
+
+    pub fn contains(&self, state: RootState) -> bool {
+        self.0.contains(&state)
+    }
+
+    /// The simulation only advances while the game itself is on top, anything over it freezes the world.
+    pub fn simulating(&self) -> bool {
+        self.current() == RootState::InGame
+    }
+
+    /// The states to draw, from the first one which is not an overlay up to the one on top.
+    pub fn drawn(&self) -> &[RootState] {
+        let bottom = self.0.iter().rposition(|state| !state.is_overlay()).unwrap_or(0);
+        &self.0[bottom..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn popping_goes_back_to_the_state_underneath_but_never_past_the_bottom() {
+        let mut states = States::new(RootState::InGame);
+        states.push(RootState::Inventory);
+        states.push(RootState::Dialogue);
+        assert_eq!(states.current(), RootState::Dialogue);
+        states.pop();
+        assert_eq!(states.current(), RootState::Inventory);
+        states.pop();
+        states.pop();
+        states.pop();
+        assert_eq!(states.current(), RootState::InGame);
+    }
+
+    #[test]
+    fn overlays_are_drawn_over_the_last_state_which_is_not_one() {
+        let mut states = States::new(RootState::StartMenu);
+        states.push(RootState::Settings);
+        assert_eq!(states.drawn(), &[RootState::StartMenu, RootState::Settings]);
+        states.reset(RootState::InGame);
+        states.push(RootState::Paused);
+        states.push(RootState::Settings);
+        assert_eq!(states.drawn(), &[RootState::InGame, RootState::Paused, RootState::Settings]);
+        states.pop();
+        states.pop();
+        assert_eq!(states.drawn(), &[RootState::InGame]);
+    }
+}
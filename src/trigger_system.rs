@@ -0,0 +1,269 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::map;
+use crate::simulation::{self, Flags, MapObjects, MapPath, Notifications};
+use crate::spawner;
+use crate::script::Scripts;
+use crate::quest;
+use crate::events::{self, GameEvent, GameEvents};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Actions of triggers which have gone off, waiting to be carried out at the end of the tick.
+/// Some of them change the whole world, which no system is allowed to do.
+#[derive(Default, Debug)]
+pub struct FiredTriggers(pub Vec<FiredTrigger>);
+
+/// The once only triggers which went off on maps the player has left, by the path of the map.
+/// Each is named by its zone and where it comes among the triggers of the zone. Zones are thrown
+/// away along with the rest of a map, so coming back to one looks here to keep them spent.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct SpentTriggers(pub BTreeMap<String, BTreeSet<(String, usize)>>);
+
+/// Remembers which triggers of the zones on the current map are spent, before the map is left.
+pub fn remember_spent(ecs: &World) {
+    let zones = ecs.read_storage::<TriggerZone>();
+    let spent = zones.join()
+        .flat_map(|zone| {
+            zone.triggers.iter().enumerate()
+                .filter(|(_index, trigger)| trigger.spent)
+                .map(move |(index, _trigger)| (zone.name.clone(), index))
+        })
+        .collect();
+    ecs.fetch_mut::<SpentTriggers>().0.insert(ecs.fetch::<MapPath>().0.clone(), spent);
+}
+
+#[derive(Clone, Debug)]
+pub struct FiredTrigger {
+    pub zone: String,
+    pub when: TriggerWhen,
+    pub action: TriggerAction,
+}
+
+/// Sets off the triggers of zones as the player walks into and out of them.
+pub struct TriggerSystem {}
+
+impl<'a> System<'a> for TriggerSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, FiredTriggers>,
+        Write<'a, GameEvents>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, TriggerZone>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, mut fired, mut events, positions, players, mut zones) = data;
+
+        let (player, tile) = match (&entities, &positions, &players).join().next() {
+            Some((entity, position, _player)) => (entity, (map::to_tl(position.x), map::to_tl(position.y))),
+            None => return,
+        };
+        for zone in (&mut zones).join() {
+            let occupied = zone.contains(tile);
+            if occupied == zone.occupied {
+                continue;
+            }
+            zone.occupied = occupied;
+            if occupied {
+                events.single_write(GameEvent::ZoneEntered { entity: player, zone: zone.name.clone() });
+            }
+            let when = if occupied { TriggerWhen::Enter } else { TriggerWhen::Exit };
+            fired.0.extend(fire(zone, when));
+        }
+    }
+}
+
+/// Sets off the interact triggers of every zone the player is standing in or facing into.
+/// Returns whether any went off.
+pub fn interact_with_zones(ecs: &World) -> bool {
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let mut zones = ecs.write_storage::<TriggerZone>();
+    let mut fired = ecs.fetch_mut::<FiredTriggers>();
+
+    let (position, player) = match (&positions, &players).join().next() {
+        Some(player) => player,
+        None => return false,
+    };
+    let here = (map::to_tl(position.x), map::to_tl(position.y));
+    let (dx, dy) = player.direction.offset();
+    let faced = (here.0 + dx, here.1 + dy);
+    let mut interacted = false;
+    for zone in (&mut zones).join().filter(|zone| zone.contains(here) || zone.contains(faced)) {
+        let actions = fire(zone, TriggerWhen::Interact);
+        interacted |= !actions.is_empty();
+        fired.0.extend(actions);
+    }
+    interacted
+}
+
+/// Carries out the actions of every trigger which went off, in the order they went off in.
+pub fn run_triggers(ecs: &mut World) {
+    let fired = std::mem::take(&mut ecs.fetch_mut::<FiredTriggers>().0);
+    for FiredTrigger { zone, when, action } in fired {
+        match action {
+            TriggerAction::Message(message) => ecs.fetch_mut::<Notifications>().push(message),
+            TriggerAction::SetFlag(flag) => ecs.fetch_mut::<Flags>().set(&flag),
+            TriggerAction::Warp { map, tile } => simulation::warp_player(ecs, map.as_deref(), tile),
+            TriggerAction::PlaySound(sound) => events::publish(ecs, GameEvent::SoundPlayed { sound }),
+            TriggerAction::Spawn(object) => {
+                // Whatever was spawned can refer to the rest of the map, like the route of a patrol
+                let objects = ecs.fetch::<MapObjects>().0.clone();
+                spawner::spawn_map_object(ecs, &object, &objects);
+            }
+            TriggerAction::StartQuest(id) => quest::start_quest(ecs, &id),
+            TriggerAction::Script(script) => ecs.fetch_mut::<Scripts>().call(&script, when.function(), &zone),
+        }
+    }
+}
+
+/// Every trigger of a zone which goes off at the given time, spending those which only go off once.
+fn fire(zone: &mut TriggerZone, when: TriggerWhen) -> Vec<FiredTrigger> {
+    let name = &zone.name;
+    zone.triggers.iter_mut()
+        .filter(|trigger| trigger.when == when && !trigger.spent)
+        .map(|trigger| {
+            trigger.spent = trigger.once;
+            FiredTrigger { zone: name.clone(), when, action: trigger.action.clone() }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{Headless, object, trigger};
+    use crate::map;
+    use crate::simulation;
+    use crate::input::Input;
+    use std::path::Path;
+    use crate::events::{GameEvent, Subscription};
+
+    #[test]
+    fn trigger_zones_go_off_on_enter_exit_and_interact() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        game.spawn(&[trigger("Porch", (3, 0), (2, 2), &[
+            ("on_enter", "once message Welcome; flag visited_porch; sound birdsong"),
+            ("on_exit", "message Goodbye"),
+            ("on_interact", "warp 8 8"),
+        ])]);
+        let mut events = Subscription::default();
+        events.subscribe(&game.ecs);
+
+        game.input(Input::MoveTo(3, 0));
+        game.step(60);
+        assert_eq!(game.notifications(), vec!["Welcome".to_string()]);
+        assert!(game.ecs.fetch::<simulation::Flags>().is_set("visited_porch"));
+        let events = events.read(&game.ecs);
+        assert!(events.contains(&GameEvent::SoundPlayed { sound: "birdsong".to_string() }));
+        assert!(events.iter().any(|event| matches!(event, GameEvent::ZoneEntered { zone, .. } if zone == "Porch")));
+
+        // Walking around inside the zone is not entering it again
+        game.input(Input::MoveTo(4, 1));
+        game.step(60);
+        assert!(game.notifications().is_empty());
+        game.input(Input::MoveTo(1, 1));
+        game.step(60);
+        assert_eq!(game.notifications(), vec!["Goodbye".to_string()]);
+        game.input(Input::MoveTo(3, 1));
+        game.step(60);
+        assert!(game.notifications().is_empty());
+
+        assert_eq!(game.interact(), None);
+        game.step(1);
+        assert_eq!(game.player_tile(), (8, 8));
+        game.step(1);
+        assert_eq!(game.notifications(), vec!["Goodbye".to_string()]);
+    }
+
+    #[test]
+    fn triggers_spawn_held_back_objects_and_stay_spent_across_saves() {
+        // Saves need a map on disk to come back to
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.spawn(&[
+            trigger("Vault", (4, 0), (1, 3), &[("on_enter", "once spawn Reward; once message Found it; bogus action")]),
+            object("Reward", "item", (8, 8), &[("item", "herb"), ("count", "4")]),
+        ]);
+        assert_eq!(game.lying_around("herb"), 1);
+
+        game.input(Input::MoveTo(4, 1));
+        game.step(90);
+        assert_eq!(game.player_tile(), (4, 1));
+        assert_eq!(game.lying_around("herb"), 5);
+        assert_eq!(game.notifications(), vec!["Found it".to_string()]);
+
+        game.reload();
+        game.input(Input::MoveTo(2, 1));
+        game.step(90);
+        game.input(Input::MoveTo(4, 2));
+        game.step(90);
+        assert_eq!(game.player_tile(), (4, 2));
+        assert_eq!(game.lying_around("herb"), 5);
+        assert!(game.notifications().is_empty());
+    }
+
+    #[test]
+    fn once_only_triggers_stay_spent_after_leaving_the_map_and_coming_back() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.input(Input::MoveTo(7, 5));
+        game.step(180);
+        assert_eq!(game.player_tile(), (7, 5));
+        assert_eq!(game.notifications(), vec!["You wander into the garden".to_string()]);
+
+        // The garden is thrown away with the map, and comes back from the map file
+        simulation::warp_player(&mut game.ecs, Some("generate:caves:7"), (5, 5));
+        game.step(1);
+        game.reload();
+        simulation::warp_player(&mut game.ecs, Some(map::BASIC_MAP_PATH), (0, 0));
+        game.step(1);
+        assert_eq!(game.ecs.read_storage::<TriggerZone>().join().filter(|zone| zone.name == "Garden").count(), 1);
+        game.input(Input::MoveTo(7, 5));
+        game.step(180);
+        assert_eq!(game.player_tile(), (7, 5));
+        assert!(game.notifications().is_empty());
+    }
+
+    #[test]
+    fn spawned_npcs_can_follow_routes_placed_on_the_map() {
+        let mut game = Headless::new(Headless::floor(10, 10));
+        let mut route = object("Beat", "route", (6, 2), &[]);
+        route.points = vec![(map::to_px(6) as f32, map::to_px(2) as f32), (map::to_px(6) as f32, map::to_px(6) as f32)];
+        game.spawn(&[
+            trigger("Alarm", (1, 0), (1, 1), &[("on_enter", "once spawn Guard")]),
+            object("Guard", "npc", (6, 2), &[("behaviour", "patrol"), ("route", "Beat")]),
+            route,
+        ]);
+        assert_eq!(game.ecs.read_storage::<Npc>().join().count(), 0);
+
+        game.input(Input::MoveTo(1, 0));
+        game.step(60);
+        let ais = game.ecs.read_storage::<Ai>();
+        let (_npc, ai) = (&game.ecs.read_storage::<Npc>(), &ais).join().next().expect("guard was not spawned");
+        assert!(matches!(&ai.behaviour, Behaviour::Patrol { route, .. } if route == &vec![(6, 2), (6, 6)]));
+    }
+
+    #[test]
+    fn warping_to_another_map_leaves_the_old_one_behind() {
+        let mut game = Headless::new(Headless::floor(10, 10)).with_items();
+        game.spawn(&[
+            trigger("Door", (1, 0), (1, 1), &[("on_enter", &format!("warp {} 2 5", map::BASIC_MAP_PATH))]),
+            trigger("Nowhere", (0, 1), (1, 1), &[("on_enter", "warp assets/map/missing.tmx 2 5")]),
+            object("Rock", "npc", (4, 4), &[]),
+        ]);
+        game.input(Input::MoveTo(0, 1));
+        game.step(30);
+        assert_eq!(game.player_tile(), (0, 1));
+
+        game.input(Input::MoveTo(1, 0));
+        game.step(60);
+        assert_eq!(game.player_tile(), (2, 5));
+        assert_eq!(game.ecs.fetch::<simulation::MapPath>().0, map::BASIC_MAP_PATH);
+        assert_eq!(game.ecs.fetch::<crate::death_system::SpawnPoint>().0, (2, 5));
+        let npcs: Vec<String> = game.ecs.read_storage::<Npc>().join().map(|npc| npc.name.clone()).collect();
+        assert!(npcs.contains(&"Elder".to_string()));
+        assert!(!npcs.contains(&"Rock".to_string()));
+        assert_eq!(game.ecs.read_storage::<TriggerZone>().join().filter(|zone| zone.name == "Door").count(), 0);
+    }
+}
@@ -1,21 +1,52 @@
 use specs::prelude::*;
 use crate::component::*;
+use crate::events::{GameEvent, GameEvents};
+use crate::Point2;
 use crate::TL_PX;
 use crate::HEIGHT_PX;
 use crate::WIDTH_PX;
 
+/// How far the viewport system has read through the events of the world. Kept in the world
+/// since systems are created afresh every tick.
+pub struct ViewportEvents(ReaderId<GameEvent>);
+
+/// Starts the viewport system listening for anything that moves what the player can see.
+pub fn subscribe(ecs: &mut World) {
+    let reader = ecs.fetch_mut::<GameEvents>().register_reader();
+    ecs.insert(ViewportEvents(reader));
+}
+
+/// Works out which tiles can be seen by anything with a viewport, whenever it moves or the map changes.
 pub struct ViewportSystem {}
 
 impl<'a> System<'a> for ViewportSystem {
     type SystemData = (
         Entities<'a>,
+        Read<'a, GameEvents>,
+        WriteExpect<'a, ViewportEvents>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, Player>,
         WriteStorage<'a, Viewport>
     );
 
     fn run(&mut self, data : Self::SystemData) {
-        let (entities, position, player, mut viewport) = data;
+        let (entities, events, mut reader, position, player, mut viewport) = data;
+
+        for event in events.read(&mut reader.0) {
+            match event {
+                GameEvent::PlayerMoved { entity, .. } => {
+                    if let Some(viewport) = viewport.get_mut(*entity) {
+                        viewport.dirty = true;
+                    }
+                }
+                GameEvent::MapChanged { .. } => {
+                    for viewport in (&mut viewport).join() {
+                        viewport.dirty = true;
+                    }
+                }
+                _ => (),
+            }
+        }
 
         for (_entity, position, _player, viewport) in (&entities, &position, &player, &mut viewport).join() {
             if viewport.dirty {
@@ -32,6 +63,7 @@ impl<'a> System<'a> for ViewportSystem {
 
 /// Calculate viewport origin (top-left 0,0 point) based on player position
 /// Viewport is specified as a tuple of pixel top-left coordinates
+#[allow(clippy::needless_return)]
 pub fn calculate_viewport(player_position: (f32, f32)) -> (i32, i32) {
     return (
         player_position.0.floor() as i32 - (WIDTH_PX / 2),
@@ -78,3 +110,28 @@ pub fn generate_viewport_tiles(viewport: (i32, i32)) -> Vec<(i32, i32, i32, i32,
     }
     viewport_tiles
 }
+
+/// Works out where a point in the world appears on screen, given that the player is always drawn
+/// in the middle. Anything further off the edges of the screen than the margin has no position.
+pub fn world_to_screen(x: f32, y: f32, player_position: (f32, f32), margin: f32) -> Option<Point2> {
+    let screen_x = x - player_position.0 + (WIDTH_PX / 2) as f32;
+    let screen_y = y - player_position.1 + (HEIGHT_PX / 2) as f32;
+    if screen_x < -margin || screen_x > WIDTH_PX as f32 + margin
+        || screen_y < -margin || screen_y > HEIGHT_PX as f32 + margin {
+        return None;
+    }
+    Some(Point2::new(screen_x, screen_y))
+}
+
+/// Finds the map tile drawn underneath a point on the screen.
+/// Tiles are drawn centered on their screen coordinates, so each one covers
+/// half a tile in every direction around that point.
+pub fn screen_to_tile(viewport: &Viewport, screen_x: f32, screen_y: f32) -> Option<(i32, i32)> {
+    let half_tile = TL_PX as f32 / 2.0;
+    viewport.tiles.iter()
+        .find(|(_tile_x, _tile_y, _view_x, _view_y, tile_screen_x, tile_screen_y)| {
+            screen_x >= *tile_screen_x as f32 - half_tile && screen_x < *tile_screen_x as f32 + half_tile
+                && screen_y >= *tile_screen_y as f32 - half_tile && screen_y < *tile_screen_y as f32 + half_tile
+        })
+        .map(|(tile_x, tile_y, _view_x, _view_y, _screen_x, _screen_y)| (*tile_x, *tile_y))
+}
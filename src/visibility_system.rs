@@ -0,0 +1,162 @@
+use specs::prelude::*;
+use crate::component::*;
+use crate::events::{GameEvent, GameEvents};
+use crate::fov;
+use crate::map::{self, MapTile};
+use crate::simulation::{MapPath, MapProperties};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How many tiles away the player can see on maps covered in fog.
+pub const SIGHT_RADIUS: i32 = 8;
+
+/// Every tile the player has seen, by the path of the map it is on. Kept for every map
+/// the player has been to, so that coming back to one still shows what was found there.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Explored(pub BTreeMap<String, BTreeSet<(i32, i32)>>);
+
+/// The tiles the player can see right now, and the tile they were seen from.
+/// Only worked out on maps covered in fog.
+#[derive(Default, Debug)]
+pub struct Visibility {
+    pub tiles: BTreeSet<(i32, i32)>,
+    seen_from: Option<(i32, i32)>,
+}
+
+/// Maps are only covered in fog when their `fog` property says so, everywhere else can always be seen.
+pub fn has_fog(properties: &MapProperties) -> bool {
+    properties.get("fog") == Some("true")
+}
+
+/// Whether a tile shows on screen, and if so whether it can be seen right now or is only remembered.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileVisibility {
+    Visible,
+    Explored,
+    Hidden,
+}
+
+impl Visibility {
+    /// How a tile of a map covered in fog shows on screen, given the tiles explored on the map.
+    pub fn of(&self, tile: (i32, i32), explored: Option<&BTreeSet<(i32, i32)>>) -> TileVisibility {
+        if self.tiles.contains(&tile) {
+            TileVisibility::Visible
+        } else if explored.is_some_and(|explored| explored.contains(&tile)) {
+            TileVisibility::Explored
+        } else {
+            TileVisibility::Hidden
+        }
+    }
+}
+
+/// How a tile of the current map shows on screen.
+pub fn tile_visibility(ecs: &World, tile: (i32, i32)) -> TileVisibility {
+    if !has_fog(&ecs.fetch::<MapProperties>()) {
+        return TileVisibility::Visible;
+    }
+    let explored = ecs.fetch::<Explored>();
+    ecs.fetch::<Visibility>().of(tile, explored.0.get(&ecs.fetch::<MapPath>().0))
+}
+
+/// How far the visibility system has read through the events of the world. Kept in the world
+/// since systems are created afresh every tick.
+pub struct VisibilityEvents(ReaderId<GameEvent>);
+
+/// Starts the visibility system listening for the map changing underneath the player.
+pub fn subscribe(ecs: &mut World) {
+    let reader = ecs.fetch_mut::<GameEvents>().register_reader();
+    ecs.insert(VisibilityEvents(reader));
+}
+
+/// Works out what the player can see on maps covered in fog, whenever they step onto a different tile
+/// or arrive on a different map, and remembers it as explored.
+pub struct VisibilitySystem {}
+
+impl<'a> System<'a> for VisibilitySystem {
+    type SystemData = (
+        Read<'a, GameEvents>,
+        WriteExpect<'a, VisibilityEvents>,
+        ReadExpect<'a, BTreeMap<(i32, i32), MapTile>>,
+        Read<'a, MapPath>,
+        Read<'a, MapProperties>,
+        Write<'a, Visibility>,
+        Write<'a, Explored>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Player>
+    );
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (events, mut reader, tiles, map_path, properties, mut visibility, mut explored, position, player) = data;
+
+        for event in events.read(&mut reader.0) {
+            if let GameEvent::MapChanged { .. } = event {
+                visibility.tiles.clear();
+                visibility.seen_from = None;
+            }
+        }
+        if !has_fog(&properties) {
+            return;
+        }
+
+        for (position, _player) in (&position, &player).join() {
+            let tile = (map::to_tl(position.x), map::to_tl(position.y));
+            if visibility.seen_from == Some(tile) {
+                continue;
+            }
+            visibility.tiles = fov::field_of_view(&tiles, tile, SIGHT_RADIUS);
+            visibility.seen_from = Some(tile);
+            explored.0.entry(map_path.0.clone()).or_default().extend(visibility.tiles.iter().copied());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::map;
+    use crate::simulation;
+    use crate::Direction;
+    use std::path::Path;
+
+    #[test]
+    fn fog_lifts_where_the_player_looks_and_explored_tiles_are_saved() {
+        let mut game = Headless::with_map_file(Path::new(map::BASIC_MAP_PATH));
+        game.step(1);
+        assert_eq!(tile_visibility(&game.ecs, (30, 30)), TileVisibility::Visible, "maps without fog show everything");
+        assert!(game.ecs.fetch::<Explored>().0.is_empty());
+
+        game.ecs.fetch_mut::<simulation::MapProperties>().0.insert("fog".to_string(), "true".to_string());
+        game.step(1);
+        let start = game.player_tile();
+        let far = (start.0, start.1 + SIGHT_RADIUS + 1);
+        assert_eq!(tile_visibility(&game.ecs, start), TileVisibility::Visible);
+        assert_eq!(tile_visibility(&game.ecs, far), TileVisibility::Hidden);
+
+        // Nothing is worked out again while the player stays on the same tile
+        game.ecs.fetch_mut::<Visibility>().tiles.clear();
+        game.step(1);
+        assert!(game.ecs.fetch::<Visibility>().tiles.is_empty());
+        for _ in 0..simulation::TICKS_PER_SECOND {
+            if game.player_tile() != start {
+                break;
+            }
+            game.press(Direction::Down);
+            game.step(1);
+        }
+        assert_ne!(game.player_tile(), start);
+        assert!(!game.ecs.fetch::<Visibility>().tiles.is_empty());
+        for _ in 0..simulation::TICKS_PER_SECOND * 2 {
+            game.press(Direction::Down);
+            game.step(1);
+        }
+        assert_eq!(tile_visibility(&game.ecs, far), TileVisibility::Visible);
+        game.ecs.fetch_mut::<Visibility>().tiles.clear();
+        assert_eq!(tile_visibility(&game.ecs, start), TileVisibility::Explored);
+
+        let explored = game.ecs.fetch::<Explored>().0.clone();
+        game.reload();
+        assert_eq!(game.ecs.fetch::<Explored>().0, explored);
+        assert!(explored[map::BASIC_MAP_PATH].contains(&far));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::input::Action;
+use crate::{Point2, WIDTH_PX, HEIGHT_PX};
+use ggez::{graphics, Context, GameResult};
+
+const FONT_SIZE: f32 = 24.0;
+const ROW_HEIGHT_PX: f32 = 36.0;
+const HIGHLIGHT: [f32; 4] = [1.0, 0.85, 0.4, 1.0];
+const FADED: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const DISABLED: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+
+#[derive(Clone, Debug)]
+pub struct MenuItem {
+    pub label: String,
+    /// Disabled items are drawn greyed out and are skipped over when moving the selection.
+    pub enabled: bool,
+}
+
+/// A vertical list of items with one of them selected, driven by actions from the keyboard
+/// or a gamepad as well as by the mouse. Items are picked by their index, it is up to whoever
+/// owns the menu to decide what each one does.
+#[derive(Clone, Debug)]
+pub struct Menu {
+    items: Vec<MenuItem>,
+    selected: usize,
+    /// Top left corner of the first item, on screen.
+    position: Point2,
+    width: f32,
+    row_height: f32,
+    font_size: f32,
+}
+
+impl Menu {
+    pub fn new(labels: &[&str], position: Point2, width: f32) -> Menu {
+        let items = labels.iter().map(|label| MenuItem { label: label.to_string(), enabled: true }).collect();
+        Menu { items, selected: 0, position, width, row_height: ROW_HEIGHT_PX, font_size: FONT_SIZE }
+    }
+
+    /// Packs the items closer together, for menus with a lot of them.
+    pub fn with_rows(mut self, row_height: f32, font_size: f32) -> Menu {
+        self.row_height = row_height;
+        self.font_size = font_size;
+        self
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Selects an item, or the next enabled one after it.
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+        if !self.is_enabled(self.selected) {
+            self.step(1);
+        }
+    }
+
+    pub fn set_label(&mut self, index: usize, label: String) {
+        self.items[index].label = label;
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.items[index].enabled = enabled;
+        if index == self.selected && !enabled {
+            self.step(1);
+        }
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.items.get(index).is_some_and(|item| item.enabled)
+    }
+
+    /// Moves the selection up or down, or picks the selected item.
+    /// Returns the index of the item that was picked.
+    pub fn input(&mut self, action: Action) -> Option<usize> {
+        match action {
+            Action::MoveUp => self.step(self.items.len().saturating_sub(1)),
+            Action::MoveDown => self.step(1),
+            Action::Interact if self.is_enabled(self.selected) => return Some(self.selected),
+            _ => (),
+        }
+        None
+    }
+
+    /// Selects whichever enabled item is under the mouse.
+    pub fn hover(&mut self, x: f32, y: f32) {
+        if let Some(index) = self.item_at(x, y) {
+            self.selected = index;
+        }
+    }
+
+    /// Picks whichever enabled item was clicked on.
+    pub fn click(&mut self, x: f32, y: f32) -> Option<usize> {
+        let index = self.item_at(x, y)?;
+        self.selected = index;
+        Some(index)
+    }
+
+    /// The enabled item covering a point on the screen.
+    pub fn item_at(&self, x: f32, y: f32) -> Option<usize> {
+        (0..self.items.len())
+            .find(|index| self.item_rect(*index).contains([x, y]))
+            .filter(|index| self.is_enabled(*index))
+    }
+
+    fn item_rect(&self, index: usize) -> graphics::Rect {
+        graphics::Rect::new(self.position.x, self.position.y + index as f32 * self.row_height, self.width, self.row_height)
+    }
+
+    /// How far down the screen the menu ends, for drawing anything underneath it.
+    pub fn bottom(&self) -> f32 {
+        self.position.y + self.items.len() as f32 * self.row_height
+    }
+
+    /// Moves the selection by some number of items, wrapping around and skipping disabled ones.
+    fn step(&mut self, by: usize) {
+        let count = self.items.len();
+        for offset in 1..=count {
+            let index = (self.selected + by * offset) % count;
+            if self.is_enabled(index) {
+                self.selected = index;
+                return;
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, font: graphics::Font) -> GameResult<()> {
+        for (index, item) in self.items.iter().enumerate() {
+            let (marker, color) = match (index == self.selected, item.enabled) {
+                (_, false) => ("   ", DISABLED),
+                (true, true) => ("> ", HIGHLIGHT),
+                (false, true) => ("   ", FADED),
+            };
+            let rect = self.item_rect(index);
+            let text = graphics::Text::new((format!("{}{}", marker, item.label), font, self.font_size));
+            graphics::draw(ctx, &text, (Point2::new(rect.x, rect.y), color.into()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Dims everything drawn so far and draws a bordered panel over the top of it,
+/// for screens which show up over the frozen game.
+pub fn draw_panel(ctx: &mut Context, panel: graphics::Rect) -> GameResult<()> {
+    let shade = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+        graphics::Rect::new(0.0, 0.0, WIDTH_PX as f32, HEIGHT_PX as f32), [0.0, 0.0, 0.0, 0.4].into())?;
+    graphics::draw(ctx, &shade, graphics::DrawParam::default())?;
+    let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), panel, [0.0, 0.0, 0.0, 0.85].into())?;
+    graphics::draw(ctx, &background, graphics::DrawParam::default())?;
+    let border = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), panel, graphics::WHITE)?;
+    graphics::draw(ctx, &border, graphics::DrawParam::default())
+}
+
+/// A panel of the given size in the middle of the screen.
+pub fn centered_panel(width: f32, height: f32) -> graphics::Rect {
+    graphics::Rect::new((WIDTH_PX as f32 - width) / 2.0, (HEIGHT_PX as f32 - height) / 2.0, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Action;
+    use crate::Point2;
+
+    #[test]
+    fn menus_skip_disabled_items_and_wrap_around() {
+        let mut menu = Menu::new(&["New Game", "Continue", "Quit"], Point2::new(100.0, 100.0), 200.0);
+        menu.set_enabled(1, false);
+        assert_eq!(menu.input(Action::MoveDown), None);
+        assert_eq!(menu.selected(), 2);
+        menu.input(Action::MoveDown);
+        assert_eq!(menu.selected(), 0);
+        menu.input(Action::MoveUp);
+        assert_eq!(menu.input(Action::Interact), Some(2));
+    }
+
+    #[test]
+    fn menus_follow_the_mouse() {
+        let mut menu = Menu::new(&["New Game", "Continue", "Quit"], Point2::new(100.0, 100.0), 200.0);
+        menu.set_enabled(1, false);
+        menu.hover(150.0, 100.0 + 36.0 * 2.5);
+        assert_eq!(menu.selected(), 2);
+        menu.hover(150.0, 100.0 + 36.0 * 1.5);
+        assert_eq!(menu.selected(), 2);
+        assert_eq!(menu.click(150.0, 110.0), Some(0));
+        assert_eq!(menu.click(350.0, 110.0), None);
+        assert_eq!(menu.click(150.0, 100.0 + 36.0 * 1.5), None);
+    }
+}